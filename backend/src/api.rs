@@ -1,26 +1,113 @@
 use axum::{
-    extract::{Path, Query, State, WebSocketUpgrade},
+    extract::{DefaultBodyLimit, Multipart, Path, Query, State, WebSocketUpgrade},
+    http::HeaderMap,
+    response::sse::{Event, KeepAlive, Sse},
     response::IntoResponse,
     routing::{get, post},
     Json, Router,
 };
-use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
-use futures::{SinkExt, StreamExt};
+use futures::future::join_all;
+use futures::{SinkExt, Stream, StreamExt};
 use serde::Deserialize;
-use tokio::sync::broadcast;
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc};
 use tower_http::services::{ServeDir, ServeFile};
 use uuid::Uuid;
 
-use crate::comfyui::ComfyUIClient;
+use crate::comfyui::hires_size;
+use crate::config::Config;
 use crate::error::{AppError, AppResult};
+use crate::events::{is_owner, EventHub, EventSender, Outbound, Subscriptions};
+use crate::history::{GenerationRecord, GenerationStatus, HistoryPage, HistoryQuery, HistoryStore};
+use crate::jobs::{Job, JobList, JobPriority, JobQueue, MoveTarget};
+use crate::metadata::{
+    embed_text_chunks, is_png, parse_parameters, read_text_chunks, GenerationParameters,
+    MetadataFormat, ParsedImage,
+};
 use crate::models::*;
+use crate::object_info::Capabilities;
+use crate::pool::{ComfyUIInstance, ComfyUIPool, InstanceStatus};
+use crate::preview::{PreviewSettings, PreviewThrottle};
+use crate::seed::{image_seeds, SeedMode, SeedTracker};
+use crate::sweep::{expand, SweepJob, SweepManager};
+use crate::templates::{TemplateStore, WorkflowTemplate};
+
+/// Maximum request body size for image uploads
+const MAX_UPLOAD_SIZE: usize = 64 * 1024 * 1024;
 
 /// Application state shared across handlers
 #[derive(Clone)]
 pub struct AppState {
-    pub comfyui: ComfyUIClient,
-    pub event_tx: broadcast::Sender<String>,
-    pub comfyui_client_id: String,
+    pub pool: ComfyUIPool,
+    pub templates: TemplateStore,
+    pub history: HistoryStore,
+    pub seeds: SeedTracker,
+    pub sweeps: SweepManager,
+    pub jobs: JobQueue,
+    pub events: EventHub,
+    pub event_tx: EventSender,
+}
+
+impl AppState {
+    /// Open the stores and connect the ComfyUI pool described by `config`
+    pub fn new(config: Arc<Config>) -> Self {
+        // One client per configured ComfyUI instance
+        let pool = ComfyUIPool::new(config.clone());
+
+        // Load workflow templates
+        let templates = TemplateStore::load(&config.templates_dir, &config.default_template);
+
+        // Open persistent generation history
+        let history = HistoryStore::open(&config.history_path);
+
+        // Create event broadcast channel, logged for clients resuming with Last-Event-ID
+        let event_tx = EventSender::new();
+
+        // Sweep jobs report progress on the same channel
+        let sweeps = SweepManager::new(
+            pool.clone(),
+            &config.sweeps_dir,
+            config.label_font.as_deref(),
+            event_tx.clone(),
+        );
+
+        let events = EventHub::new(
+            event_tx.clone(),
+            history.clone(),
+            sweeps.clone(),
+            PreviewSettings::new(&config),
+        );
+
+        // Backend job queue, spreading jobs over the instances as they free up
+        let jobs = JobQueue::new(pool.clone(), events.clone(), config.max_in_flight);
+
+        Self {
+            pool,
+            templates,
+            history,
+            seeds: SeedTracker::new(),
+            sweeps,
+            jobs,
+            events,
+            event_tx,
+        }
+    }
+
+    /// Start the job queue and a ComfyUI WebSocket listener per instance in background
+    pub fn spawn_workers(&self) {
+        tokio::spawn(self.jobs.clone().run());
+        for instance in self.pool.instances() {
+            tokio::spawn(start_comfyui_listener(
+                instance.clone(),
+                self.jobs.clone(),
+                self.events.clone(),
+            ));
+        }
+    }
 }
 
 /// Create the API router
@@ -45,17 +132,63 @@ pub fn create_router(state: AppState) -> Router {
             "/api/comfyui-url",
             get(get_comfyui_url_handler).post(set_comfyui_url_handler),
         )
+        .route("/api/instances", get(instances_handler))
         // Generation endpoints
         .route("/api/generate", post(generate_handler))
+        .route(
+            "/api/img2img",
+            post(img2img_handler).layer(DefaultBodyLimit::max(MAX_UPLOAD_SIZE)),
+        )
+        .route(
+            "/api/inpaint",
+            post(inpaint_handler).layer(DefaultBodyLimit::max(MAX_UPLOAD_SIZE)),
+        )
+        .route("/api/upscale", post(upscale_handler))
+        .route(
+            "/api/parse-image",
+            post(parse_image_handler).layer(DefaultBodyLimit::max(MAX_UPLOAD_SIZE)),
+        )
+        .route(
+            "/api/sweeps",
+            get(list_sweeps_handler).post(create_sweep_handler),
+        )
+        .route("/api/sweeps/:id", get(get_sweep_handler))
+        .route("/api/sweeps/:id/grid", get(sweep_grid_handler))
+        .route("/api/jobs", get(list_jobs_handler))
+        .route(
+            "/api/jobs/:id",
+            get(get_job_handler).delete(delete_job_handler),
+        )
+        .route("/api/jobs/:id/cancel", post(cancel_job_handler))
+        .route("/api/jobs/:id/move", post(move_job_handler))
+        .route("/api/jobs/:id/priority", post(job_priority_handler))
+        .route("/api/models", get(models_handler))
+        .route("/api/capabilities", get(capabilities_handler))
+        .route(
+            "/api/capabilities/refresh",
+            post(refresh_capabilities_handler),
+        )
         .route("/api/queue", get(queue_handler))
-        .route("/api/history/{prompt_id}", get(history_handler))
+        .route("/api/history/:prompt_id", get(history_handler))
+        // Local generation history
+        .route("/api/generations", get(list_generations_handler))
+        .route("/api/generations/search", get(search_generations_handler))
+        .route(
+            "/api/generations/:id",
+            get(get_generation_handler).delete(delete_generation_handler),
+        )
+        // Template endpoints
+        .route("/api/templates", get(templates_handler))
+        .route("/api/templates/reload", post(reload_templates_handler))
         // Image endpoints
-        .route("/api/images/{filename}", get(image_handler))
+        .route("/api/images/:filename", get(image_handler))
         // Control endpoints
         .route("/api/interrupt", post(interrupt_handler))
         .route("/api/clear", post(clear_handler))
-        // WebSocket endpoint
+        // Event streams
         .route("/ws", get(websocket_handler))
+        .route("/api/events", get(sse_handler))
+        .route("/api/previews/:prompt_id", get(preview_handler))
         .with_state(state)
         // Fallback to serve static files (frontend)
         .fallback_service(serve_dir)
@@ -65,13 +198,19 @@ pub fn create_router(state: AppState) -> Router {
 // Health and Status Handlers
 // ============================================================================
 
-async fn health_handler(State(state): State<AppState>) -> AppResult<Json<serde_json::Value>> {
-    let comfyui_ok = state.comfyui.health_check().await?;
+async fn health_handler(State(state): State<AppState>) -> Json<serde_json::Value> {
+    let instances = state.pool.statuses().await;
+    let healthy = instances.iter().filter(|i| i.healthy).count();
 
-    Ok(Json(serde_json::json!({
-        "status": if comfyui_ok { "ok" } else { "degraded" },
-        "comfyui": comfyui_ok
-    })))
+    Json(serde_json::json!({
+        "status": if healthy == instances.len() { "ok" } else { "degraded" },
+        "comfyui": healthy > 0,
+        "instances": instances
+    }))
+}
+
+async fn instances_handler(State(state): State<AppState>) -> Json<Vec<InstanceStatus>> {
+    Json(state.pool.statuses().await)
 }
 
 #[derive(Deserialize)]
@@ -94,29 +233,63 @@ async fn test_comfyui_handler(Json(request): Json<TestComfyUIRequest>) -> Json<s
     }
 }
 
-async fn get_comfyui_url_handler(State(state): State<AppState>) -> Json<serde_json::Value> {
-    let url = state.comfyui.get_url().await;
-    Json(serde_json::json!({ "url": url }))
+#[derive(Deserialize)]
+struct InstanceQuery {
+    instance: Option<String>,
+}
+
+/// The named instance, or the first one when no name is given
+fn instance_or_first<'a>(
+    state: &'a AppState,
+    name: Option<&str>,
+) -> AppResult<&'a ComfyUIInstance> {
+    match name {
+        Some(name) => state
+            .pool
+            .get(name)
+            .ok_or_else(|| AppError::NotFound(format!("ComfyUI instance '{}' not found", name))),
+        None => Ok(&state.pool.instances()[0]),
+    }
+}
+
+async fn get_comfyui_url_handler(
+    State(state): State<AppState>,
+    Query(query): Query<InstanceQuery>,
+) -> AppResult<Json<serde_json::Value>> {
+    let instance = instance_or_first(&state, query.instance.as_deref())?;
+    let url = instance.client.get_url().await;
+    Ok(Json(
+        serde_json::json!({ "url": url, "instance": instance.name }),
+    ))
 }
 
 #[derive(Deserialize)]
 struct SetComfyUIUrlRequest {
     url: String,
+    /// Instance to update, the first one when absent
+    instance: Option<String>,
 }
 
 async fn set_comfyui_url_handler(
     State(state): State<AppState>,
     Json(request): Json<SetComfyUIUrlRequest>,
-) -> Json<serde_json::Value> {
+) -> AppResult<Json<serde_json::Value>> {
+    let instance = instance_or_first(&state, request.instance.as_deref())?;
     let url = request.url.trim_end_matches('/');
-    state.comfyui.set_url(url).await;
-    tracing::info!("ComfyUI URL updated to: {}", url);
-    Json(serde_json::json!({ "success": true, "url": url }))
+    instance.client.set_url(url).await;
+    tracing::info!("ComfyUI URL of {} updated to: {}", instance.name, url);
+    Ok(Json(
+        serde_json::json!({ "success": true, "url": url, "instance": instance.name }),
+    ))
 }
 
 async fn status_handler(State(state): State<AppState>) -> AppResult<Json<serde_json::Value>> {
-    let system_stats = state.comfyui.get_system_stats().await?;
-    let queue = state.comfyui.get_queue().await?;
+    let healthy = state.pool.healthy().await;
+    let first = healthy.first().ok_or_else(|| {
+        AppError::ComfyUIConnection("No ComfyUI instance is available".to_string())
+    })?;
+    let system_stats = first.client.get_system_stats().await?;
+    let instances = state.pool.statuses().await;
 
     Ok(Json(serde_json::json!({
         "comfyui": {
@@ -125,9 +298,10 @@ async fn status_handler(State(state): State<AppState>) -> AppResult<Json<serde_j
             "devices": system_stats.devices
         },
         "queue": {
-            "running": queue.queue_running.len(),
-            "pending": queue.queue_pending.len()
-        }
+            "running": instances.iter().map(|i| i.running).sum::<usize>(),
+            "pending": instances.iter().map(|i| i.pending).sum::<usize>()
+        },
+        "instances": instances
     })))
 }
 
@@ -137,8 +311,9 @@ async fn status_handler(State(state): State<AppState>) -> AppResult<Json<serde_j
 
 async fn generate_handler(
     State(state): State<AppState>,
-    Json(request): Json<GenerateRequest>,
+    Json(mut request): Json<GenerateRequest>,
 ) -> AppResult<Json<QueueResponse>> {
+    request.apply_structured_prompt()?;
     tracing::info!(
         "Generate request: prompt='{}', size={}x{}, steps={}",
         request.prompt.chars().take(50).collect::<String>(),
@@ -147,7 +322,282 @@ async fn generate_handler(
         request.steps
     );
 
-    // Validate request
+    let (template, models) = prepare(&state, &request).await?;
+    let seed = state.seeds.resolve(&request).await?;
+    let built = state
+        .pool
+        .client()
+        .build_workflow(&request, &models, &template, seed)?;
+
+    let submission = Submission::generation("txt2img", &request, &built);
+    queue_workflow(&state, built.workflow, submission)
+        .await
+        .map(Json)
+}
+
+async fn img2img_handler(
+    State(state): State<AppState>,
+    multipart: Multipart,
+) -> AppResult<Json<QueueResponse>> {
+    let mut form = read_multipart(multipart).await?;
+    let request = form.generate_request()?;
+    let image = form.take_file("image")?;
+    let resize = form.flag("resize");
+
+    tracing::info!(
+        "Img2img request: prompt='{}', source={} ({} bytes), denoise={}",
+        request.prompt.chars().take(50).collect::<String>(),
+        image.filename,
+        image.data.len(),
+        request.denoise
+    );
+
+    validate_denoise(&request)?;
+    let (template, models) = prepare(&state, &request).await?;
+
+    let (uploaded, holders) = state
+        .pool
+        .upload_image(image.data, &upload_filename("img2img", &image.filename))
+        .await?;
+
+    let source = SourceImage {
+        image: uploaded.load_image_name(),
+        resize,
+        mask: None,
+    };
+    let seed = state.seeds.resolve(&request).await?;
+    let built = state
+        .pool
+        .client()
+        .build_img2img_workflow(&request, &models, &template, &source, seed)?;
+
+    // Only the instances that received the upload can run the job
+    let submission = Submission {
+        instances: holders,
+        ..Submission::generation("img2img", &request, &built)
+    };
+    queue_workflow(&state, built.workflow, submission)
+        .await
+        .map(Json)
+}
+
+async fn inpaint_handler(
+    State(state): State<AppState>,
+    multipart: Multipart,
+) -> AppResult<Json<QueueResponse>> {
+    let mut form = read_multipart(multipart).await?;
+    let request = form.generate_request()?;
+    let image = form.take_file("image")?;
+    let mask_file = form.files.remove("mask").filter(|f| !f.data.is_empty());
+
+    let mode = match form.fields.get("mode").map(|v| v.trim()) {
+        None | Some("") | Some("vae_encode_for_inpaint") => InpaintMode::VaeEncodeForInpaint,
+        Some("latent_noise_mask") => InpaintMode::LatentNoiseMask,
+        Some(other) => {
+            return Err(AppError::InvalidRequest(format!(
+                "Invalid inpaint mode '{}', expected vae_encode_for_inpaint or latent_noise_mask",
+                other
+            )))
+        }
+    };
+    let grow = match form.fields.get("grow_mask").map(|v| v.trim()) {
+        None | Some("") => 6,
+        Some(value) => value
+            .parse::<u32>()
+            .ok()
+            .filter(|v| *v <= 256)
+            .ok_or_else(|| {
+                AppError::InvalidRequest(
+                    "grow_mask must be an integer between 0 and 256".to_string(),
+                )
+            })?,
+    };
+    let channel = form
+        .fields
+        .get("mask_channel")
+        .map(|v| v.trim().to_lowercase())
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| "red".to_string());
+    if !matches!(channel.as_str(), "red" | "green" | "blue" | "alpha") {
+        return Err(AppError::InvalidRequest(format!(
+            "Invalid mask_channel '{}', expected red, green, blue or alpha",
+            channel
+        )));
+    }
+
+    tracing::info!(
+        "Inpaint request: prompt='{}', source={}, mask={}, mode={:?}, grow={}",
+        request.prompt.chars().take(50).collect::<String>(),
+        image.filename,
+        mask_file.as_ref().map_or("alpha", |f| f.filename.as_str()),
+        mode,
+        grow
+    );
+
+    validate_denoise(&request)?;
+    let (template, models) = prepare(&state, &request).await?;
+
+    let (uploaded, mut holders) = state
+        .pool
+        .upload_image(image.data, &upload_filename("inpaint", &image.filename))
+        .await?;
+
+    let mask_source = match mask_file {
+        Some(mask) => {
+            let (uploaded_mask, mask_holders) = state
+                .pool
+                .upload_image(mask.data, &upload_filename("inpaint_mask", &mask.filename))
+                .await?;
+            holders.retain(|name| mask_holders.contains(name));
+            if holders.is_empty() {
+                return Err(AppError::ComfyUIConnection(
+                    "No ComfyUI instance received both the image and the mask".to_string(),
+                ));
+            }
+            MaskSource::Image {
+                image: uploaded_mask.load_image_name(),
+                channel,
+            }
+        }
+        None => MaskSource::Alpha,
+    };
+
+    let source = SourceImage {
+        image: uploaded.load_image_name(),
+        resize: false,
+        mask: Some(InpaintMask {
+            source: mask_source,
+            mode,
+            grow,
+        }),
+    };
+    let seed = state.seeds.resolve(&request).await?;
+    let built = state
+        .pool
+        .client()
+        .build_img2img_workflow(&request, &models, &template, &source, seed)?;
+
+    let submission = Submission {
+        instances: holders,
+        ..Submission::generation("inpaint", &request, &built)
+    };
+    queue_workflow(&state, built.workflow, submission)
+        .await
+        .map(Json)
+}
+
+async fn upscale_handler(
+    State(state): State<AppState>,
+    Json(request): Json<UpscaleRequest>,
+) -> AppResult<Json<QueueResponse>> {
+    tracing::info!(
+        "Upscale request: image={}/{} [{}], model={}",
+        request.subfolder,
+        request.filename,
+        request.image_type,
+        request.upscale_model
+    );
+
+    if request.filename.is_empty()
+        || request.filename.contains(['/', '\\'])
+        || request.filename.contains("..")
+        || request.subfolder.contains("..")
+    {
+        return Err(AppError::InvalidRequest("Invalid image path".to_string()));
+    }
+    if !matches!(request.image_type.as_str(), "output" | "input" | "temp") {
+        return Err(AppError::InvalidRequest(format!(
+            "Invalid image type '{}', expected output, input or temp",
+            request.image_type
+        )));
+    }
+    if let Some(rescale) = request.rescale {
+        if !rescale.is_finite() || rescale <= 0.0 || rescale > 4.0 {
+            return Err(AppError::InvalidRequest(
+                "Rescale must be greater than 0 and at most 4".to_string(),
+            ));
+        }
+    }
+
+    let models = state.pool.get_available_models().await?;
+    if !models.upscale.is_empty() && !models.upscale.contains(&request.upscale_model) {
+        return Err(AppError::InvalidRequest(format!(
+            "Upscale model '{}' is not installed",
+            request.upscale_model
+        )));
+    }
+
+    // Outputs live on the instance that generated them
+    let instance = match &request.instance {
+        Some(name) => Some(instance_or_first(&state, Some(name))?.name.clone()),
+        None => state
+            .history
+            .find_by_image(&request.filename, &request.subfolder, None)
+            .await
+            .and_then(|record| record.instance),
+    };
+
+    let workflow = state.pool.client().build_upscale_workflow(&request);
+    let submission = Submission {
+        kind: "upscale",
+        request: serde_json::to_value(&request)?,
+        seed: None,
+        batch_size: 1,
+        models: None,
+        priority: request.priority,
+        instances: instance.into_iter().collect(),
+        owner: request.client_id.clone(),
+    };
+    queue_workflow(&state, workflow, submission).await.map(Json)
+}
+
+async fn parse_image_handler(
+    State(state): State<AppState>,
+    multipart: Multipart,
+) -> AppResult<Json<ParsedImage>> {
+    let mut form = read_multipart(multipart).await?;
+    let image = form.take_file("image")?;
+    let template = state
+        .templates
+        .get(form.fields.get("template").map(String::as_str))
+        .await?;
+
+    let chunks = read_text_chunks(&image.data)?;
+    let parsed = parse_parameters(&chunks, &template).ok_or_else(|| {
+        AppError::NotFound(format!(
+            "No generation parameters found in {}",
+            image.filename
+        ))
+    })?;
+
+    tracing::info!(
+        "Parsed parameters from {} (source: {})",
+        image.filename,
+        parsed.source
+    );
+
+    Ok(Json(parsed))
+}
+
+/// Validate a generation request, returning its template and the installed models
+///
+/// Checks the request itself, then its settings and models against what ComfyUI offers.
+async fn prepare(
+    state: &AppState,
+    request: &GenerateRequest,
+) -> AppResult<(Arc<WorkflowTemplate>, AvailableModels)> {
+    validate_generate_request(request)?;
+    let template = state.templates.get(request.template.as_deref()).await?;
+    let info = state.pool.get_object_info().await?;
+    info.validate(request)?;
+    let models = info.models();
+    validate_loras(request, &models)?;
+    validate_hires(request, &models)?;
+    Ok((template, models))
+}
+
+/// Basic sanity checks shared by all generation endpoints
+fn validate_generate_request(request: &GenerateRequest) -> AppResult<()> {
     if request.prompt.is_empty() {
         return Err(AppError::InvalidRequest(
             "Prompt cannot be empty".to_string(),
@@ -166,36 +616,222 @@ async fn generate_handler(
         ));
     }
 
-    // Get available models and build workflow
-    let models = state.comfyui.get_available_models().await?;
-    let workflow = state.comfyui.build_workflow(&request, &models);
+    Ok(())
+}
 
-    // Queue the prompt with the backend's ComfyUI client_id so we receive events
-    let response = state
-        .comfyui
-        .queue_prompt(workflow, Some(state.comfyui_client_id.clone()))
-        .await?;
+/// Denoise of the img2img endpoints, which start from an existing image
+fn validate_denoise(request: &GenerateRequest) -> AppResult<()> {
+    if request.denoise <= 0.0 || request.denoise > 1.0 {
+        return Err(AppError::InvalidRequest(
+            "Denoise must be greater than 0 and at most 1".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Check the hires pass settings against the installed upscale models
+fn validate_hires(request: &GenerateRequest, models: &AvailableModels) -> AppResult<()> {
+    let Some(hires) = &request.hires else {
+        return Ok(());
+    };
+
+    if !hires.scale.is_finite() || hires.scale <= 1.0 || hires.scale > 4.0 {
+        return Err(AppError::InvalidRequest(
+            "Hires scale must be greater than 1 and at most 4".to_string(),
+        ));
+    }
+    let (width, height) = hires_size(request, hires);
+    if width > 8192 || height > 8192 {
+        return Err(AppError::InvalidRequest(format!(
+            "Hires output {}x{} exceeds 8192 pixels per side",
+            width, height
+        )));
+    }
+    if hires.steps == 0 {
+        return Err(AppError::InvalidRequest(
+            "Hires steps must be at least 1".to_string(),
+        ));
+    }
+    if hires.denoise <= 0.0 || hires.denoise > 1.0 {
+        return Err(AppError::InvalidRequest(
+            "Hires denoise must be greater than 0 and at most 1".to_string(),
+        ));
+    }
+    if hires.method == HiresMethod::Model {
+        match &hires.upscale_model {
+            None => {
+                return Err(AppError::InvalidRequest(
+                    "Hires method 'model' requires upscale_model".to_string(),
+                ))
+            }
+            Some(name) if !models.upscale.is_empty() && !models.upscale.contains(name) => {
+                return Err(AppError::InvalidRequest(format!(
+                    "Upscale model '{}' is not installed",
+                    name
+                )))
+            }
+            Some(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Check requested LoRAs against the installed list
+fn validate_loras(request: &GenerateRequest, models: &AvailableModels) -> AppResult<()> {
+    for lora in &request.loras {
+        if !models.lora.is_empty() && !models.lora.contains(&lora.name) {
+            return Err(AppError::InvalidRequest(format!(
+                "LoRA '{}' is not installed",
+                lora.name
+            )));
+        }
+        if !lora.strength_model.is_finite() || lora.strength_model.abs() > 100.0 {
+            return Err(AppError::InvalidRequest(format!(
+                "LoRA '{}' strength must be between -100 and 100",
+                lora.name
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// What the history store keeps about a queued workflow
+struct Submission {
+    kind: &'static str,
+    request: serde_json::Value,
+    seed: Option<u64>,
+    batch_size: u32,
+    models: Option<ResolvedModels>,
+    priority: JobPriority,
+    /// Instances able to run the workflow, any when empty
+    instances: Vec<String>,
+    /// WebSocket client the job's events are routed to, only watchers when unset
+    owner: Option<String>,
+}
+
+impl Submission {
+    fn generation(kind: &'static str, request: &GenerateRequest, built: &BuiltWorkflow) -> Self {
+        Self {
+            kind,
+            request: serde_json::to_value(request).unwrap_or_default(),
+            seed: Some(built.seed),
+            batch_size: request.batch_size,
+            models: Some(built.models.clone()),
+            priority: request.priority,
+            instances: Vec::new(),
+            owner: request.client_id.clone(),
+        }
+    }
+}
+
+/// Add a built workflow to the backend job queue and record it in the history
+///
+/// The job id doubles as the ComfyUI prompt_id, so it is known before ComfyUI sees the job.
+async fn queue_workflow(
+    state: &AppState,
+    workflow: serde_json::Value,
+    submission: Submission,
+) -> AppResult<QueueResponse> {
+    let prompt_id = Uuid::new_v4().to_string();
+
+    state
+        .history
+        .insert(GenerationRecord::new(
+            prompt_id.clone(),
+            submission.kind,
+            submission.request,
+            submission.seed,
+            submission.models,
+        ))
+        .await;
+    if let Some(owner) = &submission.owner {
+        state.events.claim(&prompt_id, owner);
+    }
+
+    let position = state
+        .jobs
+        .enqueue(
+            &prompt_id,
+            submission.kind,
+            submission.priority,
+            workflow,
+            submission.instances,
+        )
+        .await;
 
     tracing::info!(
-        "Prompt queued: id={}, number={}",
-        response.prompt_id,
-        response.number
+        "Job queued: id={}, kind={}, priority={:?}, position={}",
+        prompt_id,
+        submission.kind,
+        submission.priority,
+        position
     );
 
-    Ok(Json(QueueResponse {
-        prompt_id: response.prompt_id,
-        number: response.number,
-    }))
+    Ok(QueueResponse {
+        prompt_id,
+        number: position as u32,
+        seed: submission.seed,
+        image_seeds: submission
+            .seed
+            .map(|seed| image_seeds(seed, submission.batch_size))
+            .unwrap_or_default(),
+    })
+}
+
+async fn models_handler(State(state): State<AppState>) -> AppResult<Json<AvailableModels>> {
+    let models = state.pool.get_available_models().await?;
+    Ok(Json(models))
+}
+
+async fn capabilities_handler(State(state): State<AppState>) -> AppResult<Json<Capabilities>> {
+    let info = state.pool.get_object_info().await?;
+    Ok(Json(info.capabilities()))
+}
+
+async fn refresh_capabilities_handler(
+    State(state): State<AppState>,
+) -> AppResult<Json<Capabilities>> {
+    let info = state.pool.refresh_object_info().await?;
+    tracing::info!(
+        "Refreshed object_info: {} node classes",
+        info.node_classes().len()
+    );
+    Ok(Json(info.capabilities()))
 }
 
 async fn queue_handler(State(state): State<AppState>) -> AppResult<Json<serde_json::Value>> {
-    let queue = state.comfyui.get_queue().await?;
+    let healthy = state.pool.healthy().await;
+    if healthy.is_empty() {
+        return Err(AppError::ComfyUIConnection(
+            "No ComfyUI instance is available".to_string(),
+        ));
+    }
+    let queues = join_all(healthy.iter().map(|i| i.client.get_queue())).await;
+
+    let mut running = Vec::new();
+    let mut pending = Vec::new();
+    let mut instances = serde_json::Map::new();
+    for (instance, queue) in healthy.iter().zip(queues) {
+        let Ok(queue) = queue else { continue };
+        instances.insert(
+            instance.name.clone(),
+            serde_json::json!({
+                "running": queue.queue_running.len(),
+                "pending": queue.queue_pending.len()
+            }),
+        );
+        running.extend(queue.queue_running);
+        pending.extend(queue.queue_pending);
+    }
 
     Ok(Json(serde_json::json!({
-        "running": queue.queue_running.len(),
-        "pending": queue.queue_pending.len(),
-        "running_prompts": queue.queue_running,
-        "pending_prompts": queue.queue_pending
+        "running": running.len(),
+        "pending": pending.len(),
+        "backend_pending": state.jobs.pending_count().await,
+        "running_prompts": running,
+        "pending_prompts": pending,
+        "instances": instances
     })))
 }
 
@@ -203,27 +839,13 @@ async fn history_handler(
     State(state): State<AppState>,
     Path(prompt_id): Path<String>,
 ) -> AppResult<Json<serde_json::Value>> {
-    let history = state.comfyui.get_history(&prompt_id).await?;
+    let record = state.history.get(&prompt_id).await;
+    let instance = record.as_ref().and_then(|r| r.instance.as_deref());
+    let history = state.pool.get_history(&prompt_id, instance).await?;
 
     match history {
         Some(h) => {
-            // Collect all images from outputs (metadata only)
-            let images: Vec<ImageResult> = h
-                .outputs
-                .values()
-                .flat_map(|output| {
-                    output.images.iter().filter_map(|img| {
-                        if img.image_type != "output" {
-                            return None;
-                        }
-                        Some(ImageResult {
-                            filename: img.filename.clone(),
-                            subfolder: img.subfolder.clone(),
-                            image_type: img.image_type.clone(),
-                        })
-                    })
-                })
-                .collect();
+            let images = h.output_images();
 
             Ok(Json(serde_json::json!({
                 "prompt_id": prompt_id,
@@ -232,11 +854,348 @@ async fn history_handler(
                 "images": images
             })))
         }
-        None => Err(AppError::NotFound(format!(
-            "Prompt {} not found",
-            prompt_id
-        ))),
+        // ComfyUI forgets its history on restart, fall back to our own record
+        None => match record {
+            Some(record) => Ok(Json(serde_json::json!({
+                "prompt_id": prompt_id,
+                "status": record.status,
+                "completed": record.status == GenerationStatus::Completed,
+                "images": record.images
+            }))),
+            None => Err(AppError::NotFound(format!(
+                "Prompt {} not found",
+                prompt_id
+            ))),
+        },
+    }
+}
+
+// ============================================================================
+// Sweep Handlers
+// ============================================================================
+
+async fn create_sweep_handler(
+    State(state): State<AppState>,
+    Json(request): Json<SweepRequest>,
+) -> AppResult<Json<SweepJob>> {
+    let mut base = request.base.clone();
+    base.apply_structured_prompt()?;
+
+    // Validate every combination before resolving seeds, so a rejected sweep queues nothing
+    // and advances no seed sequence
+    let mut prepared = Vec::new();
+    for (_, cell) in expand(&request, &base)? {
+        prepared.push(prepare(&state, &cell).await?);
+    }
+
+    // Every cell shares one seed unless an axis varies it
+    base.seed = state.seeds.resolve(&request.base).await? as i64;
+    base.seed_mode = Some(SeedMode::Fixed);
+    let cells = expand(&request, &base)?;
+
+    let mut built = Vec::with_capacity(cells.len());
+    for ((_, cell), (template, models)) in cells.iter().zip(prepared) {
+        let seed = state.seeds.resolve(cell).await?;
+        built.push(
+            state
+                .pool
+                .client()
+                .build_workflow(cell, &models, &template, seed)?,
+        );
+    }
+
+    let job = SweepJob::new(
+        &request,
+        cells.iter().map(|(index, _)| index.clone()).collect(),
+    );
+    let job_id = job.id.clone();
+    tracing::info!(
+        "Sweep {}: queueing {} combination(s) over {}",
+        job_id,
+        job.total,
+        job.axes
+            .iter()
+            .map(|a| a.param.as_str())
+            .collect::<Vec<_>>()
+            .join(" x ")
+    );
+    state.sweeps.create(job).await;
+
+    for (cell, ((_, request), built)) in cells.iter().zip(built).enumerate() {
+        let submission = Submission::generation("sweep", request, &built);
+        match queue_workflow(&state, built.workflow, submission).await {
+            Ok(response) => {
+                state
+                    .sweeps
+                    .attach(&job_id, cell, &response.prompt_id, built.seed)
+                    .await;
+            }
+            Err(e) => {
+                tracing::warn!("Sweep {} cell {} not queued: {}", job_id, cell, e);
+                state.sweeps.fail(&job_id, cell, e.to_string()).await;
+            }
+        }
+    }
+
+    state
+        .sweeps
+        .get(&job_id)
+        .await
+        .map(Json)
+        .ok_or_else(|| AppError::Internal(format!("Sweep {} vanished", job_id)))
+}
+
+async fn list_sweeps_handler(State(state): State<AppState>) -> Json<Vec<SweepJob>> {
+    Json(state.sweeps.list().await)
+}
+
+async fn get_sweep_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> AppResult<Json<SweepJob>> {
+    state.sweeps.refresh(&id).await;
+    state
+        .sweeps
+        .get(&id)
+        .await
+        .map(Json)
+        .ok_or_else(|| AppError::NotFound(format!("Sweep {} not found", id)))
+}
+
+async fn sweep_grid_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    if Uuid::parse_str(&id).is_err() {
+        return Err(AppError::InvalidRequest(format!(
+            "Invalid sweep id '{}'",
+            id
+        )));
     }
+    let data = tokio::fs::read(state.sweeps.grid_path(&id))
+        .await
+        .map_err(|_| AppError::NotFound(format!("Contact sheet for sweep {} not found", id)))?;
+
+    Ok(([(axum::http::header::CONTENT_TYPE, "image/png")], data))
+}
+
+// ============================================================================
+// Job Handlers
+// ============================================================================
+
+async fn list_jobs_handler(State(state): State<AppState>) -> Json<JobList> {
+    Json(state.jobs.list().await)
+}
+
+async fn get_job_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> AppResult<Json<Job>> {
+    state
+        .jobs
+        .get(&id)
+        .await
+        .map(Json)
+        .ok_or_else(|| AppError::NotFound(format!("Job {} not found", id)))
+}
+
+async fn cancel_job_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> AppResult<Json<Job>> {
+    state.jobs.cancel(&id).await.map(Json)
+}
+
+async fn delete_job_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> AppResult<Json<serde_json::Value>> {
+    state.jobs.remove(&id).await?;
+    Ok(Json(serde_json::json!({ "success": true })))
+}
+
+#[derive(Debug, Deserialize)]
+struct MoveJobRequest {
+    to: MoveTarget,
+}
+
+async fn move_job_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(request): Json<MoveJobRequest>,
+) -> AppResult<Json<Job>> {
+    state.jobs.move_job(&id, request.to).await.map(Json)
+}
+
+#[derive(Debug, Deserialize)]
+struct JobPriorityRequest {
+    priority: JobPriority,
+}
+
+async fn job_priority_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(request): Json<JobPriorityRequest>,
+) -> AppResult<Json<Job>> {
+    state
+        .jobs
+        .set_priority(&id, request.priority)
+        .await
+        .map(Json)
+}
+
+// ============================================================================
+// Multipart Uploads
+// ============================================================================
+
+/// A file part of a multipart request
+struct UploadedFile {
+    filename: String,
+    data: Vec<u8>,
+}
+
+/// Parsed multipart form: file parts and text fields by name
+#[derive(Default)]
+struct MultipartForm {
+    files: HashMap<String, UploadedFile>,
+    fields: HashMap<String, String>,
+}
+
+impl MultipartForm {
+    /// Parse the `request` field as a `GenerateRequest`
+    fn generate_request(&self) -> AppResult<GenerateRequest> {
+        let json = self.fields.get("request").ok_or_else(|| {
+            AppError::InvalidRequest(
+                "Missing 'request' field with generation parameters".to_string(),
+            )
+        })?;
+        let mut request: GenerateRequest = serde_json::from_str(json)
+            .map_err(|e| AppError::InvalidRequest(format!("Invalid 'request' field: {}", e)))?;
+        request.apply_structured_prompt()?;
+        Ok(request)
+    }
+
+    /// Take a required file part
+    fn take_file(&mut self, name: &str) -> AppResult<UploadedFile> {
+        match self.files.remove(name) {
+            Some(file) if !file.data.is_empty() => Ok(file),
+            Some(_) => Err(AppError::InvalidRequest(format!(
+                "File '{}' is empty",
+                name
+            ))),
+            None => Err(AppError::InvalidRequest(format!("Missing file '{}'", name))),
+        }
+    }
+
+    /// Whether a boolean text field is set to true
+    fn flag(&self, name: &str) -> bool {
+        self.fields
+            .get(name)
+            .is_some_and(|v| matches!(v.trim(), "true" | "1" | "on"))
+    }
+}
+
+/// Read every part of a multipart request into memory
+async fn read_multipart(mut multipart: Multipart) -> AppResult<MultipartForm> {
+    let mut form = MultipartForm::default();
+
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| AppError::InvalidRequest(format!("Invalid multipart body: {}", e)))?
+    {
+        let Some(name) = field.name().map(String::from) else {
+            continue;
+        };
+
+        if let Some(filename) = field.file_name().map(String::from) {
+            let data = field.bytes().await.map_err(|e| {
+                AppError::InvalidRequest(format!("Failed to read '{}': {}", name, e))
+            })?;
+            form.files.insert(
+                name,
+                UploadedFile {
+                    filename,
+                    data: data.to_vec(),
+                },
+            );
+        } else {
+            let text = field.text().await.map_err(|e| {
+                AppError::InvalidRequest(format!("Failed to read '{}': {}", name, e))
+            })?;
+            form.fields.insert(name, text);
+        }
+    }
+
+    Ok(form)
+}
+
+/// Unique ComfyUI input filename keeping the original extension
+fn upload_filename(prefix: &str, original: &str) -> String {
+    let extension = std::path::Path::new(original)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("png")
+        .to_lowercase();
+    format!("{}_{}.{}", prefix, Uuid::new_v4().simple(), extension)
+}
+
+// ============================================================================
+// Generation History Handlers
+// ============================================================================
+
+async fn list_generations_handler(
+    State(state): State<AppState>,
+    Query(query): Query<HistoryQuery>,
+) -> Json<HistoryPage> {
+    Json(state.history.list(&query).await)
+}
+
+async fn search_generations_handler(
+    State(state): State<AppState>,
+    Query(query): Query<HistoryQuery>,
+) -> AppResult<Json<HistoryPage>> {
+    if query.q.as_deref().is_none_or(|q| q.trim().is_empty()) {
+        return Err(AppError::InvalidRequest(
+            "Search query 'q' cannot be empty".to_string(),
+        ));
+    }
+    Ok(Json(state.history.list(&query).await))
+}
+
+async fn get_generation_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> AppResult<Json<GenerationRecord>> {
+    state
+        .history
+        .get(&id)
+        .await
+        .map(Json)
+        .ok_or_else(|| AppError::NotFound(format!("Generation {} not found", id)))
+}
+
+async fn delete_generation_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> AppResult<Json<serde_json::Value>> {
+    state.history.delete(&id).await?;
+    tracing::info!("Generation {} deleted from history", id);
+    Ok(Json(serde_json::json!({ "status": "deleted", "id": id })))
+}
+
+// ============================================================================
+// Template Handlers
+// ============================================================================
+
+async fn templates_handler(State(state): State<AppState>) -> Json<serde_json::Value> {
+    let templates = state.templates.list().await;
+    Json(serde_json::json!({ "templates": templates }))
+}
+
+async fn reload_templates_handler(State(state): State<AppState>) -> Json<serde_json::Value> {
+    let count = state.templates.reload().await;
+    Json(serde_json::json!({ "success": true, "count": count }))
 }
 
 // ============================================================================
@@ -248,6 +1207,10 @@ pub struct ImageQuery {
     subfolder: Option<String>,
     #[serde(rename = "type")]
     image_type: Option<String>,
+    /// Override the configured PNG metadata format (none, newbie, a1111, both)
+    metadata: Option<String>,
+    /// ComfyUI instance holding the image, looked up in the history when absent
+    instance: Option<String>,
 }
 
 async fn image_handler(
@@ -258,11 +1221,41 @@ async fn image_handler(
     let subfolder = query.subfolder.unwrap_or_default();
     let image_type = query.image_type.unwrap_or_else(|| "output".to_string());
 
-    let image_data = state
-        .comfyui
-        .get_image(&filename, &subfolder, &image_type)
+    let format = match query.metadata.as_deref() {
+        Some(value) => MetadataFormat::parse(value).ok_or_else(|| {
+            AppError::InvalidRequest(format!(
+                "Invalid metadata format '{}', expected none, newbie, a1111 or both",
+                value
+            ))
+        })?,
+        None => state.pool.client().png_metadata(),
+    };
+
+    // Instances number their outputs independently, so a name may exist on several
+    let record = state
+        .history
+        .find_by_image(&filename, &subfolder, query.instance.as_deref())
+        .await;
+    let instance = query
+        .instance
+        .as_deref()
+        .or(record.as_ref().and_then(|r| r.instance.as_deref()));
+
+    let mut image_data = state
+        .pool
+        .get_image(instance, &filename, &subfolder, &image_type)
         .await?;
 
+    // Embed the recipe of images generated through this backend
+    if format != MetadataFormat::None && is_png(&image_data) {
+        if let Some(params) = record.as_ref().and_then(GenerationParameters::from_record) {
+            match embed_text_chunks(&image_data, &params.chunks_for(format)) {
+                Ok(data) => image_data = data,
+                Err(e) => tracing::warn!("Failed to embed metadata into {}: {}", filename, e),
+            }
+        }
+    }
+
     // Determine content type based on filename
     let content_type = if filename.ends_with(".png") {
         "image/png"
@@ -285,20 +1278,29 @@ async fn image_handler(
 // ============================================================================
 
 async fn interrupt_handler(State(state): State<AppState>) -> AppResult<Json<serde_json::Value>> {
-    state.comfyui.interrupt().await?;
-    tracing::info!("Execution interrupted");
+    let healthy = state.pool.healthy().await;
+    for result in join_all(healthy.iter().map(|i| i.client.interrupt())).await {
+        result?;
+    }
+    tracing::info!("Execution interrupted on {} instance(s)", healthy.len());
 
     Ok(Json(serde_json::json!({
-        "status": "interrupted"
+        "status": "interrupted",
+        "instances": healthy.len()
     })))
 }
 
 async fn clear_handler(State(state): State<AppState>) -> AppResult<Json<serde_json::Value>> {
-    state.comfyui.clear_queue().await?;
-    tracing::info!("Queue cleared");
+    let cancelled = state.jobs.cancel_queued().await;
+    let healthy = state.pool.healthy().await;
+    for result in join_all(healthy.iter().map(|i| i.client.clear_queue())).await {
+        result?;
+    }
+    tracing::info!("Queue cleared ({} job(s) cancelled)", cancelled);
 
     Ok(Json(serde_json::json!({
-        "status": "cleared"
+        "status": "cleared",
+        "cancelled": cancelled
     })))
 }
 
@@ -306,39 +1308,154 @@ async fn clear_handler(State(state): State<AppState>) -> AppResult<Json<serde_js
 // WebSocket Handler
 // ============================================================================
 
+/// Longest client_id a WebSocket client may choose
+const MAX_CLIENT_ID_LEN: usize = 128;
+
+#[derive(Deserialize)]
+struct WsQuery {
+    /// client_id of an earlier connection, to keep receiving the events of its prompts
+    #[serde(rename = "clientId")]
+    client_id: Option<String>,
+    /// `all` to receive the events of every client's prompts
+    watch: Option<String>,
+    /// Previews per second wanted, below the server's cap; 0 for none
+    preview_fps: Option<f32>,
+}
+
+/// A frontend connected to /ws or /api/events
+struct WsClient {
+    id: String,
+    watch_all: bool,
+    /// Shortest gap between two previews, `None` when previews are off
+    preview_interval: Option<Duration>,
+}
+
 async fn websocket_handler(
     State(state): State<AppState>,
+    Query(query): Query<WsQuery>,
     ws: WebSocketUpgrade,
 ) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_websocket(socket, state))
+    let client_id = query
+        .client_id
+        .filter(|id| !id.is_empty() && id.len() <= MAX_CLIENT_ID_LEN)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let client = WsClient {
+        id: client_id,
+        watch_all: query.watch.as_deref() == Some("all"),
+        preview_interval: state.events.preview_settings().interval(query.preview_fps),
+    };
+    ws.on_upgrade(move |socket| handle_websocket(socket, state, client))
 }
 
-async fn handle_websocket(socket: axum::extract::ws::WebSocket, state: AppState) {
-    let client_id = Uuid::new_v4().to_string();
-    tracing::info!("WebSocket connected: {}", client_id);
+async fn handle_websocket(socket: axum::extract::ws::WebSocket, state: AppState, client: WsClient) {
+    let WsClient {
+        id: client_id,
+        watch_all,
+        preview_interval,
+    } = client;
+    tracing::info!(
+        "WebSocket connected: {}{}",
+        client_id,
+        if watch_all { " (watching all)" } else { "" }
+    );
 
     let (mut sender, mut receiver) = socket.split();
 
-    // Send connected message
+    // Subscribe before taking the snapshot so no event falls between the two
+    let mut event_rx = state.event_tx.subscribe();
+    let mut preview_rx = preview_interval.map(|_| state.events.subscribe_previews());
+    let preview_interval = preview_interval.unwrap_or_default();
+
+    // Replies to client messages share the socket with the events
+    let (reply_tx, mut reply_rx) = mpsc::channel::<String>(16);
+    let subscriptions = Arc::new(Mutex::new(Subscriptions::default()));
+
+    // Send connected message, then the state a reloaded page picks up from
     let connected_msg = FrontendMessage::Connected {
         client_id: client_id.clone(),
     };
-    if let Ok(msg) = serde_json::to_string(&connected_msg) {
-        let _ = sender.send(axum::extract::ws::Message::Text(msg)).await;
+    let snapshot = ws_snapshot(
+        &state,
+        &client_id,
+        watch_all,
+        &subscriptions,
+        SnapshotReason::Connect,
+    )
+    .await;
+    for message in [connected_msg, snapshot] {
+        if let Ok(msg) = serde_json::to_string(&message) {
+            let _ = sender.send(axum::extract::ws::Message::Text(msg)).await;
+        }
     }
 
-    // Subscribe to events
-    let mut event_rx = state.event_tx.subscribe();
-
-    // Spawn task to forward events to client
+    // Spawn task to forward the client's events and replies
+    let forward_state = state.clone();
+    let forward_client_id = client_id.clone();
+    let forward_subscriptions = subscriptions.clone();
     let forward_task = tokio::spawn(async move {
-        while let Ok(event) = event_rx.recv().await {
-            if sender
-                .send(axum::extract::ws::Message::Text(event))
-                .await
-                .is_err()
-            {
-                break;
+        let mut throttle = PreviewThrottle::new(preview_interval);
+        'socket: loop {
+            let events: Vec<String> = tokio::select! {
+                Some(reply) = reply_rx.recv() => vec![reply],
+                event = event_rx.recv() => match event {
+                    Ok(event) => {
+                        let wanted = forward_subscriptions
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .wants(&event, &forward_client_id, watch_all);
+                        if wanted {
+                            let held = throttle.flush(event.prompt_id.as_deref());
+                            held.map(|preview| preview.json)
+                                .into_iter()
+                                .chain([event.json])
+                                .collect()
+                        } else {
+                            Vec::new()
+                        }
+                    }
+                    Err(RecvError::Lagged(missed)) => {
+                        tracing::warn!(
+                            "WebSocket client {} is too slow, {} events dropped, resyncing",
+                            forward_client_id,
+                            missed
+                        );
+                        // Skip the backlog, the snapshot supersedes it
+                        event_rx = event_rx.resubscribe();
+                        let snapshot = ws_snapshot(
+                            &forward_state,
+                            &forward_client_id,
+                            watch_all,
+                            &forward_subscriptions,
+                            SnapshotReason::Resync,
+                        )
+                        .await;
+                        serde_json::to_string(&snapshot).ok().into_iter().collect()
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                Some(preview) = next_preview_event(&mut preview_rx) => {
+                    // Previews over the client's rate are held back, later ones supersede them
+                    let wanted = forward_subscriptions
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .wants(&preview, &forward_client_id, watch_all);
+                    wanted
+                        .then(|| throttle.offer(preview))
+                        .flatten()
+                        .map(|preview| preview.json)
+                        .into_iter()
+                        .collect()
+                }
+                preview = throttle.due() => vec![preview.json],
+            };
+            for json in events {
+                if sender
+                    .send(axum::extract::ws::Message::Text(json))
+                    .await
+                    .is_err()
+                {
+                    break 'socket;
+                }
             }
         }
     });
@@ -348,7 +1465,13 @@ async fn handle_websocket(socket: axum::extract::ws::WebSocket, state: AppState)
         match msg {
             Ok(axum::extract::ws::Message::Text(text)) => {
                 tracing::debug!("Received from client {}: {}", client_id, text);
-                // Handle client messages if needed
+                let reply = handle_client_message(&state, &client_id, &subscriptions, &text).await;
+                let Ok(json) = serde_json::to_string(&reply) else {
+                    continue;
+                };
+                if reply_tx.send(json).await.is_err() {
+                    break;
+                }
             }
             Ok(axum::extract::ws::Message::Close(_)) => {
                 tracing::info!("WebSocket closed: {}", client_id);
@@ -366,77 +1489,474 @@ async fn handle_websocket(socket: axum::extract::ws::WebSocket, state: AppState)
     tracing::info!("WebSocket disconnected: {}", client_id);
 }
 
+/// Completed generations listed in a snapshot
+const SNAPSHOT_RECENT: usize = 20;
+
+/// The queue, the running jobs and the recent completions of the prompts `visible` accepts
+async fn state_snapshot(
+    state: &AppState,
+    reason: SnapshotReason,
+    visible: impl Fn(&str) -> bool,
+) -> FrontendMessage {
+    let jobs = state.jobs.list().await;
+    let completed = state
+        .history
+        .list(&HistoryQuery {
+            status: Some(GenerationStatus::Completed),
+            per_page: Some(200),
+            ..Default::default()
+        })
+        .await;
+
+    FrontendMessage::Snapshot {
+        reason,
+        pending: jobs
+            .pending
+            .into_iter()
+            .filter(|j| visible(&j.id))
+            .collect(),
+        active: jobs.active.into_iter().filter(|j| visible(&j.id)).collect(),
+        recent: completed
+            .items
+            .into_iter()
+            .filter(|r| visible(&r.id))
+            .take(SNAPSHOT_RECENT)
+            .collect(),
+    }
+}
+
+/// Snapshot for a WebSocket connection, following its routing and subscriptions
+async fn ws_snapshot(
+    state: &AppState,
+    client_id: &str,
+    watch_all: bool,
+    subscriptions: &Mutex<Subscriptions>,
+    reason: SnapshotReason,
+) -> FrontendMessage {
+    state_snapshot(state, reason, |prompt_id| {
+        let owner = state.events.owner(prompt_id);
+        subscriptions
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .wants_prompt(prompt_id, owner.as_deref(), client_id, watch_all)
+    })
+    .await
+}
+
+/// Next preview for a client, skipping those it fell behind on; never resolves without
+/// a receiver
+async fn next_preview_event(rx: &mut Option<broadcast::Receiver<Outbound>>) -> Option<Outbound> {
+    let Some(rx) = rx else {
+        return std::future::pending().await;
+    };
+    loop {
+        match rx.recv().await {
+            Ok(preview) => return Some(preview),
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
+
+/// Carry out a message received on /ws, returning the reply
+async fn handle_client_message(
+    state: &AppState,
+    client_id: &str,
+    subscriptions: &Mutex<Subscriptions>,
+    text: &str,
+) -> FrontendMessage {
+    // The request_id is read first so malformed messages can still be answered
+    let value = match serde_json::from_str::<serde_json::Value>(text) {
+        Ok(value) => value,
+        Err(e) => {
+            return request_error(
+                None,
+                AppError::InvalidRequest(format!("Invalid JSON: {}", e)),
+            )
+        }
+    };
+    let request_id = value
+        .get("request_id")
+        .and_then(|id| id.as_str())
+        .map(str::to_string);
+    let message = match serde_json::from_value::<ClientMessage>(value) {
+        Ok(message) => message,
+        Err(e) => {
+            return request_error(
+                request_id,
+                AppError::InvalidRequest(format!("Invalid message: {}", e)),
+            )
+        }
+    };
+
+    let result = match message {
+        ClientMessage::Ping => return FrontendMessage::Pong { request_id },
+        ClientMessage::Generate { mut request } => {
+            // Events of the generation go to this connection unless it names another client
+            request
+                .client_id
+                .get_or_insert_with(|| client_id.to_string());
+            match generate_handler(State(state.clone()), Json(*request)).await {
+                Ok(Json(response)) => serde_json::to_value(response).map_err(AppError::from),
+                Err(e) => Err(e),
+            }
+        }
+        ClientMessage::Cancel { prompt_id } => match state.jobs.cancel(&prompt_id).await {
+            Ok(job) => serde_json::to_value(job).map_err(AppError::from),
+            Err(e) => Err(e),
+        },
+        ClientMessage::Subscribe { prompt_id } => {
+            if state.history.get(&prompt_id).await.is_none() {
+                Err(AppError::NotFound(format!(
+                    "Generation {} not found",
+                    prompt_id
+                )))
+            } else {
+                subscriptions
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .subscribe(&prompt_id)
+                    .map(|()| serde_json::json!({ "prompt_id": prompt_id }))
+            }
+        }
+        ClientMessage::Unsubscribe { prompt_id } => subscriptions
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .unsubscribe(&prompt_id)
+            .map(|()| serde_json::json!({ "prompt_id": prompt_id })),
+    };
+
+    match result {
+        Ok(result) => FrontendMessage::Ack { request_id, result },
+        Err(e) => request_error(request_id, e),
+    }
+}
+
+fn request_error(request_id: Option<String>, error: AppError) -> FrontendMessage {
+    let (status, message) = error.status();
+    tracing::warn!("WebSocket request failed: {} - {}", status, message);
+    FrontendMessage::RequestError {
+        request_id,
+        status: status.as_u16(),
+        message,
+        fields: error.fields().to_vec(),
+    }
+}
+
 // ============================================================================
-// ComfyUI WebSocket Listener
+// Server-Sent Events Handler
 // ============================================================================
 
-/// Start listening to ComfyUI WebSocket for events
-pub async fn start_comfyui_listener(
-    comfyui: ComfyUIClient,
-    event_tx: broadcast::Sender<String>,
+#[derive(Deserialize)]
+struct SseQuery {
+    /// client_id whose prompts' events are sent, as for /ws
+    #[serde(rename = "clientId")]
+    client_id: Option<String>,
+    /// `all` to receive the events of every client's prompts
+    watch: Option<String>,
+    /// Comma-separated prompt_ids; when given only their events are sent
+    prompt_id: Option<String>,
+    /// Previews per second wanted, below the server's cap; 0 for none
+    preview_fps: Option<f32>,
+    /// Event id to resume after, for clients that cannot send `Last-Event-ID`
+    last_event_id: Option<String>,
+}
+
+/// Which events an event stream receives
+struct SseFilter {
     client_id: String,
+    watch_all: bool,
+    prompts: Option<HashSet<String>>,
+}
+
+impl SseFilter {
+    fn wants(&self, event: &Outbound) -> bool {
+        match &self.prompts {
+            Some(prompts) => event
+                .prompt_id
+                .as_deref()
+                .is_some_and(|id| prompts.contains(id)),
+            None => event.is_for(&self.client_id, self.watch_all),
+        }
+    }
+
+    fn wants_prompt(&self, prompt_id: &str, owner: Option<&str>) -> bool {
+        match &self.prompts {
+            Some(prompts) => prompts.contains(prompt_id),
+            None => is_owner(owner, &self.client_id, self.watch_all),
+        }
+    }
+}
+
+/// The events of /ws as a Server-Sent Events stream, for networks where WebSockets fail
+///
+/// Events carry `<epoch>-<n>` ids; a client reconnecting with `Last-Event-ID` first gets
+/// the events it missed, or a `resync` snapshot when they are no longer in the log.
+async fn sse_handler(
+    State(state): State<AppState>,
+    Query(query): Query<SseQuery>,
+    headers: HeaderMap,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let filter = SseFilter {
+        client_id: query
+            .client_id
+            .filter(|id| !id.is_empty() && id.len() <= MAX_CLIENT_ID_LEN)
+            .unwrap_or_else(|| Uuid::new_v4().to_string()),
+        watch_all: query.watch.as_deref() == Some("all"),
+        prompts: query
+            .prompt_id
+            .map(|ids| {
+                ids.split(',')
+                    .map(str::trim)
+                    .filter(|id| !id.is_empty())
+                    .map(str::to_string)
+                    .collect::<HashSet<_>>()
+            })
+            .filter(|ids| !ids.is_empty()),
+    };
+    let preview_interval = state.events.preview_settings().interval(query.preview_fps);
+
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .or(query.last_event_id.as_deref());
+    let after = last_event_id.and_then(|id| parse_event_id(id, state.event_tx.epoch()));
+    tracing::info!(
+        "Event stream connected: {}{}",
+        filter.client_id,
+        match after {
+            Some(id) => format!(" (resuming after {})", id),
+            None => String::new(),
+        }
+    );
+
+    let (tx, rx) = mpsc::channel::<Event>(16);
+    tokio::spawn(forward_sse(state, filter, preview_interval, after, tx));
+    let stream = futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|event| (Ok(event), rx))
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// Feed one event stream until the client goes away
+async fn forward_sse(
+    state: AppState,
+    filter: SseFilter,
+    preview_interval: Option<Duration>,
+    after: Option<u64>,
+    tx: mpsc::Sender<Event>,
 ) {
-    let api_base = comfyui.public_base_url().to_string();
+    let epoch = state.event_tx.epoch().to_string();
+    let resume = state.event_tx.resume(after);
+    let mut event_rx = resume.rx;
+    let mut preview_rx = preview_interval.map(|_| state.events.subscribe_previews());
+    let preview_interval = preview_interval.unwrap_or_default();
+
+    let snapshot = |reason| {
+        state_snapshot(&state, reason, |prompt_id| {
+            filter.wants_prompt(prompt_id, state.events.owner(prompt_id).as_deref())
+        })
+    };
+
+    let connected = FrontendMessage::Connected {
+        client_id: filter.client_id.clone(),
+    };
+    let mut initial = vec![sse_event(&connected, None)];
+    match resume.missed {
+        Some(missed) => initial.extend(missed.into_iter().filter(|event| filter.wants(event)).map(
+            |event| {
+                Event::default()
+                    .id(event_id(&epoch, event.id))
+                    .data(event.json)
+            },
+        )),
+        // The snapshot carries the last id so a reconnect does not replay what it covers
+        None => {
+            let reason = match after {
+                Some(_) => SnapshotReason::Resync,
+                None => SnapshotReason::Connect,
+            };
+            let snapshot = snapshot(reason).await;
+            initial.push(sse_event(&snapshot, Some(event_id(&epoch, resume.last_id))));
+        }
+    }
+    for event in initial {
+        if tx.send(event).await.is_err() {
+            return;
+        }
+    }
+
+    let mut throttle = PreviewThrottle::new(preview_interval);
+    // Previews are sent as URLs and not logged, so they carry no id
+    let preview_event =
+        |preview: Outbound| Event::default().data(preview.linked_json.unwrap_or(preview.json));
+    'stream: loop {
+        let events: Vec<Event> = tokio::select! {
+            _ = tx.closed() => break,
+            event = event_rx.recv() => match event {
+                Ok(event) if filter.wants(&event) => throttle
+                    .flush(event.prompt_id.as_deref())
+                    .map(preview_event)
+                    .into_iter()
+                    .chain([Event::default().id(event_id(&epoch, event.id)).data(event.json)])
+                    .collect(),
+                Ok(_) => Vec::new(),
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!(
+                        "Event stream {} is too slow, {} events dropped, resyncing",
+                        filter.client_id,
+                        missed
+                    );
+                    let resume = state.event_tx.resume(None);
+                    event_rx = resume.rx;
+                    let snapshot = snapshot(SnapshotReason::Resync).await;
+                    vec![sse_event(&snapshot, Some(event_id(&epoch, resume.last_id)))]
+                }
+                Err(RecvError::Closed) => break,
+            },
+            Some(preview) = next_preview_event(&mut preview_rx) => filter
+                .wants(&preview)
+                .then(|| throttle.offer(preview))
+                .flatten()
+                .map(preview_event)
+                .into_iter()
+                .collect(),
+            preview = throttle.due() => vec![preview_event(preview)],
+        };
+        for event in events {
+            if tx.send(event).await.is_err() {
+                break 'stream;
+            }
+        }
+    }
+    tracing::info!("Event stream disconnected: {}", filter.client_id);
+}
+
+fn sse_event(message: &FrontendMessage, id: Option<String>) -> Event {
+    let event = Event::default().data(serde_json::to_string(message).unwrap_or_default());
+    match id {
+        Some(id) => event.id(id),
+        None => event,
+    }
+}
+
+fn event_id(epoch: &str, id: u64) -> String {
+    format!("{}-{}", epoch, id)
+}
+
+/// Log position of an event id from this process, `None` for ids of an earlier run
+fn parse_event_id(value: &str, epoch: &str) -> Option<u64> {
+    let (event_epoch, id) = value.trim().split_once('-')?;
+    (event_epoch == epoch).then(|| id.parse().ok()).flatten()
+}
+
+/// Latest preview of a prompt, linked from the `preview` events of /api/events
+async fn preview_handler(
+    State(state): State<AppState>,
+    Path(prompt_id): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    let preview = state
+        .events
+        .latest_preview(&prompt_id)
+        .ok_or_else(|| AppError::NotFound(format!("No preview for prompt {}", prompt_id)))?;
+    Ok((
+        [
+            (axum::http::header::CONTENT_TYPE, preview.mime_type),
+            (axum::http::header::CACHE_CONTROL, "no-store"),
+        ],
+        preview.data,
+    ))
+}
+
+// ============================================================================
+// ComfyUI WebSocket Listener
+// ============================================================================
+
+/// Start listening to the WebSocket of one ComfyUI instance for events
+pub async fn start_comfyui_listener(instance: ComfyUIInstance, jobs: JobQueue, events: EventHub) {
+    let api_base = instance.client.public_base_url().to_string();
 
     loop {
         // Get current WebSocket URL (may have changed)
-        let ws_url = format!("{}?clientId={}", comfyui.ws_url().await, client_id);
+        let ws_url = format!(
+            "{}?clientId={}",
+            instance.client.ws_url().await,
+            instance.client_id
+        );
 
         match tokio_tungstenite::connect_async(&ws_url).await {
             Ok((ws_stream, _)) => {
-                tracing::info!("Connected to ComfyUI WebSocket");
+                tracing::info!("Connected to ComfyUI WebSocket of {}", instance.name);
 
                 let (_, mut read) = ws_stream.split();
                 let mut current_prompt_id: Option<String> = None;
+                let mut unhandled = HashSet::new();
 
                 while let Some(msg) = read.next().await {
                     match msg {
                         Ok(tokio_tungstenite::tungstenite::Message::Text(text)) => {
-                            if let Ok(comfy_msg) = serde_json::from_str::<serde_json::Value>(&text)
-                            {
-                                let msg_type =
-                                    comfy_msg.get("type").and_then(|v| v.as_str()).unwrap_or("");
-                                if msg_type == "execution_start" {
-                                    current_prompt_id = comfy_msg
-                                        .get("data")
-                                        .and_then(|v| v.get("prompt_id"))
-                                        .and_then(|v| v.as_str())
-                                        .map(|v| v.to_string());
-                                }
-                                // Log all non-monitor messages
-                                if !msg_type.contains("monitor") && msg_type != "status" {
-                                    tracing::info!("ComfyUI [{}]: {}", msg_type, text);
+                            let Some(comfy_msg) =
+                                parse_comfyui_message(&instance.name, &text, &mut unhandled)
+                            else {
+                                continue;
+                            };
+                            match &comfy_msg {
+                                ComfyUIMessage::ExecutionStart { prompt_id }
+                                | ComfyUIMessage::ExecutionCached { prompt_id, .. }
+                                | ComfyUIMessage::Executing {
+                                    node: Some(_),
+                                    prompt_id,
                                 }
-                                if let Some(frontend_msg) =
-                                    convert_comfyui_message(&comfy_msg, &api_base)
-                                {
-                                    if let Ok(json) = serde_json::to_string(&frontend_msg) {
-                                        let _ = event_tx.send(json);
-                                    }
+                                | ComfyUIMessage::Progress { prompt_id, .. } => {
+                                    current_prompt_id = Some(prompt_id.clone());
                                 }
+                                ComfyUIMessage::Executing { node: None, .. }
+                                | ComfyUIMessage::ExecutionSuccess { .. }
+                                | ComfyUIMessage::ExecutionInterrupted { .. }
+                                | ComfyUIMessage::ExecutionError { .. } => current_prompt_id = None,
+                                _ => {}
+                            }
+                            if let Some(frontend_msg) =
+                                convert_comfyui_message(comfy_msg, &api_base)
+                            {
+                                jobs.apply_event(Some(&instance.name), &frontend_msg).await;
+                                events.publish_from(&instance.name, frontend_msg).await;
                             }
                         }
                         Ok(tokio_tungstenite::tungstenite::Message::Binary(data)) => {
-                            // Handle binary preview images
-                            if data.len() > 8 {
-                                // First 4 bytes: type, next 4: format, rest: image data
-                                let image_data = &data[8..];
-                                let base64_image = BASE64.encode(image_data);
-                                let prompt_id = current_prompt_id
-                                    .as_deref()
-                                    .unwrap_or("current")
-                                    .to_string();
-                                let preview_msg = FrontendMessage::Preview {
-                                    prompt_id,
-                                    image_data: format!("data:image/jpeg;base64,{}", base64_image),
-                                };
-                                if let Ok(json) = serde_json::to_string(&preview_msg) {
-                                    let _ = event_tx.send(json);
+                            let preview = match ComfyUIBinaryMessage::parse(&data) {
+                                Some(ComfyUIBinaryMessage::Preview(preview)) => preview,
+                                Some(ComfyUIBinaryMessage::Other(event)) => {
+                                    tracing::debug!(
+                                        "Ignored binary ComfyUI event {} on {}",
+                                        event,
+                                        instance.name
+                                    );
+                                    continue;
                                 }
-                            }
+                                None => {
+                                    tracing::warn!(
+                                        "Malformed binary ComfyUI message on {} ({} bytes)",
+                                        instance.name,
+                                        data.len()
+                                    );
+                                    continue;
+                                }
+                            };
+                            // Older ComfyUI versions only send previews of the running prompt
+                            let Some(prompt_id) = preview
+                                .prompt_id
+                                .clone()
+                                .or_else(|| current_prompt_id.clone())
+                            else {
+                                continue;
+                            };
+                            events
+                                .publish_preview(&instance.name, prompt_id, preview)
+                                .await;
                         }
                         Err(e) => {
-                            tracing::debug!("ComfyUI WebSocket error: {}", e);
+                            tracing::debug!("ComfyUI WebSocket error on {}: {}", instance.name, e);
                             break;
                         }
                         _ => {}
@@ -444,97 +1964,149 @@ pub async fn start_comfyui_listener(
                 }
             }
             Err(e) => {
-                tracing::debug!("ComfyUI WebSocket not available: {}", e);
+                tracing::debug!(
+                    "ComfyUI WebSocket of {} not available: {}",
+                    instance.name,
+                    e
+                );
             }
         }
 
-        tracing::debug!("ComfyUI WebSocket disconnected, reconnecting in 5 seconds...");
+        tracing::debug!(
+            "ComfyUI WebSocket of {} disconnected, reconnecting in 5 seconds...",
+            instance.name
+        );
         tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
     }
 }
 
+/// Parse a text message from ComfyUI, logging the ones that are not understood
+///
+/// Types without a variant are logged in full the first time they are seen
+/// (`unhandled`), since extensions such as system monitors send theirs every second.
+fn parse_comfyui_message(
+    instance: &str,
+    text: &str,
+    unhandled: &mut HashSet<String>,
+) -> Option<ComfyUIMessage> {
+    let value = match serde_json::from_str::<serde_json::Value>(text) {
+        Ok(value) => value,
+        Err(e) => {
+            tracing::warn!("Invalid JSON from ComfyUI {}: {}", instance, e);
+            return None;
+        }
+    };
+    let msg_type = value
+        .get("type")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default()
+        .to_string();
+
+    if !ComfyUIMessage::TYPES.contains(&msg_type.as_str()) {
+        if unhandled.insert(msg_type.clone()) {
+            tracing::info!("Unhandled ComfyUI message on {}: {}", instance, text);
+        } else {
+            tracing::debug!("Unhandled ComfyUI message on {} [{}]", instance, msg_type);
+        }
+        return None;
+    }
+    if msg_type != "status" {
+        tracing::info!("ComfyUI {} [{}]: {}", instance, msg_type, text);
+    }
+
+    match serde_json::from_value(value) {
+        Ok(message) => Some(message),
+        Err(e) => {
+            tracing::warn!(
+                "Malformed ComfyUI {} message on {}: {}",
+                msg_type,
+                instance,
+                e
+            );
+            None
+        }
+    }
+}
+
 /// Convert ComfyUI message to frontend message
-fn convert_comfyui_message(msg: &serde_json::Value, _api_base: &str) -> Option<FrontendMessage> {
-    let msg_type = msg.get("type")?.as_str()?;
-    let data = msg.get("data")?;
-
-    match msg_type {
-        "status" => {
-            let queue_remaining = data
-                .get("status")?
-                .get("exec_info")?
-                .get("queue_remaining")?
-                .as_u64()? as u32;
-
-            Some(FrontendMessage::QueueStatus {
+fn convert_comfyui_message(msg: ComfyUIMessage, _api_base: &str) -> Option<FrontendMessage> {
+    let message = match msg {
+        ComfyUIMessage::Status { status } => {
+            let queue_remaining = status.exec_info.queue_remaining;
+            FrontendMessage::QueueStatus {
                 running: if queue_remaining > 0 { 1 } else { 0 },
                 pending: queue_remaining.saturating_sub(1),
-            })
+            }
+        }
+        ComfyUIMessage::ExecutionStart { prompt_id } => FrontendMessage::Started { prompt_id },
+        ComfyUIMessage::ExecutionCached { nodes, prompt_id } => {
+            FrontendMessage::Cached { prompt_id, nodes }
         }
-        "execution_start" => {
-            let prompt_id = data.get("prompt_id")?.as_str()?.to_string();
-            Some(FrontendMessage::Started { prompt_id })
+        ComfyUIMessage::Executing { node, prompt_id } => {
+            FrontendMessage::Executing { prompt_id, node }
         }
-        "progress" => {
-            let prompt_id = data.get("prompt_id")?.as_str()?.to_string();
-            let node = data.get("node")?.as_str()?.to_string();
-            let value = data.get("value")?.as_u64()? as u32;
-            let max = data.get("max")?.as_u64()? as u32;
+        ComfyUIMessage::Progress {
+            value,
+            max,
+            prompt_id,
+            node,
+        } => {
             let percentage = if max > 0 {
                 (value as f32 / max as f32) * 100.0
             } else {
                 0.0
             };
-
-            Some(FrontendMessage::Progress {
+            FrontendMessage::Progress {
                 prompt_id,
                 node,
                 value,
                 max,
                 percentage,
-            })
+            }
         }
-        "executed" => {
-            let prompt_id = data.get("prompt_id")?.as_str()?.to_string();
-            let output = data.get("output")?;
+        ComfyUIMessage::Executed {
+            output, prompt_id, ..
+        } => {
             let images: Vec<ImageResult> = output
-                .get("images")?
-                .as_array()?
-                .iter()
-                .filter_map(|img| {
-                    let img_type = img.get("type")?.as_str()?.to_string();
-                    if img_type != "output" {
-                        return None;
-                    }
-                    let filename = img.get("filename")?.as_str()?.to_string();
-                    let subfolder = img.get("subfolder")?.as_str()?.to_string();
-                    Some(ImageResult {
-                        filename,
-                        subfolder,
-                        image_type: img_type,
-                    })
+                .images
+                .into_iter()
+                .filter(|img| img.image_type == "output")
+                .map(|img| ImageResult {
+                    filename: img.filename,
+                    subfolder: img.subfolder,
+                    image_type: img.image_type,
                 })
                 .collect();
-
-            if !images.is_empty() {
-                Some(FrontendMessage::Completed { prompt_id, images })
-            } else {
-                None
+            if images.is_empty() {
+                return None;
+            }
+            FrontendMessage::Completed {
+                prompt_id,
+                images,
+                seed: None,
             }
         }
-        "execution_error" => {
-            let prompt_id = data.get("prompt_id")?.as_str()?.to_string();
-            let exception_message = data
-                .get("exception_message")
-                .and_then(|v| v.as_str())
-                .unwrap_or("Unknown error")
-                .to_string();
-
-            Some(FrontendMessage::Error {
-                prompt_id: Some(prompt_id),
-                message: exception_message,
-            })
-        }
-        _ => None,
-    }
+        ComfyUIMessage::ExecutionSuccess { prompt_id } => FrontendMessage::Success { prompt_id },
+        ComfyUIMessage::ExecutionInterrupted {
+            prompt_id,
+            node_id,
+            node_type,
+        } => FrontendMessage::Interrupted {
+            prompt_id,
+            node_id,
+            node_type,
+        },
+        ComfyUIMessage::ExecutionError {
+            prompt_id,
+            node_id,
+            node_type,
+            exception_message,
+        } => FrontendMessage::Error {
+            prompt_id: Some(prompt_id),
+            message: exception_message,
+            node_id: Some(node_id),
+            node_type: Some(node_type),
+        },
+    };
+    Some(message)
 }
@@ -1,38 +1,62 @@
-use crate::config::Config;
-use crate::error::{AppError, AppResult};
+use crate::config::{ComfyUIConfig, Config};
+use crate::error::{AppError, AppResult, FieldError};
+use crate::metadata::MetadataFormat;
 use crate::models::*;
+use crate::object_info::ObjectInfo;
+use crate::templates::WorkflowTemplate;
+use crate::workflow;
 use reqwest::Client;
 use serde_json::{json, Value};
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, RwLock};
 
 pub use crate::models::{find_model, AvailableModels};
 
-/// ComfyUI client for interacting with the ComfyUI server
+/// Time limit of the health probes used for load balancing
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// ComfyUI client for interacting with one ComfyUI server
 #[derive(Clone)]
 pub struct ComfyUIClient {
     client: Client,
     config: Arc<Config>,
+    endpoint: Arc<RwLock<ComfyUIConfig>>,
+    object_info: Arc<Mutex<Option<CachedObjectInfo>>>,
+}
+
+/// `/object_info` as last fetched from a server URL
+struct CachedObjectInfo {
+    info: Arc<ObjectInfo>,
+    url: String,
+    fetched: Instant,
 }
 
 impl ComfyUIClient {
-    /// Create a new ComfyUI client
-    pub fn new(config: Arc<Config>) -> Self {
+    /// Create a new ComfyUI client for the server at `url`
+    pub fn new(config: Arc<Config>, url: &str) -> Self {
         let client = Client::builder()
             .timeout(std::time::Duration::from_secs(300))
             .build()
             .expect("Failed to create HTTP client");
 
-        Self { client, config }
+        Self {
+            client,
+            config,
+            endpoint: Arc::new(RwLock::new(ComfyUIConfig::new(url))),
+            object_info: Arc::new(Mutex::new(None)),
+        }
     }
 
     /// Get the base URL for ComfyUI
     pub async fn base_url(&self) -> String {
-        self.config.comfyui.read().await.url.clone()
+        self.endpoint.read().await.url.clone()
     }
 
     /// Get the WebSocket URL for ComfyUI
     pub async fn ws_url(&self) -> String {
-        self.config.comfyui.read().await.ws_url.clone()
+        self.endpoint.read().await.ws_url.clone()
     }
 
     /// Get the public base URL for this backend
@@ -40,25 +64,20 @@ impl ComfyUIClient {
         &self.config.public_base_url
     }
 
+    /// PNG metadata format configured for served images
+    pub fn png_metadata(&self) -> MetadataFormat {
+        self.config.png_metadata
+    }
+
     /// Update ComfyUI URL
     pub async fn set_url(&self, url: &str) {
-        let mut comfyui = self.config.comfyui.write().await;
-        *comfyui = crate::config::ComfyUIConfig::new(url);
+        let mut comfyui = self.endpoint.write().await;
+        *comfyui = ComfyUIConfig::new(url);
     }
 
     /// Get current ComfyUI URL
     pub async fn get_url(&self) -> String {
-        self.config.comfyui.read().await.url.clone()
-    }
-
-    /// Check if ComfyUI is reachable
-    pub async fn health_check(&self) -> AppResult<bool> {
-        let base_url = self.base_url().await;
-        let url = format!("{}/system_stats", base_url);
-        match self.client.get(&url).send().await {
-            Ok(resp) => Ok(resp.status().is_success()),
-            Err(_) => Ok(false),
-        }
+        self.endpoint.read().await.url.clone()
     }
 
     /// Get system stats from ComfyUI
@@ -95,17 +114,50 @@ impl ComfyUIClient {
             .map_err(|e| AppError::ComfyUIApi(e.to_string()))
     }
 
-    /// Queue a prompt for execution
+    /// Queue status and system stats, failing fast when the server does not answer
+    pub async fn probe(&self) -> AppResult<(QueueStatus, SystemStats)> {
+        let base_url = self.base_url().await;
+        let get = |path: &str| {
+            self.client
+                .get(format!("{}{}", base_url, path))
+                .timeout(PROBE_TIMEOUT)
+                .send()
+        };
+        let (queue, stats) = tokio::try_join!(get("/queue"), get("/system_stats"))?;
+
+        for resp in [&queue, &stats] {
+            if !resp.status().is_success() {
+                return Err(AppError::ComfyUIApi(format!(
+                    "Health probe failed: {}",
+                    resp.status()
+                )));
+            }
+        }
+
+        let queue = queue
+            .json()
+            .await
+            .map_err(|e| AppError::ComfyUIApi(e.to_string()))?;
+        let stats = stats
+            .json()
+            .await
+            .map_err(|e| AppError::ComfyUIApi(e.to_string()))?;
+        Ok((queue, stats))
+    }
+
+    /// Queue a prompt for execution, optionally under a caller chosen prompt_id
     pub async fn queue_prompt(
         &self,
         workflow: Value,
         client_id: Option<String>,
+        prompt_id: Option<String>,
     ) -> AppResult<ComfyUIPromptResponse> {
         let url = format!("{}/prompt", self.base_url().await);
 
         let request = ComfyUIPromptRequest {
             prompt: workflow,
             client_id,
+            prompt_id,
         };
 
         let resp = self.client.post(&url).json(&request).send().await?;
@@ -219,6 +271,31 @@ impl ComfyUIClient {
             .map_err(|e| AppError::ComfyUIApi(e.to_string()))
     }
 
+    /// Upload an image into ComfyUI's input folder
+    pub async fn upload_image(&self, data: Vec<u8>, filename: &str) -> AppResult<UploadedImage> {
+        let url = format!("{}/upload/image", self.base_url().await);
+
+        let part = reqwest::multipart::Part::bytes(data).file_name(filename.to_string());
+        let form = reqwest::multipart::Form::new()
+            .part("image", part)
+            .text("type", "input")
+            .text("overwrite", "true");
+
+        let resp = self.client.post(&url).multipart(form).send().await?;
+
+        if !resp.status().is_success() {
+            let error_text = resp.text().await.unwrap_or_default();
+            return Err(AppError::ComfyUIApi(format!(
+                "Failed to upload image: {}",
+                error_text
+            )));
+        }
+
+        resp.json()
+            .await
+            .map_err(|e| AppError::ComfyUIApi(e.to_string()))
+    }
+
     /// Cancel the current execution
     pub async fn interrupt(&self) -> AppResult<()> {
         let url = format!("{}/interrupt", self.base_url().await);
@@ -234,6 +311,53 @@ impl ComfyUIClient {
         Ok(())
     }
 
+    /// Interrupt `prompt_id` if it is the prompt currently executing
+    ///
+    /// Returns false without interrupting when another prompt (or none) is running. The id is
+    /// also sent along so ComfyUI versions that support it ignore a stale interrupt.
+    pub async fn interrupt_prompt(&self, prompt_id: &str) -> AppResult<bool> {
+        if !self.get_queue().await?.is_running(prompt_id) {
+            return Ok(false);
+        }
+
+        let url = format!("{}/interrupt", self.base_url().await);
+        let resp = self
+            .client
+            .post(&url)
+            .json(&json!({"prompt_id": prompt_id}))
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            return Err(AppError::ComfyUIApi(format!(
+                "Failed to interrupt: {}",
+                resp.status()
+            )));
+        }
+
+        Ok(true)
+    }
+
+    /// Remove pending prompts from the ComfyUI queue
+    pub async fn delete_from_queue(&self, prompt_ids: &[String]) -> AppResult<()> {
+        let url = format!("{}/queue", self.base_url().await);
+        let resp = self
+            .client
+            .post(&url)
+            .json(&json!({"delete": prompt_ids}))
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            return Err(AppError::ComfyUIApi(format!(
+                "Failed to delete from queue: {}",
+                resp.status()
+            )));
+        }
+
+        Ok(())
+    }
+
     /// Clear the queue
     pub async fn clear_queue(&self) -> AppResult<()> {
         let url = format!("{}/queue", self.base_url().await);
@@ -254,9 +378,43 @@ impl ComfyUIClient {
         Ok(())
     }
 
-    /// Get available models from ComfyUI
-    pub async fn get_available_models(&self) -> AppResult<AvailableModels> {
-        let url = format!("{}/object_info", self.base_url().await);
+    /// Get the installed node classes and their input schemas
+    ///
+    /// The answer is cached for `OBJECT_INFO_TTL` seconds and refetched early when the
+    /// server URL changes. Concurrent callers share a single request.
+    pub async fn get_object_info(&self) -> AppResult<Arc<ObjectInfo>> {
+        let base_url = self.base_url().await;
+        let ttl = Duration::from_secs(self.config.object_info_ttl);
+        let mut cache = self.object_info.lock().await;
+        if let Some(cached) = cache
+            .as_ref()
+            .filter(|c| c.url == base_url && c.fetched.elapsed() < ttl)
+        {
+            return Ok(cached.info.clone());
+        }
+
+        let info = Arc::new(self.fetch_object_info(&base_url).await?);
+        *cache = Some(CachedObjectInfo {
+            info: info.clone(),
+            url: base_url,
+            fetched: Instant::now(),
+        });
+        Ok(info)
+    }
+
+    /// Fetch `/object_info` again, e.g. after models were installed
+    pub async fn refresh_object_info(&self) -> AppResult<Arc<ObjectInfo>> {
+        self.invalidate_object_info().await;
+        self.get_object_info().await
+    }
+
+    /// Drop the cached `/object_info` so the next use fetches it
+    pub async fn invalidate_object_info(&self) {
+        *self.object_info.lock().await = None;
+    }
+
+    async fn fetch_object_info(&self, base_url: &str) -> AppResult<ObjectInfo> {
+        let url = format!("{}/object_info", base_url);
         let resp = self.client.get(&url).send().await?;
 
         if !resp.status().is_success() {
@@ -271,197 +429,472 @@ impl ComfyUIClient {
             .await
             .map_err(|e| AppError::ComfyUIApi(e.to_string()))?;
 
-        let mut models = AvailableModels::default();
+        Ok(ObjectInfo::new(info))
+    }
 
-        // Get UNET models
-        if let Some(unet_list) = info
-            .get("UNETLoader")
-            .and_then(|v| v.get("input"))
-            .and_then(|v| v.get("required"))
-            .and_then(|v| v.get("unet_name"))
-            .and_then(|v| v.get(0))
-            .and_then(|v| v.as_array())
-        {
-            models.unet = unet_list
-                .iter()
-                .filter_map(|v| v.as_str().map(String::from))
-                .collect();
-        }
-
-        // Get CLIP models
-        if let Some(clip_list) = info
-            .get("DualCLIPLoader")
-            .and_then(|v| v.get("input"))
-            .and_then(|v| v.get("required"))
-            .and_then(|v| v.get("clip_name1"))
-            .and_then(|v| v.get(0))
-            .and_then(|v| v.as_array())
-        {
-            models.clip = clip_list
-                .iter()
-                .filter_map(|v| v.as_str().map(String::from))
-                .collect();
-        }
-
-        // Get VAE models
-        if let Some(vae_list) = info
-            .get("VAELoader")
-            .and_then(|v| v.get("input"))
-            .and_then(|v| v.get("required"))
-            .and_then(|v| v.get("vae_name"))
-            .and_then(|v| v.get(0))
-            .and_then(|v| v.as_array())
-        {
-            models.vae = vae_list
-                .iter()
-                .filter_map(|v| v.as_str().map(String::from))
-                .collect();
+    /// Get available models from ComfyUI
+    pub async fn get_available_models(&self) -> AppResult<AvailableModels> {
+        Ok(self.get_object_info().await?.models())
+    }
+
+    /// Build workflow from generation request by rendering a workflow template
+    pub fn build_workflow(
+        &self,
+        request: &GenerateRequest,
+        models: &AvailableModels,
+        template: &WorkflowTemplate,
+        seed: u64,
+    ) -> AppResult<BuiltWorkflow> {
+        let models = resolve_models(request, models, template)?;
+
+        let mut values: HashMap<&str, Value> = HashMap::from([
+            ("seed", json!(seed)),
+            ("steps", json!(request.steps)),
+            ("cfg", json!(request.cfg)),
+            ("sampler_name", json!(request.sampler_name)),
+            ("scheduler", json!(request.scheduler)),
+            ("denoise", json!(request.denoise)),
+            ("width", json!(request.width)),
+            ("height", json!(request.height)),
+            ("batch_size", json!(request.batch_size)),
+            ("prompt", json!(template.format_prompt(&request.prompt))),
+            (
+                "negative_prompt",
+                json!(template.format_negative_prompt(&request.negative_prompt)),
+            ),
+            ("unet", json!(models.unet)),
+            ("clip1", json!(models.clip1)),
+            ("clip2", json!(models.clip2)),
+            ("vae", json!(models.vae)),
+        ]);
+        if let Some(weight_dtype) = &request.weight_dtype {
+            values.insert("weight_dtype", json!(weight_dtype));
         }
 
-        Ok(models)
+        let mut workflow = template.render(&values);
+
+        if !request.loras.is_empty() {
+            let sampler = template.sampler_node().ok_or_else(|| {
+                AppError::InvalidRequest(format!(
+                    "Template '{}' has no sampler node to attach LoRAs to",
+                    template.name
+                ))
+            })?;
+            chain_loras(&mut workflow, &sampler, &request.loras);
+        }
+
+        if let Some(hires) = &request.hires {
+            let sampler = template.sampler_node().ok_or_else(|| {
+                AppError::InvalidRequest(format!(
+                    "Template '{}' has no sampler node for a hires pass",
+                    template.name
+                ))
+            })?;
+            add_hires_pass(&mut workflow, template, &sampler, request, hires)?;
+        }
+
+        Ok(BuiltWorkflow {
+            workflow,
+            seed,
+            models,
+        })
     }
 
-    /// Build workflow from generation request based on newbie-api.json template
-    pub fn build_workflow(&self, request: &GenerateRequest, models: &AvailableModels) -> Value {
-        let seed = if request.seed < 0 {
-            rand_seed()
+    /// Build a LoadImage → UpscaleModelLoader → ImageUpscaleWithModel → SaveImage workflow
+    pub fn build_upscale_workflow(&self, request: &UpscaleRequest) -> Value {
+        let mut workflow = json!({});
+
+        // LoadImage accepts "subfolder/name [type]" to read from the output or temp folders
+        let image = if request.subfolder.is_empty() {
+            format!("{} [{}]", request.filename, request.image_type)
         } else {
-            request.seed as u64
+            format!(
+                "{}/{} [{}]",
+                request.subfolder, request.filename, request.image_type
+            )
         };
 
-        // Find matching model files (case-insensitive, try keywords in order)
-        let unet_name = find_model(&models.unet, &["newbie"])
-            .unwrap_or_else(|| "newbie01.safetensors".to_string());
-        let clip_name1 = find_model(
-            &models.clip,
-            &["gemma_3_4b", "gemma3_4b", "gemma_3", "gemma3", "gemma"],
-        )
-        .unwrap_or_else(|| "gemma3-4b-it.safetensors".to_string());
-        let clip_name2 = find_model(&models.clip, &["jina"])
-            .unwrap_or_else(|| "jina-clip-v2.safetensors".to_string());
-        let vae_name = find_model(&models.vae, &["newbie", "diffusion_pytorch"])
-            .unwrap_or_else(|| "newbie-image.safetensors".to_string());
-
-        // Build the prompt prefix for newbie model
-        let positive_prompt = format!(
-            "You are an assistant designed to generate high-quality anime images with the highest degree of image-text alignment based on xml format textual prompts. <Prompt Start>\n{}",
-            request.prompt
+        let load = workflow::add_node(
+            &mut workflow,
+            "LoadImage",
+            "加载图像",
+            json!({ "image": image }),
+        );
+        let loader = workflow::add_node(
+            &mut workflow,
+            "UpscaleModelLoader",
+            "加载放大模型",
+            json!({ "model_name": request.upscale_model }),
+        );
+        let upscale = workflow::add_node(
+            &mut workflow,
+            "ImageUpscaleWithModel",
+            "使用模型放大图像",
+            json!({
+                "upscale_model": workflow::link(&loader, 0),
+                "image": workflow::link(&load, 0)
+            }),
         );
+        let mut pixels = workflow::link(&upscale, 0);
+
+        if let Some(rescale) = request.rescale {
+            let scale = workflow::add_node(
+                &mut workflow,
+                "ImageScaleBy",
+                "按系数缩放图像",
+                json!({ "upscale_method": "lanczos", "scale_by": rescale, "image": pixels }),
+            );
+            pixels = workflow::link(&scale, 0);
+        }
 
-        // Default negative prompt if empty
-        let negative_prompt = if request.negative_prompt.is_empty() {
-            "<danbooru_tags>low_score_rate, worst quality, low quality, bad quality, lowres, low res, pixelated, blurry, blurred, compression artifacts, jpeg artifacts, bad anatomy, worst hands, deformed hands, deformed fingers, deformed feet, deformed toes, extra limbs, extra arms, extra legs, extra fingers, extra digits, extra digit, fused fingers, missing limbs, missing arms, missing fingers, missing toes, wrong hands, ugly hands, ugly fingers, twisted hands, flexible deformity, conjoined, disembodied, text, watermark, signature, logo, ugly, worst, very displeasing, displeasing, error, doesnotexist, unfinished, poorly drawn face, poorly drawn hands, poorly drawn feet, artistic error, bad proportions, bad perspective, out of frame, ai-generated, ai-assisted, stable diffusion, overly saturated, overly vivid, cross-eye, expressionless, scan, sketch, monochrome, simple background, abstract, sequence, lineup, 2koma, 4koma, microsoft paint \\(medium\\), artifacts, adversarial noise, has bad revision, resized, image sample,low_aesthetic</danbooru_tags>".to_string()
-        } else {
-            format!("<danbooru_tags>{}</danbooru_tags>", request.negative_prompt)
-        };
+        workflow::add_node(
+            &mut workflow,
+            "SaveImage",
+            "保存图像",
+            json!({ "filename_prefix": "upscale", "images": pixels }),
+        );
 
-        json!({
-            "3": {
-                "inputs": {
-                    "seed": seed,
-                    "steps": request.steps,
-                    "cfg": request.cfg,
-                    "sampler_name": request.sampler_name,
-                    "scheduler": request.scheduler,
-                    "denoise": request.denoise,
-                    "model": ["51", 0],
-                    "positive": ["61", 0],
-                    "negative": ["59", 0],
-                    "latent_image": ["9", 0]
-                },
-                "class_type": "KSampler",
-                "_meta": {"title": "K采样器"}
-            },
-            "4": {
-                "inputs": {
-                    "samples": ["3", 0],
-                    "vae": ["5", 0]
-                },
-                "class_type": "VAEDecode",
-                "_meta": {"title": "VAE解码"}
-            },
-            "5": {
-                "inputs": {
-                    "vae_name": vae_name
-                },
-                "class_type": "VAELoader",
-                "_meta": {"title": "VAE加载器"}
-            },
-            "9": {
-                "inputs": {
+        workflow
+    }
+
+    /// Build an img2img workflow: the template graph with its latent replaced by the encoded source
+    ///
+    /// With `source.mask` set this becomes an inpainting workflow.
+    pub fn build_img2img_workflow(
+        &self,
+        request: &GenerateRequest,
+        models: &AvailableModels,
+        template: &WorkflowTemplate,
+        source: &SourceImage,
+        seed: u64,
+    ) -> AppResult<BuiltWorkflow> {
+        let mut built = self.build_workflow(request, models, template, seed)?;
+        let workflow = &mut built.workflow;
+        let sampler = template.sampler_node().ok_or_else(|| {
+            AppError::InvalidRequest(format!(
+                "Template '{}' has no sampler node for img2img",
+                template.name
+            ))
+        })?;
+        let vae = vae_link(workflow, template).ok_or_else(|| {
+            AppError::InvalidRequest(format!(
+                "Template '{}' has no VAE to encode the source image",
+                template.name
+            ))
+        })?;
+
+        let load = workflow::add_node(
+            workflow,
+            "LoadImage",
+            "加载图像",
+            json!({ "image": source.image }),
+        );
+        let mut pixels = workflow::link(&load, 0);
+
+        if source.resize {
+            let scale = workflow::add_node(
+                workflow,
+                "ImageScale",
+                "缩放图像",
+                json!({
+                    "upscale_method": "lanczos",
                     "width": request.width,
                     "height": request.height,
-                    "batch_size": request.batch_size
-                },
-                "class_type": "EmptySD3LatentImage",
-                "_meta": {"title": "空Latent_SD3"}
-            },
-            "39": {
-                "inputs": {
-                    "filename_prefix": "ComfyUI",
-                    "images": ["4", 0]
-                },
-                "class_type": "SaveImage",
-                "_meta": {"title": "保存图像"}
-            },
-            "40": {
-                "inputs": {
-                    "images": ["4", 0]
-                },
-                "class_type": "PreviewImage",
-                "_meta": {"title": "预览图像"}
-            },
-            "51": {
-                "inputs": {
-                    "multiplier": 0.9,
-                    "model": ["54", 0]
-                },
-                "class_type": "RescaleCFG",
-                "_meta": {"title": "缩放CFG"}
-            },
-            "54": {
-                "inputs": {
-                    "unet_name": unet_name,
-                    "weight_dtype": "default"
-                },
-                "class_type": "UNETLoader",
-                "_meta": {"title": "UNET加载器"}
-            },
-            "58": {
-                "inputs": {
-                    "clip_name1": clip_name1,
-                    "clip_name2": clip_name2,
-                    "type": "newbie",
-                    "device": "default"
-                },
-                "class_type": "DualCLIPLoader",
-                "_meta": {"title": "双CLIP加载器"}
-            },
-            "59": {
-                "inputs": {
-                    "text": negative_prompt,
-                    "clip": ["58", 0]
-                },
-                "class_type": "CLIPTextEncode",
-                "_meta": {"title": "CLIP文本编码器"}
-            },
-            "61": {
-                "inputs": {
-                    "text": positive_prompt,
-                    "clip": ["58", 0]
-                },
-                "class_type": "CLIPTextEncode",
-                "_meta": {"title": "CLIP文本编码器"}
+                    "crop": "center",
+                    "image": pixels
+                }),
+            );
+            pixels = workflow::link(&scale, 0);
+        }
+
+        let mut latent = match &source.mask {
+            Some(mask) => encode_inpaint(workflow, &load, pixels, vae, mask),
+            None => {
+                let encode = workflow::add_node(
+                    workflow,
+                    "VAEEncode",
+                    "VAE编码",
+                    json!({ "pixels": pixels, "vae": vae }),
+                );
+                workflow::link(&encode, 0)
             }
+        };
+
+        if request.batch_size > 1 {
+            let repeat = workflow::add_node(
+                workflow,
+                "RepeatLatentBatch",
+                "复制Latent批次",
+                json!({ "samples": latent, "amount": request.batch_size }),
+            );
+            latent = workflow::link(&repeat, 0);
+        }
+
+        workflow::set_input(workflow, &sampler, "latent_image", latent);
+
+        Ok(built)
+    }
+}
+
+/// Model files for the template's loaders: the ones the request names, else the first
+/// installed file matching the usual NewBie names
+///
+/// Names are only checked when ComfyUI lists the loader's options. Loaders the template
+/// does not bind are left empty unless the request names a file.
+fn resolve_models(
+    request: &GenerateRequest,
+    installed: &AvailableModels,
+    template: &WorkflowTemplate,
+) -> AppResult<ResolvedModels> {
+    let mut missing = Vec::new();
+    let mut pick = |field: &str, chosen: &Option<String>, options: &[String], keywords: &[&str]| {
+        let found = match chosen {
+            Some(name) if options.is_empty() || options.contains(name) => return name.clone(),
+            Some(name) => Err(format!("'{}' is not installed", name)),
+            None if !template.bindings.contains_key(field) => return String::new(),
+            None => find_model(options, keywords).ok_or_else(|| {
+                format!(
+                    "no installed file matches {}, name one in '{}'",
+                    keywords.join(" / "),
+                    field
+                )
+            }),
+        };
+        found.unwrap_or_else(|message| {
+            missing.push(FieldError {
+                message,
+                allowed: Some(options.to_vec()),
+                ..FieldError::new(field)
+            });
+            String::new()
         })
+    };
+
+    let models = ResolvedModels {
+        unet: pick("unet", &request.unet, &installed.unet, &["newbie"]),
+        clip1: pick(
+            "clip1",
+            &request.clip1,
+            &installed.clip,
+            &["gemma_3_4b", "gemma3_4b", "gemma_3", "gemma3", "gemma"],
+        ),
+        clip2: pick("clip2", &request.clip2, &installed.clip, &["jina"]),
+        vae: pick(
+            "vae",
+            &request.vae,
+            &installed.vae,
+            &["newbie", "diffusion_pytorch"],
+        ),
+    };
+
+    if missing.is_empty() {
+        Ok(models)
+    } else {
+        Err(AppError::MissingModels(missing))
+    }
+}
+
+/// Append an upscale step and a second sampler after `sampler`
+///
+/// Everything that consumed the first pass latent is rewired to the second pass.
+fn add_hires_pass(
+    workflow: &mut Value,
+    template: &WorkflowTemplate,
+    sampler: &str,
+    request: &GenerateRequest,
+    hires: &HiresRequest,
+) -> AppResult<()> {
+    let first_pass = workflow::link(sampler, 0);
+    // Collect consumers before the upscale chain adds its own links to the first pass
+    let consumers = workflow::consumers_of(workflow, sampler, 0);
+
+    let upscaled = match hires.method {
+        HiresMethod::Latent => {
+            let upscale = workflow::add_node(
+                workflow,
+                "LatentUpscaleBy",
+                "Latent按系数缩放",
+                json!({
+                    "upscale_method": hires.latent_upscale_method,
+                    "scale_by": hires.scale,
+                    "samples": first_pass
+                }),
+            );
+            workflow::link(&upscale, 0)
+        }
+        HiresMethod::Model => {
+            let model_name = hires.upscale_model.as_deref().ok_or_else(|| {
+                AppError::InvalidRequest("Hires method 'model' requires upscale_model".to_string())
+            })?;
+            let vae = vae_link(workflow, template).ok_or_else(|| {
+                AppError::InvalidRequest(format!(
+                    "Template '{}' has no VAE for a model-based hires pass",
+                    template.name
+                ))
+            })?;
+            let (width, height) = hires_size(request, hires);
+
+            let decode = workflow::add_node(
+                workflow,
+                "VAEDecode",
+                "VAE解码",
+                json!({ "samples": first_pass, "vae": vae }),
+            );
+            let loader = workflow::add_node(
+                workflow,
+                "UpscaleModelLoader",
+                "加载放大模型",
+                json!({ "model_name": model_name }),
+            );
+            let upscale = workflow::add_node(
+                workflow,
+                "ImageUpscaleWithModel",
+                "使用模型放大图像",
+                json!({
+                    "upscale_model": workflow::link(&loader, 0),
+                    "image": workflow::link(&decode, 0)
+                }),
+            );
+            // Upscale models have a fixed factor, scale to the exact target size
+            let scale = workflow::add_node(
+                workflow,
+                "ImageScale",
+                "缩放图像",
+                json!({
+                    "upscale_method": "lanczos",
+                    "width": width,
+                    "height": height,
+                    "crop": "disabled",
+                    "image": workflow::link(&upscale, 0)
+                }),
+            );
+            let encode = workflow::add_node(
+                workflow,
+                "VAEEncode",
+                "VAE编码",
+                json!({ "pixels": workflow::link(&scale, 0), "vae": vae }),
+            );
+            workflow::link(&encode, 0)
+        }
+    };
+
+    let mut inputs = workflow
+        .get(sampler)
+        .and_then(|node| node.get("inputs"))
+        .cloned()
+        .unwrap_or_else(|| json!({}));
+    inputs["steps"] = json!(hires.steps);
+    inputs["denoise"] = json!(hires.denoise);
+    inputs["latent_image"] = upscaled;
+    let second_pass = workflow::add_node(workflow, "KSampler", "K采样器 (高清修复)", inputs);
+
+    workflow::redirect_links(
+        workflow,
+        &consumers,
+        &first_pass,
+        &workflow::link(&second_pass, 0),
+    );
+
+    Ok(())
+}
+
+/// Final image size of a hires pass, rounded down to a multiple of 8
+pub fn hires_size(request: &GenerateRequest, hires: &HiresRequest) -> (u32, u32) {
+    let scale = |v: u32| ((v as f32 * hires.scale) as u32 / 8 * 8).max(8);
+    (scale(request.width), scale(request.height))
+}
+
+/// Encode the source for inpainting and return the latent link
+fn encode_inpaint(
+    workflow: &mut Value,
+    load: &str,
+    pixels: Value,
+    vae: Value,
+    mask: &InpaintMask,
+) -> Value {
+    let mut mask_link = match &mask.source {
+        MaskSource::Alpha => workflow::link(load, 1),
+        MaskSource::Image { image, channel } => {
+            let load_mask = workflow::add_node(
+                workflow,
+                "LoadImageMask",
+                "加载图像遮罩",
+                json!({ "image": image, "channel": channel }),
+            );
+            workflow::link(&load_mask, 0)
+        }
+    };
+
+    match mask.mode {
+        InpaintMode::VaeEncodeForInpaint => {
+            let encode = workflow::add_node(
+                workflow,
+                "VAEEncodeForInpaint",
+                "VAE内补编码器",
+                json!({
+                    "pixels": pixels,
+                    "vae": vae,
+                    "mask": mask_link,
+                    "grow_mask_by": mask.grow
+                }),
+            );
+            workflow::link(&encode, 0)
+        }
+        InpaintMode::LatentNoiseMask => {
+            if mask.grow > 0 {
+                let grow = workflow::add_node(
+                    workflow,
+                    "GrowMask",
+                    "扩展遮罩",
+                    json!({ "mask": mask_link, "expand": mask.grow, "tapered_corners": true }),
+                );
+                mask_link = workflow::link(&grow, 0);
+            }
+            let encode = workflow::add_node(
+                workflow,
+                "VAEEncode",
+                "VAE编码",
+                json!({ "pixels": pixels, "vae": vae }),
+            );
+            let noise_mask = workflow::add_node(
+                workflow,
+                "SetLatentNoiseMask",
+                "设置Latent噪波遮罩",
+                json!({ "samples": workflow::link(&encode, 0), "mask": mask_link }),
+            );
+            workflow::link(&noise_mask, 0)
+        }
     }
 }
 
-/// Generate a random seed
-fn rand_seed() -> u64 {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    let duration = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards");
-    duration.as_nanos() as u64 % 1_000_000_000_000_000
+/// Link to the VAE output: the node bound to `vae`, else whatever feeds the VAEDecode
+fn vae_link(workflow: &Value, template: &WorkflowTemplate) -> Option<Value> {
+    if let Some(node_id) = template.bound_nodes("vae").first() {
+        return Some(workflow::link(node_id, 0));
+    }
+    let decode = workflow::find_node_by_class(workflow, "VAEDecode")?;
+    workflow::get_input(workflow, &decode, "vae").cloned()
+}
+
+/// Insert one LoraLoaderModelOnly per entry between the sampler and its current model input
+fn chain_loras(workflow: &mut Value, sampler: &str, loras: &[LoraRequest]) {
+    let Some(mut model) = workflow::get_input(workflow, sampler, "model").cloned() else {
+        return;
+    };
+
+    for lora in loras {
+        let node_id = workflow::add_node(
+            workflow,
+            "LoraLoaderModelOnly",
+            "LoRA加载器",
+            json!({
+                "lora_name": lora.name,
+                "strength_model": lora.strength_model,
+                "model": model
+            }),
+        );
+        model = workflow::link(&node_id, 0);
+    }
+
+    workflow::set_input(workflow, sampler, "model", model);
 }
@@ -1,6 +1,6 @@
 use std::env;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+
+use crate::metadata::MetadataFormat;
 
 /// Dynamic ComfyUI configuration that can be updated at runtime
 #[derive(Debug, Clone)]
@@ -20,6 +20,13 @@ impl ComfyUIConfig {
     }
 }
 
+/// A named ComfyUI instance of the pool
+#[derive(Debug, Clone)]
+pub struct InstanceConfig {
+    pub name: String,
+    pub url: String,
+}
+
 /// Application configuration loaded from environment variables
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
@@ -28,12 +35,34 @@ pub struct Config {
     pub host: String,
     /// Server port
     pub port: u16,
-    /// ComfyUI configuration (dynamic)
-    pub comfyui: Arc<RwLock<ComfyUIConfig>>,
+    /// ComfyUI instances jobs are spread over, in order of preference
+    pub comfyui_instances: Vec<InstanceConfig>,
     /// Public base URL for this backend (used in image URLs)
     pub public_base_url: String,
     /// Allowed CORS origins
     pub cors_origins: Vec<String>,
+    /// Directory containing workflow template JSON files
+    pub templates_dir: String,
+    /// Template used when a request does not name one
+    pub default_template: String,
+    /// Append-only JSONL file holding the generation history
+    pub history_path: String,
+    /// Parameter chunks embedded into served PNGs
+    pub png_metadata: MetadataFormat,
+    /// Directory receiving sweep contact sheets
+    pub sweeps_dir: String,
+    /// Optional font for sweep labels, tried before the bundled one
+    pub label_font: Option<String>,
+    /// Jobs handed to each ComfyUI instance at once; the rest wait in the backend queue
+    pub max_in_flight: usize,
+    /// Live previews per second sent to each client at most, 0 disables previews
+    pub preview_max_fps: f32,
+    /// Longest edge of forwarded previews in pixels, 0 forwards them unchanged
+    pub preview_max_size: u32,
+    /// JPEG quality of downscaled previews
+    pub preview_quality: u8,
+    /// Seconds ComfyUI's `/object_info` is cached for
+    pub object_info_ttl: u64,
 }
 
 impl Config {
@@ -47,9 +76,17 @@ impl Config {
             .parse()
             .expect("PORT must be a valid number");
 
-        let comfyui_host = env::var("COMFYUI_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
-        let comfyui_port = env::var("COMFYUI_PORT").unwrap_or_else(|_| "8188".to_string());
-        let comfyui_url = format!("http://{}:{}", comfyui_host, comfyui_port);
+        let comfyui_instances = match env::var("COMFYUI_INSTANCES") {
+            Ok(list) if !list.trim().is_empty() => parse_instances(&list),
+            _ => {
+                let host = env::var("COMFYUI_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+                let port = env::var("COMFYUI_PORT").unwrap_or_else(|_| "8188".to_string());
+                vec![InstanceConfig {
+                    name: "default".to_string(),
+                    url: format!("http://{}:{}", host, port),
+                }]
+            }
+        };
 
         let public_base_url = env::var("PUBLIC_BASE_URL")
             .unwrap_or_else(|_| format!("http://localhost:{}", port))
@@ -62,12 +99,67 @@ impl Config {
             .map(|s| s.trim().to_string())
             .collect();
 
+        let templates_dir = env::var("TEMPLATES_DIR").unwrap_or_else(|_| "templates".to_string());
+        let default_template =
+            env::var("DEFAULT_TEMPLATE").unwrap_or_else(|_| "newbie".to_string());
+
+        let history_path =
+            env::var("HISTORY_PATH").unwrap_or_else(|_| "data/history.jsonl".to_string());
+
+        let png_metadata = env::var("PNG_METADATA")
+            .ok()
+            .map(|v| {
+                MetadataFormat::parse(&v).expect("PNG_METADATA must be none, newbie, a1111 or both")
+            })
+            .unwrap_or_default();
+
+        let sweeps_dir = env::var("SWEEPS_DIR").unwrap_or_else(|_| "data/sweeps".to_string());
+        let label_font = env::var("LABEL_FONT").ok().filter(|v| !v.is_empty());
+
+        let max_in_flight = env::var("COMFYUI_MAX_IN_FLIGHT")
+            .unwrap_or_else(|_| "2".to_string())
+            .parse()
+            .expect("COMFYUI_MAX_IN_FLIGHT must be a valid number");
+
+        let preview_max_fps = env::var("PREVIEW_MAX_FPS")
+            .unwrap_or_else(|_| "5".to_string())
+            .parse::<f32>()
+            .ok()
+            .filter(|fps| fps.is_finite() && *fps >= 0.0)
+            .expect("PREVIEW_MAX_FPS must be a non-negative number");
+        let preview_max_size = env::var("PREVIEW_MAX_SIZE")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .expect("PREVIEW_MAX_SIZE must be a valid number");
+        let preview_quality = env::var("PREVIEW_QUALITY")
+            .unwrap_or_else(|_| "75".to_string())
+            .parse::<u8>()
+            .ok()
+            .filter(|quality| (1..=100).contains(quality))
+            .expect("PREVIEW_QUALITY must be between 1 and 100");
+
+        let object_info_ttl = env::var("OBJECT_INFO_TTL")
+            .unwrap_or_else(|_| "300".to_string())
+            .parse()
+            .expect("OBJECT_INFO_TTL must be a valid number of seconds");
+
         Self {
             host,
             port,
-            comfyui: Arc::new(RwLock::new(ComfyUIConfig::new(&comfyui_url))),
+            comfyui_instances,
             public_base_url,
             cors_origins,
+            templates_dir,
+            default_template,
+            history_path,
+            png_metadata,
+            sweeps_dir,
+            label_font,
+            max_in_flight,
+            preview_max_fps,
+            preview_max_size,
+            preview_quality,
+            object_info_ttl,
         }
     }
 
@@ -76,3 +168,35 @@ impl Config {
         format!("{}:{}", self.host, self.port)
     }
 }
+
+/// Parse `COMFYUI_INSTANCES`: comma separated `name=url` entries, the name being optional
+fn parse_instances(list: &str) -> Vec<InstanceConfig> {
+    let mut instances: Vec<InstanceConfig> = Vec::new();
+    for (index, entry) in list
+        .split(',')
+        .map(str::trim)
+        .filter(|e| !e.is_empty())
+        .enumerate()
+    {
+        let (name, url) = match entry.split_once('=') {
+            Some((name, url)) => (name.trim().to_string(), url.trim()),
+            None => (format!("comfyui{}", index + 1), entry),
+        };
+        if instances.iter().any(|i| i.name == name) {
+            panic!(
+                "COMFYUI_INSTANCES names must be unique, '{}' is repeated",
+                name
+            );
+        }
+        let url = if url.contains("://") {
+            url.to_string()
+        } else {
+            format!("http://{}", url)
+        };
+        instances.push(InstanceConfig { name, url });
+    }
+    if instances.is_empty() {
+        panic!("COMFYUI_INSTANCES must name at least one instance");
+    }
+    instances
+}
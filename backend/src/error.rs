@@ -3,6 +3,7 @@ use axum::{
     response::{IntoResponse, Response},
     Json,
 };
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use thiserror::Error;
 
@@ -19,6 +20,14 @@ pub enum AppError {
     #[error("Invalid request: {0}")]
     InvalidRequest(String),
 
+    /// Parameters ComfyUI would reject, one entry per offending field
+    #[error("Invalid parameters: {}", describe_fields(.0))]
+    Validation(Vec<FieldError>),
+
+    /// Model files a workflow needs that ComfyUI does not have
+    #[error("Model missing: {}", describe_fields(.0))]
+    MissingModels(Vec<FieldError>),
+
     #[error("Not found: {0}")]
     NotFound(String),
 
@@ -35,25 +44,85 @@ pub enum AppError {
     HttpClient(#[from] reqwest::Error),
 }
 
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        let (status, error_message) = match &self {
+impl AppError {
+    /// HTTP status and message the error is reported with
+    pub fn status(&self) -> (StatusCode, String) {
+        match self {
             AppError::ComfyUIConnection(msg) => (StatusCode::SERVICE_UNAVAILABLE, msg.clone()),
             AppError::ComfyUIApi(msg) => (StatusCode::BAD_GATEWAY, msg.clone()),
             AppError::InvalidRequest(msg) => (StatusCode::BAD_REQUEST, msg.clone()),
+            AppError::Validation(_) | AppError::MissingModels(_) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, self.to_string())
+            }
             AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg.clone()),
             AppError::Internal(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg.clone()),
             AppError::WebSocket(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg.clone()),
             AppError::Serialization(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
             AppError::HttpClient(e) => (StatusCode::BAD_GATEWAY, e.to_string()),
-        };
+        }
+    }
+
+    /// Per-field details of a validation error, empty for other errors
+    pub fn fields(&self) -> &[FieldError] {
+        match self {
+            AppError::Validation(fields) | AppError::MissingModels(fields) => fields,
+            _ => &[],
+        }
+    }
+}
+
+/// Why one request field was rejected
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FieldError {
+    /// Request field, dotted for nested ones (`hires.steps`)
+    pub field: String,
+    pub message: String,
+    /// Accepted values of an enumerated field
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multiple_of: Option<f64>,
+}
+
+impl FieldError {
+    pub fn new(field: &str) -> Self {
+        Self {
+            field: field.to_string(),
+            message: String::new(),
+            allowed: None,
+            min: None,
+            max: None,
+            multiple_of: None,
+        }
+    }
+}
+
+fn describe_fields(fields: &[FieldError]) -> String {
+    fields
+        .iter()
+        .map(|f| format!("{} {}", f.field, f.message))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let (status, error_message) = self.status();
 
         tracing::error!("API error: {} - {}", status, error_message);
 
-        let body = Json(json!({
+        let mut body = json!({
             "error": error_message,
             "status": status.as_u16()
-        }));
+        });
+        if !self.fields().is_empty() {
+            body["fields"] = json!(self.fields());
+        }
+        let body = Json(body);
 
         (status, body).into_response()
     }
@@ -0,0 +1,411 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::error::{AppError, AppResult};
+use crate::history::HistoryStore;
+use crate::models::{FrontendMessage, PreviewImage};
+use crate::preview::PreviewSettings;
+use crate::sweep::SweepManager;
+use crate::util::now_millis;
+
+/// Prompts whose owner is remembered; the oldest are forgotten first
+const MAX_OWNED_PROMPTS: usize = 10_000;
+
+/// Prompts a single WebSocket connection may subscribe to
+const MAX_SUBSCRIPTIONS: usize = 1_000;
+
+/// Previews buffered per client; a client falling further behind skips to the newest
+const PREVIEW_CAPACITY: usize = 4;
+
+/// Events buffered per client before it lags
+const EVENT_CAPACITY: usize = 100;
+
+/// Events kept for clients resuming with `Last-Event-ID`
+const EVENT_LOG_CAPACITY: usize = 1_000;
+
+/// Prompts whose latest preview can be fetched by URL
+const MAX_STORED_PREVIEWS: usize = 64;
+
+/// A serialized event and the WebSocket client it belongs to
+#[derive(Debug, Clone)]
+pub struct Outbound {
+    /// Position in the event log, 0 for previews which are not logged
+    pub id: u64,
+    /// Client that submitted the prompt, `None` for prompts without an owner
+    pub owner: Option<String>,
+    pub prompt_id: Option<String>,
+    pub json: String,
+    /// Previews only: the same event with an `image_url` instead of the inline image
+    pub linked_json: Option<String>,
+}
+
+impl Outbound {
+    pub fn new(message: &FrontendMessage, owner: Option<String>) -> Option<Self> {
+        serde_json::to_string(message).ok().map(|json| Self {
+            id: 0,
+            owner,
+            prompt_id: message.prompt_id().map(str::to_string),
+            json,
+            linked_json: None,
+        })
+    }
+
+    /// Whether a client should receive this event
+    ///
+    /// Events without a prompt reach everyone, those of a prompt only its owner and the
+    /// clients watching all.
+    pub fn is_for(&self, client_id: &str, watch_all: bool) -> bool {
+        self.prompt_id.is_none() || is_owner(self.owner.as_deref(), client_id, watch_all)
+    }
+}
+
+/// Whether a client sees a prompt owned by `owner`: ownerless prompts only go to the
+/// clients watching all
+pub fn is_owner(owner: Option<&str>, client_id: &str, watch_all: bool) -> bool {
+    watch_all || owner == Some(client_id)
+}
+
+/// Prompts a WebSocket connection subscribed to (`true`) or unsubscribed from (`false`),
+/// overriding the owner routing
+#[derive(Debug, Default)]
+pub struct Subscriptions {
+    overrides: HashMap<String, bool>,
+}
+
+impl Subscriptions {
+    pub fn subscribe(&mut self, prompt_id: &str) -> AppResult<()> {
+        self.set(prompt_id, true)
+    }
+
+    pub fn unsubscribe(&mut self, prompt_id: &str) -> AppResult<()> {
+        self.set(prompt_id, false)
+    }
+
+    fn set(&mut self, prompt_id: &str, subscribed: bool) -> AppResult<()> {
+        if !self.overrides.contains_key(prompt_id) && self.overrides.len() >= MAX_SUBSCRIPTIONS {
+            return Err(AppError::InvalidRequest(format!(
+                "At most {} subscriptions per connection",
+                MAX_SUBSCRIPTIONS
+            )));
+        }
+        self.overrides.insert(prompt_id.to_string(), subscribed);
+        Ok(())
+    }
+
+    /// Whether the connection should see the state of `prompt_id`, owned by `owner`
+    pub fn wants_prompt(
+        &self,
+        prompt_id: &str,
+        owner: Option<&str>,
+        client_id: &str,
+        watch_all: bool,
+    ) -> bool {
+        match self.overrides.get(prompt_id) {
+            Some(subscribed) => *subscribed,
+            None => is_owner(owner, client_id, watch_all),
+        }
+    }
+
+    /// Whether the connection should receive `event`
+    pub fn wants(&self, event: &Outbound, client_id: &str, watch_all: bool) -> bool {
+        match event
+            .prompt_id
+            .as_deref()
+            .and_then(|id| self.overrides.get(id))
+        {
+            Some(subscribed) => *subscribed,
+            None => event.is_for(client_id, watch_all),
+        }
+    }
+}
+
+#[derive(Default)]
+struct EventLog {
+    last_id: u64,
+    events: VecDeque<Outbound>,
+}
+
+/// Events a client resuming from an event id missed
+pub struct Resume {
+    pub rx: broadcast::Receiver<Outbound>,
+    /// Id of the last logged event, everything after it arrives on `rx`
+    pub last_id: u64,
+    /// Logged events after the requested id, `None` when some are no longer in the log
+    pub missed: Option<Vec<Outbound>>,
+}
+
+/// Broadcasts events to the connected clients, numbering and logging them for replay
+///
+/// Ids restart with every process, so they are only meaningful together with `epoch`.
+#[derive(Clone)]
+pub struct EventSender {
+    tx: broadcast::Sender<Outbound>,
+    epoch: Arc<str>,
+    log: Arc<Mutex<EventLog>>,
+}
+
+impl Default for EventSender {
+    fn default() -> Self {
+        Self {
+            tx: broadcast::channel(EVENT_CAPACITY).0,
+            epoch: Uuid::new_v4().simple().to_string()[..8].into(),
+            log: Arc::new(Mutex::new(EventLog::default())),
+        }
+    }
+}
+
+impl EventSender {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Identifies this process in event ids handed to clients
+    pub fn epoch(&self) -> &str {
+        &self.epoch
+    }
+
+    pub fn send(&self, mut event: Outbound) {
+        // Sending under the lock keeps the channel in id order
+        let mut log = self.log.lock().unwrap_or_else(|e| e.into_inner());
+        log.last_id += 1;
+        event.id = log.last_id;
+        log.events.push_back(event.clone());
+        if log.events.len() > EVENT_LOG_CAPACITY {
+            log.events.pop_front();
+        }
+        let _ = self.tx.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Outbound> {
+        self.tx.subscribe()
+    }
+
+    /// Subscribe, collecting the logged events after `after` (`None` for a new client)
+    pub fn resume(&self, after: Option<u64>) -> Resume {
+        let log = self.log.lock().unwrap_or_else(|e| e.into_inner());
+        let oldest = log.events.front().map_or(log.last_id + 1, |e| e.id);
+        let missed = after
+            .filter(|after| (oldest.saturating_sub(1)..=log.last_id).contains(after))
+            .map(|after| {
+                log.events
+                    .iter()
+                    .filter(|e| e.id > after)
+                    .cloned()
+                    .collect()
+            });
+        Resume {
+            rx: self.tx.subscribe(),
+            last_id: log.last_id,
+            missed,
+        }
+    }
+}
+
+/// Latest preview of the prompts that sent one recently
+#[derive(Default)]
+struct StoredPreviews {
+    by_prompt: HashMap<String, PreviewImage>,
+    order: VecDeque<String>,
+}
+
+/// prompt_id -> client_id of the prompts submitted with a client_id
+#[derive(Default)]
+struct Owners {
+    by_prompt: HashMap<String, String>,
+    order: VecDeque<String>,
+}
+
+/// Applies generation events to the stores that track them and routes them to clients
+#[derive(Clone)]
+pub struct EventHub {
+    tx: EventSender,
+    /// Previews travel apart so dropping them never costs a client other events
+    previews: broadcast::Sender<Outbound>,
+    preview_settings: PreviewSettings,
+    stored_previews: Arc<Mutex<StoredPreviews>>,
+    history: HistoryStore,
+    sweeps: SweepManager,
+    owners: Arc<Mutex<Owners>>,
+}
+
+impl EventHub {
+    pub fn new(
+        tx: EventSender,
+        history: HistoryStore,
+        sweeps: SweepManager,
+        preview_settings: PreviewSettings,
+    ) -> Self {
+        Self {
+            tx,
+            previews: broadcast::channel(PREVIEW_CAPACITY).0,
+            preview_settings,
+            stored_previews: Arc::new(Mutex::new(StoredPreviews::default())),
+            history,
+            sweeps,
+            owners: Arc::new(Mutex::new(Owners::default())),
+        }
+    }
+
+    pub fn subscribe_previews(&self) -> broadcast::Receiver<Outbound> {
+        self.previews.subscribe()
+    }
+
+    /// Latest preview of `prompt_id`, for clients receiving previews as URLs
+    pub fn latest_preview(&self, prompt_id: &str) -> Option<PreviewImage> {
+        let stored = self
+            .stored_previews
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        stored.by_prompt.get(prompt_id).cloned()
+    }
+
+    fn store_preview(&self, prompt_id: &str, preview: &PreviewImage) {
+        let mut stored = self
+            .stored_previews
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if stored
+            .by_prompt
+            .insert(prompt_id.to_string(), preview.clone())
+            .is_none()
+        {
+            stored.order.push_back(prompt_id.to_string());
+        }
+        while stored.order.len() > MAX_STORED_PREVIEWS {
+            if let Some(oldest) = stored.order.pop_front() {
+                stored.by_prompt.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn preview_settings(&self) -> &PreviewSettings {
+        &self.preview_settings
+    }
+
+    /// Send the events of `prompt_id` only to `client_id` (and clients watching everything)
+    pub fn claim(&self, prompt_id: &str, client_id: &str) {
+        let mut owners = self.owners.lock().unwrap_or_else(|e| e.into_inner());
+        if owners
+            .by_prompt
+            .insert(prompt_id.to_string(), client_id.to_string())
+            .is_none()
+        {
+            owners.order.push_back(prompt_id.to_string());
+        }
+        while owners.order.len() > MAX_OWNED_PROMPTS {
+            if let Some(oldest) = owners.order.pop_front() {
+                owners.by_prompt.remove(&oldest);
+            }
+        }
+    }
+
+    /// Client the events of `prompt_id` are routed to, `None` when only watchers get them
+    pub fn owner(&self, prompt_id: &str) -> Option<String> {
+        let owners = self.owners.lock().unwrap_or_else(|e| e.into_inner());
+        owners.by_prompt.get(prompt_id).cloned()
+    }
+
+    /// Record `message` in the history and sweeps, then send it to the clients it concerns
+    pub async fn publish(&self, message: FrontendMessage) {
+        self.dispatch(message, None).await;
+    }
+
+    /// Publish an event received from a ComfyUI instance, tagged with the instance name
+    ///
+    /// Events about a prompt we submitted to another instance are only forwarded, so the
+    /// same prompt_id on two instances cannot mix up their state.
+    pub async fn publish_from(&self, instance: &str, message: FrontendMessage) {
+        self.dispatch(message, Some(instance)).await;
+    }
+
+    /// Downscale a preview from a ComfyUI instance as configured, then publish it
+    pub async fn publish_preview(&self, instance: &str, prompt_id: String, preview: PreviewImage) {
+        // Skip the work while nobody watches
+        if self.previews.receiver_count() == 0 {
+            return;
+        }
+        let settings = self.preview_settings;
+        let Ok(preview) = tokio::task::spawn_blocking(move || settings.shrink(preview)).await
+        else {
+            return;
+        };
+        self.store_preview(&prompt_id, &preview);
+        let message = FrontendMessage::Preview {
+            prompt_id,
+            image_data: format!(
+                "data:{};base64,{}",
+                preview.mime_type,
+                BASE64.encode(&preview.data)
+            ),
+            node: preview.node_id,
+        };
+        self.dispatch(message, Some(instance)).await;
+    }
+
+    async fn dispatch(&self, mut message: FrontendMessage, instance: Option<&str>) {
+        // These change no state, skip the lookup for these frequent events
+        let record = match &message {
+            FrontendMessage::Progress { .. }
+            | FrontendMessage::Preview { .. }
+            | FrontendMessage::Executing { .. }
+            | FrontendMessage::Cached { .. } => None,
+            other => match other.prompt_id() {
+                Some(prompt_id) => self.history.get(prompt_id).await,
+                None => None,
+            },
+        };
+        let foreign = instance.is_some_and(|name| {
+            record
+                .as_ref()
+                .and_then(|r| r.instance.as_deref())
+                .is_some_and(|submitted_to| submitted_to != name)
+        });
+
+        if !foreign {
+            if let FrontendMessage::Completed { seed, .. } = &mut message {
+                *seed = record.and_then(|r| r.seed);
+            }
+            self.history.apply_event(&message).await;
+            self.sweeps.apply_event(&message).await;
+        }
+
+        let Ok(mut json) = serde_json::to_value(&message) else {
+            return;
+        };
+        if let (Some(name), Some(object)) = (instance, json.as_object_mut()) {
+            object.insert("instance".to_string(), name.into());
+        }
+        // Events of prompts submitted elsewhere only reach the clients watching all, not the
+        // owner of our prompt
+        let owner = match message.prompt_id() {
+            Some(prompt_id) if !foreign => self.owner(prompt_id),
+            _ => None,
+        };
+        let mut event = Outbound {
+            id: 0,
+            owner,
+            prompt_id: message.prompt_id().map(str::to_string),
+            json: json.to_string(),
+            linked_json: None,
+        };
+        match &message {
+            FrontendMessage::Preview { prompt_id, .. } => {
+                if let Some(object) = json.as_object_mut() {
+                    object.remove("image_data");
+                    object.insert("image_url".to_string(), preview_url(prompt_id).into());
+                }
+                event.linked_json = Some(json.to_string());
+                let _ = self.previews.send(event);
+            }
+            _ => self.tx.send(event),
+        }
+    }
+}
+
+/// URL serving the latest preview of a prompt; the query string defeats caching
+fn preview_url(prompt_id: &str) -> String {
+    format!("/api/previews/{}?t={}", prompt_id, now_millis())
+}
@@ -0,0 +1,401 @@
+use ab_glyph::{point, Font, FontArc, GlyphId, PxScale, ScaleFont};
+use image::{imageops, DynamicImage, ImageFormat, Rgb, RgbImage};
+use std::io::Cursor;
+
+use crate::error::{AppError, AppResult};
+
+/// Font bundled for labels, used for every glyph a custom font lacks
+const BUNDLED_FONT: &[u8] = include_bytes!("../assets/fonts/DejaVuSansMono.ttf");
+
+/// Cells are scaled down so the images of a sheet stay within this many pixels
+const MAX_SHEET_PIXELS: f64 = 64_000_000.0;
+
+/// Longest side of a sheet, before labels
+const MAX_SHEET_SIDE: f64 = 16_000.0;
+
+/// Labels longer than this many lines are cut off with an ellipsis
+const MAX_LABEL_LINES: usize = 3;
+
+const BACKGROUND: Rgb<u8> = Rgb([255, 255, 255]);
+const MISSING_CELL: Rgb<u8> = Rgb([224, 224, 224]);
+const TEXT_COLOR: Rgb<u8> = Rgb([0, 0, 0]);
+
+// ============================================================================
+// Fonts
+// ============================================================================
+
+/// Fonts used to draw labels, tried in order for every character
+pub struct LabelFont {
+    fonts: Vec<FontArc>,
+}
+
+impl LabelFont {
+    /// Load `path` (e.g. a CJK font for Chinese prompt labels) in front of the bundled font
+    pub fn load(path: Option<&str>) -> Self {
+        let mut fonts = Vec::new();
+        if let Some(path) = path {
+            match std::fs::read(path)
+                .map_err(|e| e.to_string())
+                .and_then(|data| FontArc::try_from_vec(data).map_err(|e| e.to_string()))
+            {
+                Ok(font) => fonts.push(font),
+                Err(e) => tracing::warn!("Cannot load label font {}: {}", path, e),
+            }
+        }
+        fonts
+            .push(FontArc::try_from_slice(BUNDLED_FONT).expect("Bundled label font must be valid"));
+        Self { fonts }
+    }
+
+    /// The first font with a glyph for `c`, else the bundled font
+    fn font_for(&self, c: char) -> (usize, &FontArc, GlyphId) {
+        self.fonts
+            .iter()
+            .enumerate()
+            .map(|(index, font)| (index, font, font.glyph_id(c)))
+            .find(|(_, _, id)| id.0 != 0)
+            .unwrap_or_else(|| {
+                let index = self.fonts.len() - 1;
+                let font = &self.fonts[index];
+                (index, font, font.glyph_id(c))
+            })
+    }
+
+    fn ascent(&self, size: f32) -> f32 {
+        self.fonts[self.fonts.len() - 1]
+            .as_scaled(PxScale::from(size))
+            .ascent()
+    }
+
+    /// Width of `text` in pixels
+    fn text_width(&self, text: &str, size: f32) -> f32 {
+        let mut width = 0.0;
+        let mut prev: Option<(usize, GlyphId)> = None;
+        for c in text.chars() {
+            let (index, font, id) = self.font_for(c);
+            let scaled = font.as_scaled(PxScale::from(size));
+            if let Some((_, prev_id)) = prev.filter(|(i, _)| *i == index) {
+                width += scaled.kern(prev_id, id);
+            }
+            width += scaled.h_advance(id);
+            prev = Some((index, id));
+        }
+        width
+    }
+
+    /// Draw one line of text with its top-left corner at (x, y)
+    fn draw(&self, canvas: &mut RgbImage, text: &str, x: f32, y: f32, size: f32) {
+        let baseline = y + self.ascent(size);
+        let mut caret = x;
+        let mut prev: Option<(usize, GlyphId)> = None;
+        for c in text.chars() {
+            let (index, font, id) = self.font_for(c);
+            let scaled = font.as_scaled(PxScale::from(size));
+            if let Some((_, prev_id)) = prev.filter(|(i, _)| *i == index) {
+                caret += scaled.kern(prev_id, id);
+            }
+            let glyph = id.with_scale_and_position(size, point(caret, baseline));
+            if let Some(outlined) = font.outline_glyph(glyph) {
+                let bounds = outlined.px_bounds();
+                outlined.draw(|gx, gy, coverage| {
+                    let px = bounds.min.x as i64 + gx as i64;
+                    let py = bounds.min.y as i64 + gy as i64;
+                    if px < 0
+                        || py < 0
+                        || px >= canvas.width() as i64
+                        || py >= canvas.height() as i64
+                    {
+                        return;
+                    }
+                    let pixel = canvas.get_pixel_mut(px as u32, py as u32);
+                    for channel in 0..3 {
+                        let base = pixel.0[channel] as f32;
+                        let text = TEXT_COLOR.0[channel] as f32;
+                        pixel.0[channel] = (base + (text - base) * coverage.min(1.0)).round() as u8;
+                    }
+                });
+            }
+            caret += scaled.h_advance(id);
+            prev = Some((index, id));
+        }
+    }
+
+    /// Break `text` into lines no wider than `max_width`
+    fn wrap(&self, text: &str, size: f32, max_width: f32) -> Vec<String> {
+        let mut lines: Vec<String> = Vec::new();
+        let mut line = String::new();
+
+        for word in text.split_whitespace() {
+            let candidate = if line.is_empty() {
+                word.to_string()
+            } else {
+                format!("{} {}", line, word)
+            };
+            if self.text_width(&candidate, size) <= max_width {
+                line = candidate;
+                continue;
+            }
+            if !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+            // Words wider than a line are broken between characters
+            for c in word.chars() {
+                line.push(c);
+                if self.text_width(&line, size) > max_width && line.chars().count() > 1 {
+                    line.pop();
+                    lines.push(std::mem::take(&mut line));
+                    line.push(c);
+                }
+            }
+        }
+        if !line.is_empty() {
+            lines.push(line);
+        }
+
+        if lines.len() > MAX_LABEL_LINES {
+            lines.truncate(MAX_LABEL_LINES);
+            let last = &mut lines[MAX_LABEL_LINES - 1];
+            while !last.is_empty() && self.text_width(&format!("{}…", last), size) > max_width {
+                last.pop();
+            }
+            last.push('…');
+        }
+        lines
+    }
+}
+
+// ============================================================================
+// Contact Sheet
+// ============================================================================
+
+/// Images and labels of a sweep, with cells ordered x fastest, then y, then z
+pub struct ContactSheet<'a> {
+    pub x_labels: &'a [String],
+    pub y_labels: &'a [String],
+    pub z_labels: &'a [String],
+    /// One entry per combination, `None` for cells without an image
+    pub cells: Vec<Option<DynamicImage>>,
+}
+
+/// Render a labelled grid PNG: one block per z value, columns for x and rows for y
+pub fn render_contact_sheet(font: &LabelFont, sheet: &ContactSheet) -> AppResult<Vec<u8>> {
+    let columns = sheet.x_labels.len().max(1);
+    let rows = sheet.y_labels.len().max(1);
+    let blocks = sheet.z_labels.len().max(1);
+    if sheet.cells.len() != columns * rows * blocks {
+        return Err(AppError::Internal(format!(
+            "Contact sheet has {} cells, expected {}",
+            sheet.cells.len(),
+            columns * rows * blocks
+        )));
+    }
+
+    let first = sheet
+        .cells
+        .iter()
+        .flatten()
+        .next()
+        .ok_or_else(|| AppError::Internal("Contact sheet has no images".to_string()))?;
+
+    // Size every cell like the first image, shrunk to keep the sheet reasonable
+    let (width, height) = (first.width() as f64, first.height() as f64);
+    let total_width = width * columns as f64;
+    let total_height = height * (rows * blocks) as f64;
+    let scale = 1f64
+        .min((MAX_SHEET_PIXELS / (total_width * total_height)).sqrt())
+        .min(MAX_SHEET_SIDE / total_width)
+        .min(MAX_SHEET_SIDE / total_height);
+    let cell_w = ((width * scale) as u32).max(1);
+    let cell_h = ((height * scale) as u32).max(1);
+
+    let size = (cell_w as f32 / 20.0).clamp(14.0, 48.0);
+    let line_height = (size * 1.25).ceil();
+    let padding = (size / 2.0).ceil();
+
+    // Header above each block of columns
+    let x_lines: Vec<Vec<String>> = sheet
+        .x_labels
+        .iter()
+        .map(|label| font.wrap(label, size, cell_w as f32 - 2.0 * padding))
+        .collect();
+    let header_h = label_height(&x_lines, line_height, padding);
+
+    // Column left of the rows, as wide as the longest label up to one cell
+    let label_w = sheet
+        .y_labels
+        .iter()
+        .map(|label| font.text_width(label, size))
+        .fold(0.0f32, f32::max);
+    let left_w = if label_w > 0.0 {
+        (label_w + 2.0 * padding).min(cell_w as f32).ceil() as u32
+    } else {
+        0
+    };
+    let y_lines: Vec<Vec<String>> = sheet
+        .y_labels
+        .iter()
+        .map(|label| font.wrap(label, size, left_w as f32 - 2.0 * padding))
+        .collect();
+
+    let title_h = if sheet.z_labels.is_empty() {
+        0
+    } else {
+        (line_height + 2.0 * padding) as u32
+    };
+    let block_h = title_h + header_h + cell_h * rows as u32;
+    let canvas_w = left_w + cell_w * columns as u32;
+    let canvas_h = block_h * blocks as u32;
+    let mut canvas = RgbImage::from_pixel(canvas_w, canvas_h, BACKGROUND);
+
+    for block in 0..blocks {
+        let top = block_h * block as u32;
+
+        if let Some(title) = sheet.z_labels.get(block) {
+            let line = font.wrap(title, size, canvas_w as f32 - 2.0 * padding);
+            if let Some(line) = line.first() {
+                font.draw(&mut canvas, line, padding, top as f32 + padding, size);
+            }
+        }
+
+        for (column, lines) in x_lines.iter().enumerate() {
+            let left = left_w + cell_w * column as u32;
+            draw_centered(
+                font,
+                &mut canvas,
+                lines,
+                (left, top + title_h, cell_w, header_h),
+                size,
+                line_height,
+            );
+        }
+
+        for row in 0..rows {
+            let row_top = top + title_h + header_h + cell_h * row as u32;
+            if let Some(lines) = y_lines.get(row) {
+                draw_centered(
+                    font,
+                    &mut canvas,
+                    lines,
+                    (0, row_top, left_w, cell_h),
+                    size,
+                    line_height,
+                );
+            }
+
+            for column in 0..columns {
+                let left = left_w + cell_w * column as u32;
+                let index = (block * rows + row) * columns + column;
+                match &sheet.cells[index] {
+                    Some(image) => {
+                        let fitted = image
+                            .resize(cell_w, cell_h, imageops::FilterType::Triangle)
+                            .to_rgb8();
+                        let x = left + (cell_w - fitted.width()) / 2;
+                        let y = row_top + (cell_h - fitted.height()) / 2;
+                        imageops::overlay(&mut canvas, &fitted, x as i64, y as i64);
+                    }
+                    None => {
+                        let placeholder = RgbImage::from_pixel(cell_w, cell_h, MISSING_CELL);
+                        imageops::overlay(&mut canvas, &placeholder, left as i64, row_top as i64);
+                        draw_centered(
+                            font,
+                            &mut canvas,
+                            &["missing".to_string()],
+                            (left, row_top, cell_w, cell_h),
+                            size,
+                            line_height,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    let mut png = Vec::new();
+    DynamicImage::ImageRgb8(canvas)
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(|e| AppError::Internal(format!("Failed to encode contact sheet: {}", e)))?;
+    Ok(png)
+}
+
+/// Height of a header row holding the tallest of the wrapped labels
+fn label_height(labels: &[Vec<String>], line_height: f32, padding: f32) -> u32 {
+    let lines = labels.iter().map(Vec::len).max().unwrap_or(0);
+    if lines == 0 {
+        0
+    } else {
+        (lines as f32 * line_height + 2.0 * padding).ceil() as u32
+    }
+}
+
+/// Draw lines centered in the (x, y, width, height) box
+fn draw_centered(
+    font: &LabelFont,
+    canvas: &mut RgbImage,
+    lines: &[String],
+    (x, y, width, height): (u32, u32, u32, u32),
+    size: f32,
+    line_height: f32,
+) {
+    let block = lines.len() as f32 * line_height;
+    let mut top = y as f32 + (height as f32 - block) / 2.0 + (line_height - size) / 2.0;
+    for line in lines {
+        let left = x as f32 + (width as f32 - font.text_width(line, size)) / 2.0;
+        font.draw(canvas, line, left.max(x as f32), top, size);
+        top += line_height;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(color: [u8; 3]) -> Option<DynamicImage> {
+        Some(DynamicImage::ImageRgb8(RgbImage::from_pixel(
+            64,
+            64,
+            Rgb(color),
+        )))
+    }
+
+    #[test]
+    fn cells_fill_rows_left_to_right() {
+        let labels = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        let (x_labels, y_labels) = (labels(&["a", "b"]), labels(&["1", "2"]));
+        let sheet = ContactSheet {
+            x_labels: &x_labels,
+            y_labels: &y_labels,
+            z_labels: &[],
+            cells: vec![
+                solid([255, 0, 0]),
+                solid([0, 255, 0]),
+                solid([0, 0, 255]),
+                None,
+            ],
+        };
+        let png = render_contact_sheet(&LabelFont::load(None), &sheet).unwrap();
+        let canvas = image::load_from_memory(&png).unwrap().to_rgb8();
+
+        // Labels take the left column and the top row, the cells keep their size
+        let left = canvas.width() - 128;
+        let top = canvas.height() - 128;
+        let corner =
+            |column: u32, row: u32| *canvas.get_pixel(left + 64 * column + 2, top + 64 * row + 2);
+        assert_eq!(corner(0, 0), Rgb([255, 0, 0]));
+        assert_eq!(corner(1, 0), Rgb([0, 255, 0]));
+        assert_eq!(corner(0, 1), Rgb([0, 0, 255]));
+        assert_eq!(corner(1, 1), MISSING_CELL);
+    }
+
+    #[test]
+    fn cell_count_must_match_the_labels() {
+        let x_labels = vec!["a".to_string(), "b".to_string()];
+        let sheet = ContactSheet {
+            x_labels: &x_labels,
+            y_labels: &[],
+            z_labels: &[],
+            cells: vec![solid([0, 0, 0])],
+        };
+        assert!(render_contact_sheet(&LabelFont::load(None), &sheet).is_err());
+    }
+}
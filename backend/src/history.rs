@@ -0,0 +1,575 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc};
+use tokio::sync::Mutex;
+
+use crate::error::{AppError, AppResult};
+use crate::models::{FrontendMessage, ImageResult, ResolvedModels};
+use crate::util::now_millis;
+
+/// Rewrite the log on startup once it holds this many superseded lines
+const COMPACT_THRESHOLD: usize = 1000;
+
+// ============================================================================
+// Records
+// ============================================================================
+
+/// Lifecycle of a generation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GenerationStatus {
+    Queued,
+    Running,
+    Completed,
+    Error,
+    Cancelled,
+    /// Stopped by an interrupt not sent through the job queue
+    Interrupted,
+}
+
+/// One generation submitted through this backend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenerationRecord {
+    /// ComfyUI prompt_id
+    pub id: String,
+    /// Endpoint that created it (txt2img, img2img, inpaint, upscale, sweep)
+    pub kind: String,
+    /// The request as submitted by the client
+    pub request: serde_json::Value,
+    /// Seed actually used (random seeds resolved)
+    pub seed: Option<u64>,
+    /// Model files the workflow was built with
+    pub models: Option<ResolvedModels>,
+    pub status: GenerationStatus,
+    /// Unix timestamps in milliseconds
+    pub created_at: u64,
+    pub started_at: Option<u64>,
+    pub completed_at: Option<u64>,
+    #[serde(default)]
+    pub images: Vec<ImageResult>,
+    #[serde(default)]
+    pub error: Option<String>,
+    /// ComfyUI instance the generation was submitted to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+}
+
+impl GenerationRecord {
+    /// New queued record
+    pub fn new(
+        id: String,
+        kind: &str,
+        request: serde_json::Value,
+        seed: Option<u64>,
+        models: Option<ResolvedModels>,
+    ) -> Self {
+        Self {
+            id,
+            kind: kind.to_string(),
+            request,
+            seed,
+            models,
+            status: GenerationStatus::Queued,
+            created_at: now_millis(),
+            started_at: None,
+            completed_at: None,
+            images: Vec::new(),
+            error: None,
+            instance: None,
+        }
+    }
+
+    /// Case-insensitive match against the prompt texts and output filenames
+    fn matches(&self, query: &str) -> bool {
+        let text_matches = |key: &str| {
+            self.request
+                .get(key)
+                .and_then(|v| v.as_str())
+                .is_some_and(|v| v.to_lowercase().contains(query))
+        };
+        text_matches("prompt")
+            || text_matches("negative_prompt")
+            || text_matches("filename")
+            || self.id.contains(query)
+            || self
+                .images
+                .iter()
+                .any(|img| img.filename.to_lowercase().contains(query))
+    }
+}
+
+/// A line of the history log: a full record or a deletion
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum LogEntry {
+    Deleted { deleted: String },
+    Record(Box<GenerationRecord>),
+}
+
+/// Filters for listing history
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HistoryQuery {
+    /// 1-based page number
+    pub page: Option<usize>,
+    pub per_page: Option<usize>,
+    /// Text search over prompts and filenames
+    pub q: Option<String>,
+    pub status: Option<GenerationStatus>,
+    pub kind: Option<String>,
+}
+
+/// A page of history records, newest first
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryPage {
+    pub items: Vec<GenerationRecord>,
+    pub total: usize,
+    pub page: usize,
+    pub per_page: usize,
+}
+
+// ============================================================================
+// Store
+// ============================================================================
+
+struct HistoryInner {
+    /// Records in submission order
+    records: Vec<GenerationRecord>,
+    /// Position of each record in `records` by id
+    index: HashMap<String, usize>,
+    /// Lines for the writer thread, `None` when the log cannot be written
+    log: Option<mpsc::Sender<String>>,
+}
+
+impl HistoryInner {
+    fn new(records: Vec<GenerationRecord>, log: Option<mpsc::Sender<String>>) -> Self {
+        let mut inner = Self {
+            records,
+            index: HashMap::new(),
+            log,
+        };
+        inner.reindex();
+        inner
+    }
+
+    fn reindex(&mut self) {
+        self.index = self
+            .records
+            .iter()
+            .enumerate()
+            .map(|(i, r)| (r.id.clone(), i))
+            .collect();
+    }
+
+    fn get(&self, id: &str) -> Option<&GenerationRecord> {
+        self.index.get(id).map(|&i| &self.records[i])
+    }
+
+    /// Apply `f` to the record with `id`, returning the updated copy
+    fn update(
+        &mut self,
+        id: &str,
+        f: impl FnOnce(&mut GenerationRecord),
+    ) -> Option<GenerationRecord> {
+        let record = &mut self.records[*self.index.get(id)?];
+        f(record);
+        Some(record.clone())
+    }
+}
+
+/// Persistent generation history kept in an append-only JSONL file
+#[derive(Clone)]
+pub struct HistoryStore {
+    path: PathBuf,
+    inner: Arc<Mutex<HistoryInner>>,
+}
+
+impl HistoryStore {
+    /// Open (or create) the history log at `path` and replay it
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let (records, superseded) = replay(&path);
+
+        if superseded >= COMPACT_THRESHOLD {
+            if let Err(e) = write_snapshot(&path, &records) {
+                tracing::warn!("Failed to compact history {}: {}", path.display(), e);
+            } else {
+                tracing::info!("Compacted history log ({} stale lines)", superseded);
+            }
+        }
+
+        let log = open_append(&path)
+            .map_err(|e| tracing::warn!("History disabled, cannot open {}: {}", path.display(), e))
+            .ok()
+            .map(|file| spawn_writer(path.clone(), file));
+        tracing::info!(
+            "Loaded {} generation record(s) from {}",
+            records.len(),
+            path.display()
+        );
+
+        Self {
+            path,
+            inner: Arc::new(Mutex::new(HistoryInner::new(records, log))),
+        }
+    }
+
+    /// Add a newly queued generation
+    pub async fn insert(&self, record: GenerationRecord) {
+        let mut inner = self.inner.lock().await;
+        self.append(&mut inner, &LogEntry::Record(Box::new(record.clone())));
+        let position = inner.records.len();
+        inner.index.entry(record.id.clone()).or_insert(position);
+        inner.records.push(record);
+    }
+
+    /// Get a record by id
+    pub async fn get(&self, id: &str) -> Option<GenerationRecord> {
+        let inner = self.inner.lock().await;
+        inner.get(id).cloned()
+    }
+
+    /// Most recent record that produced the given output image
+    ///
+    /// With `instance`, records made on another instance are skipped: instances number their
+    /// outputs independently, so the same name may belong to several records.
+    pub async fn find_by_image(
+        &self,
+        filename: &str,
+        subfolder: &str,
+        instance: Option<&str>,
+    ) -> Option<GenerationRecord> {
+        let inner = self.inner.lock().await;
+        inner
+            .records
+            .iter()
+            .rev()
+            .filter(|r| {
+                instance.is_none() || r.instance.is_none() || r.instance.as_deref() == instance
+            })
+            .find(|r| {
+                r.images
+                    .iter()
+                    .any(|img| img.filename == filename && img.subfolder == subfolder)
+            })
+            .cloned()
+    }
+
+    /// Delete a record by id
+    pub async fn delete(&self, id: &str) -> AppResult<()> {
+        let mut inner = self.inner.lock().await;
+        let index = *inner
+            .index
+            .get(id)
+            .ok_or_else(|| AppError::NotFound(format!("Generation {} not found", id)))?;
+        inner.records.remove(index);
+        inner.reindex();
+        self.append(
+            &mut inner,
+            &LogEntry::Deleted {
+                deleted: id.to_string(),
+            },
+        );
+        Ok(())
+    }
+
+    /// List records newest first with filtering and pagination
+    pub async fn list(&self, query: &HistoryQuery) -> HistoryPage {
+        let per_page = query.per_page.unwrap_or(20).clamp(1, 200);
+        let page = query.page.unwrap_or(1).max(1);
+        let text = query
+            .q
+            .as_deref()
+            .map(|q| q.trim().to_lowercase())
+            .filter(|q| !q.is_empty());
+
+        let inner = self.inner.lock().await;
+        let matching: Vec<&GenerationRecord> = inner
+            .records
+            .iter()
+            .rev()
+            .filter(|r| query.status.is_none_or(|s| r.status == s))
+            .filter(|r| query.kind.as_deref().is_none_or(|k| r.kind == k))
+            .filter(|r| text.as_deref().is_none_or(|q| r.matches(q)))
+            .collect();
+
+        HistoryPage {
+            total: matching.len(),
+            items: matching
+                .into_iter()
+                .skip((page - 1) * per_page)
+                .take(per_page)
+                .cloned()
+                .collect(),
+            page,
+            per_page,
+        }
+    }
+
+    /// Update the record a frontend event refers to
+    pub async fn apply_event(&self, message: &FrontendMessage) {
+        let mut inner = self.inner.lock().await;
+        let updated = match message {
+            // Only a job moved off a failed instance goes back to the queue
+            FrontendMessage::Queued { prompt_id, .. }
+                if inner.get(prompt_id).is_some_and(|r| r.instance.is_some()) =>
+            {
+                inner.update(prompt_id, |r| {
+                    r.status = GenerationStatus::Queued;
+                    r.started_at = None;
+                    r.instance = None;
+                })
+            }
+            FrontendMessage::Submitted {
+                prompt_id,
+                instance,
+            } => inner.update(prompt_id, |r| {
+                r.instance = Some(instance.clone());
+            }),
+            FrontendMessage::Started { prompt_id } => inner.update(prompt_id, |r| {
+                r.status = GenerationStatus::Running;
+                r.started_at = Some(now_millis());
+            }),
+            // `completed` is sent for every output node, only `success` ends the prompt
+            FrontendMessage::Completed {
+                prompt_id, images, ..
+            } => inner.update(prompt_id, |r| {
+                for image in images {
+                    if !r.images.iter().any(|i| i.filename == image.filename) {
+                        r.images.push(image.clone());
+                    }
+                }
+            }),
+            FrontendMessage::Success { prompt_id }
+                if inner.get(prompt_id).is_some_and(|r| {
+                    matches!(
+                        r.status,
+                        GenerationStatus::Queued | GenerationStatus::Running
+                    )
+                }) =>
+            {
+                inner.update(prompt_id, |r| {
+                    r.status = GenerationStatus::Completed;
+                    r.completed_at = Some(now_millis());
+                })
+            }
+            FrontendMessage::Error {
+                prompt_id: Some(prompt_id),
+                message,
+                ..
+            } => inner.update(prompt_id, |r| {
+                r.status = GenerationStatus::Error;
+                r.completed_at = Some(now_millis());
+                r.error = Some(message.clone());
+            }),
+            FrontendMessage::Cancelled { prompt_id } => inner.update(prompt_id, |r| {
+                r.status = GenerationStatus::Cancelled;
+                r.completed_at = Some(now_millis());
+            }),
+            // A cancelled job is interrupted too, keep it cancelled
+            FrontendMessage::Interrupted { prompt_id, .. }
+                if inner.get(prompt_id).is_some_and(|r| {
+                    matches!(
+                        r.status,
+                        GenerationStatus::Queued | GenerationStatus::Running
+                    )
+                }) =>
+            {
+                inner.update(prompt_id, |r| {
+                    r.status = GenerationStatus::Interrupted;
+                    r.completed_at = Some(now_millis());
+                })
+            }
+            _ => None,
+        };
+
+        if let Some(record) = updated {
+            self.append(&mut inner, &LogEntry::Record(Box::new(record)));
+        }
+    }
+
+    /// Queue a line for the writer thread; sent under the lock, so lines keep their order
+    fn append(&self, inner: &mut HistoryInner, entry: &LogEntry) {
+        let Some(log) = inner.log.as_ref() else {
+            return;
+        };
+        match serde_json::to_string(entry) {
+            Ok(line) => {
+                if log.send(line).is_err() {
+                    tracing::error!("History writer for {} stopped", self.path.display());
+                }
+            }
+            Err(e) => tracing::error!("Failed to serialize history entry: {}", e),
+        }
+    }
+}
+
+/// Append the lines sent on the returned channel to `file` from a dedicated thread, so the
+/// store's lock is never held across disk writes
+fn spawn_writer(path: PathBuf, file: File) -> mpsc::Sender<String> {
+    let (tx, rx) = mpsc::channel::<String>();
+    std::thread::spawn(move || {
+        let mut writer = BufWriter::new(file);
+        // Write whatever piled up, flushing once the channel is drained
+        while let Ok(line) = rx.recv() {
+            let result = std::iter::once(line)
+                .chain(rx.try_iter())
+                .try_for_each(|line| writeln!(writer, "{}", line))
+                .and_then(|()| writer.flush());
+            if let Err(e) = result {
+                tracing::error!("Failed to write history {}: {}", path.display(), e);
+            }
+        }
+    });
+    tx
+}
+
+/// Read the log, returning live records and the number of superseded lines
+fn replay(path: &PathBuf) -> (Vec<GenerationRecord>, usize) {
+    // Deleted records leave a hole so the positions in `index` stay valid
+    let mut slots: Vec<Option<GenerationRecord>> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    let mut superseded = 0;
+
+    let Ok(file) = File::open(path) else {
+        return (Vec::new(), 0);
+    };
+
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let Ok(line) = line else { break };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<LogEntry>(&line) {
+            Ok(LogEntry::Record(record)) => match index.get(&record.id) {
+                Some(&i) => {
+                    slots[i] = Some(*record);
+                    superseded += 1;
+                }
+                None => {
+                    index.insert(record.id.clone(), slots.len());
+                    slots.push(Some(*record));
+                }
+            },
+            Ok(LogEntry::Deleted { deleted }) => {
+                if let Some(i) = index.remove(&deleted) {
+                    slots[i] = None;
+                }
+                superseded += 2;
+            }
+            Err(e) => {
+                tracing::warn!("Skipping history line {}: {}", number + 1, e);
+                superseded += 1;
+            }
+        }
+    }
+
+    (slots.into_iter().flatten().collect(), superseded)
+}
+
+/// Atomically replace the log with one line per live record
+fn write_snapshot(path: &PathBuf, records: &[GenerationRecord]) -> std::io::Result<()> {
+    let tmp = path.with_extension("jsonl.tmp");
+    {
+        let mut file = File::create(&tmp)?;
+        for record in records {
+            let line = serde_json::to_string(record).map_err(std::io::Error::other)?;
+            writeln!(file, "{}", line)?;
+        }
+        file.sync_all()?;
+    }
+    std::fs::rename(tmp, path)
+}
+
+fn open_append(path: &PathBuf) -> std::io::Result<File> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(id: &str, status: GenerationStatus) -> GenerationRecord {
+        GenerationRecord {
+            status,
+            ..GenerationRecord::new(id.to_string(), "txt2img", serde_json::json!({}), None, None)
+        }
+    }
+
+    #[test]
+    fn replay_keeps_the_latest_line_of_each_record() {
+        let path = std::env::temp_dir().join(format!("history-{}.jsonl", uuid::Uuid::new_v4()));
+        let entries = [
+            LogEntry::Record(Box::new(record("a", GenerationStatus::Queued))),
+            LogEntry::Record(Box::new(record("b", GenerationStatus::Queued))),
+            LogEntry::Record(Box::new(record("a", GenerationStatus::Completed))),
+            LogEntry::Record(Box::new(record("c", GenerationStatus::Queued))),
+            LogEntry::Deleted {
+                deleted: "b".to_string(),
+            },
+            LogEntry::Record(Box::new(record("b", GenerationStatus::Error))),
+        ];
+        let log: String = entries
+            .iter()
+            .map(|entry| serde_json::to_string(entry).unwrap() + "\n")
+            .collect();
+        std::fs::write(&path, log + "not json\n").unwrap();
+
+        let (records, superseded) = replay(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let ids: Vec<(&str, GenerationStatus)> =
+            records.iter().map(|r| (r.id.as_str(), r.status)).collect();
+        // A deleted record that comes back moves to the end
+        assert_eq!(
+            ids,
+            [
+                ("a", GenerationStatus::Completed),
+                ("c", GenerationStatus::Queued),
+                ("b", GenerationStatus::Error),
+            ]
+        );
+        assert_eq!(superseded, 4);
+
+        let mut inner = HistoryInner::new(records, None);
+        assert_eq!(inner.get("c").unwrap().status, GenerationStatus::Queued);
+        inner.update("b", |r| r.status = GenerationStatus::Completed);
+        assert_eq!(inner.get("b").unwrap().status, GenerationStatus::Completed);
+        assert!(inner.get("missing").is_none());
+    }
+
+    #[tokio::test]
+    async fn writes_reach_the_log_in_order() {
+        let path = std::env::temp_dir().join(format!("history-{}.jsonl", uuid::Uuid::new_v4()));
+        let store = HistoryStore::open(&path);
+        store.insert(record("a", GenerationStatus::Queued)).await;
+        store.insert(record("b", GenerationStatus::Queued)).await;
+        store
+            .apply_event(&FrontendMessage::Started {
+                prompt_id: "a".to_string(),
+            })
+            .await;
+        store.delete("b").await.unwrap();
+        drop(store);
+
+        // The writer thread finishes on its own time
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        let records = loop {
+            let (records, superseded) = replay(&path);
+            if superseded == 3 || std::time::Instant::now() > deadline {
+                break records;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        };
+        std::fs::remove_file(&path).unwrap();
+
+        let ids: Vec<(&str, GenerationStatus)> =
+            records.iter().map(|r| (r.id.as_str(), r.status)).collect();
+        assert_eq!(ids, [("a", GenerationStatus::Running)]);
+    }
+}
@@ -0,0 +1,1010 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, Notify};
+
+use crate::error::{AppError, AppResult};
+use crate::events::EventHub;
+use crate::models::FrontendMessage;
+use crate::pool::{ComfyUIInstance, ComfyUIPool};
+use crate::util::now_millis;
+
+/// Finished jobs kept for `/api/jobs`
+const MAX_FINISHED_JOBS: usize = 200;
+
+/// How often instances are probed and submitted jobs checked against their queues
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Submitted jobs younger than this are not looked for in ComfyUI's queue yet
+const SUBMIT_GRACE_MS: u64 = 2000;
+
+// ============================================================================
+// Jobs
+// ============================================================================
+
+/// Scheduling priority; higher priorities are submitted first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// Lifecycle of a job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    /// Waiting in the backend queue
+    Pending,
+    /// Handed to ComfyUI, waiting in its queue
+    Submitted,
+    Running,
+    Completed,
+    Error,
+    Cancelled,
+    /// Stopped by an interrupt not sent through the queue
+    Interrupted,
+}
+
+impl JobStatus {
+    pub fn is_finished(self) -> bool {
+        matches!(
+            self,
+            JobStatus::Completed | JobStatus::Error | JobStatus::Cancelled | JobStatus::Interrupted
+        )
+    }
+}
+
+/// A workflow waiting for, or given to, ComfyUI
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    /// Job id, also used as the ComfyUI prompt_id
+    pub id: String,
+    /// Endpoint that created it (txt2img, img2img, inpaint, upscale, sweep)
+    pub kind: String,
+    pub priority: JobPriority,
+    pub status: JobStatus,
+    /// 1-based position among pending jobs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<usize>,
+    /// Unix timestamps in milliseconds
+    pub created_at: u64,
+    pub submitted_at: Option<u64>,
+    pub finished_at: Option<u64>,
+    pub error: Option<String>,
+    /// ComfyUI instance the job was submitted to
+    pub instance: Option<String>,
+    /// Instances able to run the job (holding its uploads), any when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_instances: Vec<String>,
+    /// Latest sampler progress while running
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress: Option<JobProgress>,
+    /// Kept until the job finishes so it can move to another instance
+    #[serde(skip)]
+    workflow: Value,
+}
+
+/// Last `progress` event of a running job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobProgress {
+    pub node: String,
+    pub value: u32,
+    pub max: u32,
+    pub percentage: f32,
+}
+
+/// Snapshot of the job queue
+#[derive(Debug, Clone, Serialize)]
+pub struct JobList {
+    /// In submission order
+    pub pending: Vec<Job>,
+    /// Submitted to ComfyUI and not finished
+    pub active: Vec<Job>,
+    /// Most recently finished first
+    pub finished: Vec<Job>,
+}
+
+/// Where to move a pending job
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MoveTarget {
+    Up,
+    Down,
+    Top,
+    Bottom,
+}
+
+#[derive(Default)]
+struct JobInner {
+    pending: Vec<Job>,
+    active: Vec<Job>,
+    finished: VecDeque<Job>,
+    /// Prompts left on instances that went down, removed from them once they are back
+    abandoned: HashMap<String, Vec<String>>,
+}
+
+impl JobInner {
+    /// Insert behind every pending job of the same or higher priority
+    fn insert_pending(&mut self, job: Job) -> usize {
+        let index = self
+            .pending
+            .iter()
+            .position(|j| j.priority < job.priority)
+            .unwrap_or(self.pending.len());
+        self.pending.insert(index, job);
+        index + 1
+    }
+
+    fn pending_index(&self, id: &str) -> Option<usize> {
+        self.pending.iter().position(|j| j.id == id)
+    }
+
+    /// Move an active job to the finished list
+    fn finish(&mut self, id: &str, status: JobStatus, error: Option<String>) -> Option<Job> {
+        let index = self.active.iter().position(|j| j.id == id)?;
+        let job = self.active.remove(index);
+        Some(self.retire(job, status, error))
+    }
+
+    fn retire(&mut self, mut job: Job, status: JobStatus, error: Option<String>) -> Job {
+        job.status = status;
+        job.finished_at = Some(now_millis());
+        job.error = error;
+        job.progress = None;
+        job.workflow = Value::Null;
+        self.finished.push_front(job.clone());
+        self.finished.truncate(MAX_FINISHED_JOBS);
+        job
+    }
+
+    /// Our submitted jobs per instance
+    fn in_flight(&self) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        for instance in self.active.iter().filter_map(|j| j.instance.clone()) {
+            *counts.entry(instance).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    fn find(&self, id: &str) -> Option<Job> {
+        if let Some(index) = self.pending_index(id) {
+            let mut job = self.pending[index].clone();
+            job.position = Some(index + 1);
+            return Some(job);
+        }
+        self.active
+            .iter()
+            .chain(self.finished.iter())
+            .find(|j| j.id == id)
+            .cloned()
+    }
+}
+
+// ============================================================================
+// Queue
+// ============================================================================
+
+/// Holds jobs in the backend and submits them to ComfyUI instances as they free up
+///
+/// At most `max_in_flight` jobs are handed to each instance at a time, so priorities and
+/// reordering take effect for everything else. Jobs on an instance that goes down are moved
+/// back to the front of the queue.
+#[derive(Clone)]
+pub struct JobQueue {
+    pool: ComfyUIPool,
+    events: EventHub,
+    max_in_flight: usize,
+    inner: Arc<Mutex<JobInner>>,
+    wake: Arc<Notify>,
+}
+
+impl JobQueue {
+    pub fn new(pool: ComfyUIPool, events: EventHub, max_in_flight: usize) -> Self {
+        Self {
+            pool,
+            events,
+            max_in_flight: max_in_flight.max(1),
+            inner: Arc::new(Mutex::new(JobInner::default())),
+            wake: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Add a job under `id`, returning its position among pending jobs
+    ///
+    /// A non-empty `allowed_instances` restricts the job to those instances.
+    pub async fn enqueue(
+        &self,
+        id: &str,
+        kind: &str,
+        priority: JobPriority,
+        workflow: Value,
+        allowed_instances: Vec<String>,
+    ) -> usize {
+        let job = Job {
+            id: id.to_string(),
+            kind: kind.to_string(),
+            priority,
+            status: JobStatus::Pending,
+            position: None,
+            created_at: now_millis(),
+            submitted_at: None,
+            finished_at: None,
+            error: None,
+            instance: None,
+            allowed_instances,
+            progress: None,
+            workflow,
+        };
+        let position = self.inner.lock().await.insert_pending(job);
+        self.events
+            .publish(FrontendMessage::Queued {
+                prompt_id: id.to_string(),
+                queue_position: position as u32,
+            })
+            .await;
+        self.wake.notify_one();
+        position
+    }
+
+    pub async fn list(&self) -> JobList {
+        let inner = self.inner.lock().await;
+        JobList {
+            pending: inner
+                .pending
+                .iter()
+                .enumerate()
+                .map(|(index, job)| Job {
+                    position: Some(index + 1),
+                    ..job.clone()
+                })
+                .collect(),
+            active: inner.active.clone(),
+            finished: inner.finished.iter().cloned().collect(),
+        }
+    }
+
+    pub async fn get(&self, id: &str) -> Option<Job> {
+        self.inner.lock().await.find(id)
+    }
+
+    /// Number of jobs waiting in the backend
+    pub async fn pending_count(&self) -> usize {
+        self.inner.lock().await.pending.len()
+    }
+
+    /// Cancel a job: drop it when pending, delete it from its instance's queue when submitted
+    /// and interrupt it only when it is the prompt the instance is running
+    pub async fn cancel(&self, id: &str) -> AppResult<Job> {
+        let instance = {
+            let mut inner = self.inner.lock().await;
+            if let Some(index) = inner.pending_index(id) {
+                let job = inner.pending.remove(index);
+                let job = inner.retire(job, JobStatus::Cancelled, None);
+                drop(inner);
+                self.publish_cancelled(id).await;
+                return Ok(job);
+            }
+            match inner.active.iter().find(|j| j.id == id) {
+                Some(job) => job
+                    .instance
+                    .clone()
+                    .and_then(|name| self.pool.get(&name).cloned()),
+                None => {
+                    return match inner.find(id) {
+                        Some(job) => Err(AppError::InvalidRequest(format!(
+                            "Job {} already finished ({:?})",
+                            id, job.status
+                        ))),
+                        None => Err(AppError::NotFound(format!("Job {} not found", id))),
+                    }
+                }
+            }
+        };
+
+        if let Some(instance) = instance {
+            if instance.is_healthy().await {
+                // Deleting is a no-op once the prompt started, interrupting one if it has not
+                let ids = [id.to_string()];
+                instance.client.delete_from_queue(&ids).await?;
+                if instance.client.interrupt_prompt(id).await? {
+                    tracing::info!("Interrupted running job {} on {}", id, instance.name);
+                }
+            } else {
+                self.abandon(&instance.name, id).await;
+            }
+        }
+
+        let job = self
+            .inner
+            .lock()
+            .await
+            .finish(id, JobStatus::Cancelled, None);
+        match job {
+            Some(job) => {
+                self.publish_cancelled(id).await;
+                self.wake.notify_one();
+                Ok(job)
+            }
+            // Finished while we were talking to ComfyUI
+            None => self
+                .get(id)
+                .await
+                .ok_or_else(|| AppError::NotFound(format!("Job {} not found", id))),
+        }
+    }
+
+    /// Cancel a job if it is still queued or running, then forget it
+    pub async fn remove(&self, id: &str) -> AppResult<()> {
+        if let Some(job) = self.get(id).await {
+            if !job.status.is_finished() {
+                self.cancel(id).await?;
+            }
+        }
+        let mut inner = self.inner.lock().await;
+        let before = inner.finished.len();
+        inner.finished.retain(|j| j.id != id);
+        if inner.finished.len() == before {
+            return Err(AppError::NotFound(format!("Job {} not found", id)));
+        }
+        Ok(())
+    }
+
+    /// Move a pending job within the backend queue
+    pub async fn move_job(&self, id: &str, target: MoveTarget) -> AppResult<Job> {
+        let mut inner = self.inner.lock().await;
+        let index = inner.pending_index(id).ok_or_else(|| not_pending(id))?;
+        let last = inner.pending.len() - 1;
+        let to = match target {
+            MoveTarget::Up => index.saturating_sub(1),
+            MoveTarget::Down => (index + 1).min(last),
+            MoveTarget::Top => 0,
+            MoveTarget::Bottom => last,
+        };
+        let job = inner.pending.remove(index);
+        inner.pending.insert(to, job);
+        Ok(inner.find(id).expect("moved job is pending"))
+    }
+
+    /// Change the priority of a pending job, placing it behind jobs of the same priority
+    pub async fn set_priority(&self, id: &str, priority: JobPriority) -> AppResult<Job> {
+        let mut inner = self.inner.lock().await;
+        let index = inner.pending_index(id).ok_or_else(|| not_pending(id))?;
+        let mut job = inner.pending.remove(index);
+        job.priority = priority;
+        inner.insert_pending(job);
+        Ok(inner.find(id).expect("reprioritized job is pending"))
+    }
+
+    /// Cancel every job that has not started running, returning how many were cancelled
+    ///
+    /// Used together with clearing ComfyUI's own queue.
+    pub async fn cancel_queued(&self) -> usize {
+        let cancelled: Vec<String> = {
+            let mut inner = self.inner.lock().await;
+            let mut ids = Vec::new();
+            for job in std::mem::take(&mut inner.pending) {
+                ids.push(inner.retire(job, JobStatus::Cancelled, None).id);
+            }
+            let submitted: Vec<String> = inner
+                .active
+                .iter()
+                .filter(|j| j.status == JobStatus::Submitted)
+                .map(|j| j.id.clone())
+                .collect();
+            for id in submitted {
+                inner.finish(&id, JobStatus::Cancelled, None);
+                ids.push(id);
+            }
+            ids
+        };
+        for id in &cancelled {
+            self.publish_cancelled(id).await;
+        }
+        cancelled.len()
+    }
+
+    /// Track the jobs an event refers to
+    ///
+    /// Events from an instance only apply to jobs submitted to that instance; `None` marks
+    /// events raised by the backend itself.
+    pub async fn apply_event(&self, instance: Option<&str>, message: &FrontendMessage) {
+        let mut inner = self.inner.lock().await;
+        let Some(prompt_id) = message.prompt_id() else {
+            return;
+        };
+        let ours = inner.active.iter().any(|j| {
+            j.id == prompt_id && instance.is_none_or(|name| j.instance.as_deref() == Some(name))
+        });
+        if !ours {
+            return;
+        }
+
+        let finished = match message {
+            FrontendMessage::Started { prompt_id } => {
+                if let Some(job) = inner.active.iter_mut().find(|j| j.id == *prompt_id) {
+                    job.status = JobStatus::Running;
+                }
+                false
+            }
+            FrontendMessage::Progress {
+                prompt_id,
+                node,
+                value,
+                max,
+                percentage,
+            } => {
+                if let Some(job) = inner.active.iter_mut().find(|j| j.id == *prompt_id) {
+                    job.progress = Some(JobProgress {
+                        node: node.clone(),
+                        value: *value,
+                        max: *max,
+                        percentage: *percentage,
+                    });
+                }
+                false
+            }
+            // `completed` is sent for every output node, only `success` ends the prompt
+            FrontendMessage::Success { prompt_id } => inner
+                .finish(prompt_id, JobStatus::Completed, None)
+                .is_some(),
+            FrontendMessage::Error {
+                prompt_id: Some(prompt_id),
+                message,
+                ..
+            } => inner
+                .finish(prompt_id, JobStatus::Error, Some(message.clone()))
+                .is_some(),
+            FrontendMessage::Interrupted { prompt_id, .. } => inner
+                .finish(prompt_id, JobStatus::Interrupted, None)
+                .is_some(),
+            _ => false,
+        };
+        if finished {
+            self.wake.notify_one();
+        }
+    }
+
+    /// Submit jobs as instances free up; runs for the lifetime of the server
+    pub async fn run(self) {
+        let mut ticker = tokio::time::interval(RECONCILE_INTERVAL);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            self.dispatch().await;
+            tokio::select! {
+                _ = self.wake.notified() => {}
+                _ = ticker.tick() => self.tick().await,
+            }
+        }
+    }
+
+    /// Probe the instances, handle the ones that went down or came back, then reconcile
+    async fn tick(&self) {
+        for change in self.pool.check_health().await {
+            if change.healthy {
+                self.release_abandoned(&change.instance).await;
+            } else {
+                self.fail_over(&change.instance).await;
+            }
+        }
+        self.reconcile().await;
+    }
+
+    /// Submit pending jobs while some instance has room for them
+    async fn dispatch(&self) {
+        loop {
+            let (id, workflow, instance) = {
+                let mut inner = self.inner.lock().await;
+                let in_flight = inner.in_flight();
+                let mut chosen = None;
+                for (index, job) in inner.pending.iter().enumerate() {
+                    if let Some(instance) = self
+                        .pool
+                        .select(&job.allowed_instances, &in_flight, self.max_in_flight)
+                        .await
+                    {
+                        chosen = Some((index, instance));
+                        break;
+                    }
+                }
+                let Some((index, instance)) = chosen else {
+                    return;
+                };
+
+                let mut job = inner.pending.remove(index);
+                job.status = JobStatus::Submitted;
+                job.submitted_at = Some(now_millis());
+                job.instance = Some(instance.name.clone());
+                let workflow = job.workflow.clone();
+                let id = job.id.clone();
+                inner.active.push(job);
+                (id, workflow, instance)
+            };
+
+            match instance
+                .client
+                .queue_prompt(workflow, Some(instance.client_id.clone()), Some(id.clone()))
+                .await
+            {
+                Ok(response) => {
+                    if response.prompt_id != id {
+                        tracing::warn!(
+                            "ComfyUI ignored prompt_id {} and queued {}, progress of this job will not be tracked",
+                            id,
+                            response.prompt_id
+                        );
+                    }
+                    tracing::info!("Job {} submitted to {}", id, instance.name);
+                    // Cancelled while being submitted
+                    if self.get(&id).await.map(|j| j.status) == Some(JobStatus::Cancelled) {
+                        let _ = instance.client.delete_from_queue(&[id]).await;
+                        continue;
+                    }
+                    self.events
+                        .publish(FrontendMessage::Submitted {
+                            prompt_id: id,
+                            instance: instance.name.clone(),
+                        })
+                        .await;
+                }
+                // Instance unreachable: take it out of rotation, its jobs go back to the queue
+                Err(e @ (AppError::HttpClient(_) | AppError::ComfyUIConnection(_))) => {
+                    tracing::debug!("Job {} not submitted to {}: {}", id, instance.name, e);
+                    instance.mark_down(&e).await;
+                    self.fail_over(&instance.name).await;
+                }
+                Err(e) => {
+                    tracing::warn!("Job {} rejected by {}: {}", id, instance.name, e);
+                    let message = FrontendMessage::Error {
+                        prompt_id: Some(id),
+                        message: e.to_string(),
+                        node_id: None,
+                        node_type: None,
+                    };
+                    self.apply_event(None, &message).await;
+                    self.events.publish(message).await;
+                }
+            }
+        }
+    }
+
+    /// Move the jobs of an instance that went down back to the front of the queue
+    ///
+    /// Jobs that can only run there (e.g. upscales of its outputs) fail instead.
+    async fn fail_over(&self, name: &str) {
+        let (requeued, failed) = {
+            let mut inner = self.inner.lock().await;
+            let (lost, kept): (Vec<Job>, Vec<Job>) = std::mem::take(&mut inner.active)
+                .into_iter()
+                .partition(|j| j.instance.as_deref() == Some(name));
+            inner.active = kept;
+            if lost.is_empty() {
+                return;
+            }
+            inner
+                .abandoned
+                .entry(name.to_string())
+                .or_default()
+                .extend(lost.iter().map(|j| j.id.clone()));
+
+            let (stuck, movable): (Vec<Job>, Vec<Job>) = lost.into_iter().partition(|j| {
+                !j.allowed_instances.is_empty() && j.allowed_instances.iter().all(|a| a == name)
+            });
+            let failed: Vec<String> = stuck
+                .into_iter()
+                .map(|job| {
+                    let message = format!("ComfyUI instance {} went down", name);
+                    inner.retire(job, JobStatus::Error, Some(message)).id
+                })
+                .collect();
+            let requeued: Vec<String> = movable.iter().map(|j| j.id.clone()).collect();
+            let movable = movable.into_iter().map(|mut job| {
+                job.status = JobStatus::Pending;
+                job.submitted_at = None;
+                job.instance = None;
+                job.progress = None;
+                job
+            });
+            inner.pending.splice(0..0, movable);
+            (requeued, failed)
+        };
+
+        tracing::warn!(
+            "Instance {} down: {} job(s) requeued, {} failed",
+            name,
+            requeued.len(),
+            failed.len()
+        );
+        for (index, id) in requeued.into_iter().enumerate() {
+            self.events
+                .publish(FrontendMessage::Queued {
+                    prompt_id: id,
+                    queue_position: index as u32 + 1,
+                })
+                .await;
+        }
+        for id in failed {
+            self.events
+                .publish(FrontendMessage::Error {
+                    prompt_id: Some(id),
+                    message: format!("ComfyUI instance {} went down", name),
+                    node_id: None,
+                    node_type: None,
+                })
+                .await;
+        }
+        self.wake.notify_one();
+    }
+
+    /// Remember a prompt left on an unreachable instance
+    async fn abandon(&self, name: &str, id: &str) {
+        self.inner
+            .lock()
+            .await
+            .abandoned
+            .entry(name.to_string())
+            .or_default()
+            .push(id.to_string());
+    }
+
+    /// Remove prompts we gave up on from an instance that came back, so they do not run twice
+    async fn release_abandoned(&self, name: &str) {
+        let ids = self
+            .inner
+            .lock()
+            .await
+            .abandoned
+            .remove(name)
+            .unwrap_or_default();
+        let Some(instance) = self.pool.get(name) else {
+            return;
+        };
+        if ids.is_empty() {
+            return;
+        }
+        if let Err(e) = instance.client.delete_from_queue(&ids).await {
+            tracing::warn!("Cannot clean up abandoned prompts on {}: {}", name, e);
+            return;
+        }
+        for id in &ids {
+            let _ = instance.client.interrupt_prompt(id).await;
+        }
+        tracing::info!("Removed {} abandoned prompt(s) from {}", ids.len(), name);
+    }
+
+    /// Finish submitted jobs that left their instance's queue without us seeing their events
+    async fn reconcile(&self) {
+        let candidates: Vec<(String, ComfyUIInstance)> = {
+            let inner = self.inner.lock().await;
+            let now = now_millis();
+            inner
+                .active
+                .iter()
+                .filter(|j| j.submitted_at.is_some_and(|t| now >= t + SUBMIT_GRACE_MS))
+                .filter_map(|j| {
+                    let instance = self.pool.get(j.instance.as_deref()?)?;
+                    Some((j.id.clone(), instance.clone()))
+                })
+                .collect()
+        };
+
+        for (id, instance) in candidates {
+            // Queue as of the probe just before; none while the instance is down
+            let Some(queue) = instance.queue().await else {
+                continue;
+            };
+            if queue.contains(&id) {
+                continue;
+            }
+            let messages = match instance.client.get_history(&id).await {
+                Ok(Some(history)) if history.interrupted() => vec![FrontendMessage::Interrupted {
+                    prompt_id: id,
+                    node_id: None,
+                    node_type: None,
+                }],
+                Ok(Some(history)) if history.failed() => vec![FrontendMessage::Error {
+                    prompt_id: Some(id),
+                    message: "Execution failed".to_string(),
+                    node_id: None,
+                    node_type: None,
+                }],
+                Ok(Some(history)) => vec![
+                    FrontendMessage::Completed {
+                        prompt_id: id.clone(),
+                        images: history.output_images(),
+                        seed: None,
+                    },
+                    FrontendMessage::Success { prompt_id: id },
+                ],
+                Ok(None) => vec![FrontendMessage::Error {
+                    prompt_id: Some(id),
+                    message: "Prompt is no longer known to ComfyUI".to_string(),
+                    node_id: None,
+                    node_type: None,
+                }],
+                Err(_) => continue,
+            };
+            for message in messages {
+                self.apply_event(Some(&instance.name), &message).await;
+                self.events.publish_from(&instance.name, message).await;
+            }
+        }
+    }
+
+    async fn publish_cancelled(&self, id: &str) {
+        tracing::info!("Job {} cancelled", id);
+        self.events
+            .publish(FrontendMessage::Cancelled {
+                prompt_id: id.to_string(),
+            })
+            .await;
+    }
+}
+
+fn not_pending(id: &str) -> AppError {
+    AppError::InvalidRequest(format!("Job {} is not pending", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, InstanceConfig};
+    use crate::events::EventSender;
+    use crate::history::HistoryStore;
+    use crate::metadata::MetadataFormat;
+    use crate::preview::PreviewSettings;
+    use crate::sweep::SweepManager;
+    use axum::{extract::State, routing::post, Json, Router};
+    use serde_json::json;
+    use std::sync::Mutex as StdMutex;
+
+    /// Requests received by a [`fake_comfyui`], as `"<method> <path> <body>"`
+    type Calls = Arc<StdMutex<Vec<String>>>;
+
+    /// A ComfyUI answering queue and interrupt calls, running `running` and recording the calls
+    async fn fake_comfyui(running: &str) -> (String, Calls) {
+        let calls = Calls::default();
+        let queue = json!({ "queue_running": [[0, running, {}, {}, []]], "queue_pending": [] });
+        let app = Router::new()
+            .route(
+                "/queue",
+                post(
+                    |State(calls): State<Calls>, Json(body): Json<Value>| async move {
+                        calls.lock().unwrap().push(format!("POST /queue {}", body));
+                        Json(json!({}))
+                    },
+                )
+                .get(move || async move { Json(queue) }),
+            )
+            .route(
+                "/interrupt",
+                post(
+                    |State(calls): State<Calls>, Json(body): Json<Value>| async move {
+                        calls
+                            .lock()
+                            .unwrap()
+                            .push(format!("POST /interrupt {}", body));
+                        Json(json!({}))
+                    },
+                ),
+            )
+            .with_state(calls.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, calls)
+    }
+
+    /// Scratch directory removed when the test ends
+    struct DataDir(std::path::PathBuf);
+
+    impl Drop for DataDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// A queue over instances `a` and `b`, both at `url`; nothing is dispatched until `run`
+    fn queue(url: &str) -> (JobQueue, DataDir) {
+        let data_dir = std::env::temp_dir().join(format!("jobs-{}", uuid::Uuid::new_v4()));
+        let instance = |name: &str| InstanceConfig {
+            name: name.to_string(),
+            url: url.to_string(),
+        };
+        let config = Arc::new(Config {
+            host: "127.0.0.1".to_string(),
+            port: 0,
+            comfyui_instances: vec![instance("a"), instance("b")],
+            public_base_url: "http://localhost".to_string(),
+            cors_origins: Vec::new(),
+            templates_dir: "templates".to_string(),
+            default_template: "newbie".to_string(),
+            history_path: data_dir.join("history.jsonl").display().to_string(),
+            png_metadata: MetadataFormat::default(),
+            sweeps_dir: data_dir.join("sweeps").display().to_string(),
+            label_font: None,
+            max_in_flight: 2,
+            preview_max_fps: 0.0,
+            preview_max_size: 0,
+            preview_quality: 75,
+            object_info_ttl: 300,
+        });
+        let pool = ComfyUIPool::new(config.clone());
+        let event_tx = EventSender::new();
+        let sweeps = SweepManager::new(pool.clone(), &config.sweeps_dir, None, event_tx.clone());
+        let events = EventHub::new(
+            event_tx,
+            HistoryStore::open(&config.history_path),
+            sweeps,
+            PreviewSettings::new(&config),
+        );
+        (
+            JobQueue::new(pool, events, config.max_in_flight),
+            DataDir(data_dir),
+        )
+    }
+
+    async fn enqueue(queue: &JobQueue, id: &str, priority: JobPriority) {
+        queue
+            .enqueue(id, "txt2img", priority, json!({}), Vec::new())
+            .await;
+    }
+
+    /// Hand a pending job to `instance` as `dispatch` would
+    async fn submit(queue: &JobQueue, id: &str, instance: &str, status: JobStatus) {
+        let mut inner = queue.inner.lock().await;
+        let index = inner.pending_index(id).unwrap();
+        let mut job = inner.pending.remove(index);
+        job.status = status;
+        job.submitted_at = Some(now_millis());
+        job.instance = Some(instance.to_string());
+        inner.active.push(job);
+    }
+
+    async fn pending_ids(queue: &JobQueue) -> Vec<String> {
+        queue
+            .list()
+            .await
+            .pending
+            .into_iter()
+            .map(|j| j.id)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn high_priority_jobs_overtake_a_batch() {
+        let (queue, _data_dir) = queue("http://127.0.0.1:9");
+        for i in 0..50 {
+            enqueue(&queue, &format!("batch-{}", i), JobPriority::Normal).await;
+        }
+        enqueue(&queue, "low", JobPriority::Low).await;
+        enqueue(&queue, "urgent", JobPriority::High).await;
+        enqueue(&queue, "urgent-2", JobPriority::High).await;
+
+        let pending = pending_ids(&queue).await;
+        assert_eq!(pending[..3], ["urgent", "urgent-2", "batch-0"]);
+        assert_eq!(pending.last().unwrap(), "low");
+        assert_eq!(queue.get("urgent-2").await.unwrap().position, Some(2));
+        assert_eq!(queue.get("batch-49").await.unwrap().position, Some(52));
+    }
+
+    #[tokio::test]
+    async fn pending_jobs_can_be_moved() {
+        let (queue, _data_dir) = queue("http://127.0.0.1:9");
+        for id in ["a", "b", "c", "d"] {
+            enqueue(&queue, id, JobPriority::Normal).await;
+        }
+
+        queue.move_job("d", MoveTarget::Top).await.unwrap();
+        assert_eq!(pending_ids(&queue).await, ["d", "a", "b", "c"]);
+        let moved = queue.move_job("a", MoveTarget::Down).await.unwrap();
+        assert_eq!(moved.position, Some(3));
+        assert_eq!(pending_ids(&queue).await, ["d", "b", "a", "c"]);
+        queue.move_job("d", MoveTarget::Bottom).await.unwrap();
+        queue.move_job("b", MoveTarget::Up).await.unwrap();
+        assert_eq!(pending_ids(&queue).await, ["b", "a", "c", "d"]);
+
+        submit(&queue, "c", "a", JobStatus::Submitted).await;
+        assert!(queue.move_job("c", MoveTarget::Top).await.is_err());
+        assert!(queue.move_job("missing", MoveTarget::Top).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn changed_priorities_requeue_behind_equal_ones() {
+        let (queue, _data_dir) = queue("http://127.0.0.1:9");
+        for id in ["a", "b", "c"] {
+            enqueue(&queue, id, JobPriority::Normal).await;
+        }
+
+        let job = queue.set_priority("c", JobPriority::High).await.unwrap();
+        assert_eq!((job.priority, job.position), (JobPriority::High, Some(1)));
+        queue.set_priority("c", JobPriority::Normal).await.unwrap();
+        assert_eq!(pending_ids(&queue).await, ["a", "b", "c"]);
+        queue.set_priority("a", JobPriority::Low).await.unwrap();
+        assert_eq!(pending_ids(&queue).await, ["b", "c", "a"]);
+    }
+
+    #[tokio::test]
+    async fn cancelling_a_pending_job_leaves_comfyui_alone() {
+        let (url, calls) = fake_comfyui("other").await;
+        let (queue, _data_dir) = queue(&url);
+        enqueue(&queue, "job", JobPriority::Normal).await;
+
+        let job = queue.cancel("job").await.unwrap();
+        assert_eq!(job.status, JobStatus::Cancelled);
+        assert!(calls.lock().unwrap().is_empty());
+        assert!(queue.cancel("job").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn cancelling_a_submitted_job_only_interrupts_it_when_running() {
+        let (url, calls) = fake_comfyui("running").await;
+        let (queue, _data_dir) = queue(&url);
+        enqueue(&queue, "waiting", JobPriority::Normal).await;
+        enqueue(&queue, "running", JobPriority::Normal).await;
+        submit(&queue, "waiting", "a", JobStatus::Submitted).await;
+        submit(&queue, "running", "a", JobStatus::Running).await;
+
+        queue.cancel("waiting").await.unwrap();
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [r#"POST /queue {"delete":["waiting"]}"#]
+        );
+
+        calls.lock().unwrap().clear();
+        let job = queue.cancel("running").await.unwrap();
+        assert_eq!(job.status, JobStatus::Cancelled);
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                r#"POST /queue {"delete":["running"]}"#,
+                r#"POST /interrupt {"prompt_id":"running"}"#
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn removed_jobs_are_cancelled_and_forgotten() {
+        let (queue, _data_dir) = queue("http://127.0.0.1:9");
+        enqueue(&queue, "pending", JobPriority::Normal).await;
+        enqueue(&queue, "kept", JobPriority::Normal).await;
+
+        queue.remove("pending").await.unwrap();
+        assert!(queue.get("pending").await.is_none());
+        assert_eq!(pending_ids(&queue).await, ["kept"]);
+        assert!(queue.remove("pending").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn jobs_of_a_downed_instance_move_to_the_front() {
+        let (queue, _data_dir) = queue("http://127.0.0.1:9");
+        for id in ["movable", "pinned", "elsewhere", "waiting"] {
+            enqueue(&queue, id, JobPriority::Normal).await;
+        }
+        queue.inner.lock().await.pending[1].allowed_instances = vec!["a".to_string()];
+        submit(&queue, "movable", "a", JobStatus::Running).await;
+        submit(&queue, "pinned", "a", JobStatus::Submitted).await;
+        submit(&queue, "elsewhere", "b", JobStatus::Running).await;
+
+        queue.fail_over("a").await;
+
+        assert_eq!(pending_ids(&queue).await, ["movable", "waiting"]);
+        let movable = queue.get("movable").await.unwrap();
+        assert_eq!(movable.status, JobStatus::Pending);
+        assert_eq!(movable.instance, None);
+        let pinned = queue.get("pinned").await.unwrap();
+        assert_eq!(pinned.status, JobStatus::Error);
+        assert_eq!(
+            queue.get("elsewhere").await.unwrap().status,
+            JobStatus::Running
+        );
+        assert_eq!(
+            queue.inner.lock().await.abandoned["a"],
+            ["movable", "pinned"]
+        );
+    }
+}
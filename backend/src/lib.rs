@@ -0,0 +1,19 @@
+pub mod api;
+pub mod comfyui;
+pub mod config;
+pub mod error;
+pub mod events;
+pub mod grid;
+pub mod history;
+pub mod jobs;
+pub mod metadata;
+pub mod models;
+pub mod object_info;
+pub mod pool;
+pub mod preview;
+pub mod seed;
+pub mod sweep;
+pub mod templates;
+pub(crate) mod util;
+pub mod workflow;
+pub mod xml_prompt;
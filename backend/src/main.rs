@@ -1,19 +1,11 @@
-mod api;
-mod comfyui;
-mod config;
-mod error;
-mod models;
-
 use std::sync::Arc;
 use tokio::net::TcpListener;
-use tokio::sync::broadcast;
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use crate::api::{create_router, start_comfyui_listener, AppState};
-use crate::comfyui::ComfyUIClient;
-use crate::config::Config;
+use backend::api::{create_router, AppState};
+use backend::config::Config;
 
 #[tokio::main]
 async fn main() {
@@ -29,20 +21,19 @@ async fn main() {
     // Load configuration
     let config = Arc::new(Config::from_env());
 
-    // Create ComfyUI client
-    let comfyui = ComfyUIClient::new(config.clone());
-
-    // Create event broadcast channel
-    let (event_tx, _) = broadcast::channel::<String>(100);
-
-    // Create application state
-    let comfyui_client_id = uuid::Uuid::new_v4().to_string();
+    tracing::info!(
+        "ComfyUI instances: {}",
+        config
+            .comfyui_instances
+            .iter()
+            .map(|i| format!("{} ({})", i.name, i.url))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
 
-    let state = AppState {
-        comfyui: comfyui.clone(),
-        event_tx: event_tx.clone(),
-        comfyui_client_id: comfyui_client_id.clone(),
-    };
+    // Create application state and start the job queue and ComfyUI listeners
+    let state = AppState::new(config.clone());
+    state.spawn_workers();
 
     // Configure CORS
     let cors = CorsLayer::new()
@@ -55,19 +46,6 @@ async fn main() {
         .layer(cors)
         .layer(TraceLayer::new_for_http());
 
-    // Start ComfyUI WebSocket listener in background
-    let comfyui_for_listener = comfyui.clone();
-    let event_tx_for_listener = event_tx.clone();
-    let client_id_for_listener = comfyui_client_id.clone();
-    tokio::spawn(async move {
-        start_comfyui_listener(
-            comfyui_for_listener,
-            event_tx_for_listener,
-            client_id_for_listener,
-        )
-        .await;
-    });
-
     // Start server
     let addr = config.server_addr();
     let port = config.port;
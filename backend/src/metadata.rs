@@ -0,0 +1,1280 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::comfyui::hires_size;
+use crate::error::{AppError, AppResult};
+use crate::history::GenerationRecord;
+use crate::models::{GenerateRequest, LoraRequest, ResolvedModels};
+use crate::templates::WorkflowTemplate;
+
+/// PNG file signature
+const PNG_SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+/// Largest inflated zTXt or iTXt text, bigger chunks are dropped
+const MAX_TEXT_CHUNK: u64 = 8 * 1024 * 1024;
+
+/// Prefix of the text chunk keywords written by this backend
+pub const KEY_PREFIX: &str = "newbie:";
+
+/// Version of the `newbie:*` key format
+pub const FORMAT_VERSION: &str = "1";
+
+/// Which parameter chunks to embed into served PNGs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataFormat {
+    /// Serve images untouched
+    None,
+    /// One `newbie:<field>` iTXt chunk per parameter
+    #[default]
+    Newbie,
+    /// A single A1111-style `parameters` chunk
+    A1111,
+    /// Both of the above
+    Both,
+}
+
+impl MetadataFormat {
+    /// Parse a config or query value, e.g. `PNG_METADATA=both`
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "none" | "off" | "false" => Some(Self::None),
+            "newbie" | "on" | "true" => Some(Self::Newbie),
+            "a1111" | "parameters" => Some(Self::A1111),
+            "both" | "all" => Some(Self::Both),
+            _ => None,
+        }
+    }
+}
+
+// ============================================================================
+// Generation Parameters
+// ============================================================================
+
+/// The recipe of a generated image
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GenerationParameters {
+    pub prompt: String,
+    pub negative_prompt: String,
+    pub seed: Option<u64>,
+    pub steps: u32,
+    pub cfg: f32,
+    pub sampler_name: String,
+    pub scheduler: String,
+    pub denoise: f32,
+    /// Final output resolution
+    pub width: u32,
+    pub height: u32,
+    pub models: Option<ResolvedModels>,
+    pub loras: Vec<LoraRequest>,
+}
+
+impl GenerationParameters {
+    /// Parameters of a history record, if it was a generation (not a plain upscale)
+    pub fn from_record(record: &GenerationRecord) -> Option<Self> {
+        let request: GenerateRequest = serde_json::from_value(record.request.clone()).ok()?;
+        let (width, height) = match &request.hires {
+            Some(hires) => hires_size(&request, hires),
+            None => (request.width, request.height),
+        };
+
+        Some(Self {
+            prompt: request.prompt,
+            negative_prompt: request.negative_prompt,
+            seed: record.seed,
+            steps: request.steps,
+            cfg: request.cfg,
+            sampler_name: request.sampler_name,
+            scheduler: request.scheduler,
+            denoise: request.denoise,
+            width,
+            height,
+            models: record.models.clone(),
+            loras: request.loras,
+        })
+    }
+
+    /// `newbie:<field>` text chunks
+    ///
+    /// Keys: version, prompt, negative_prompt, seed, steps, cfg, sampler_name, scheduler,
+    /// denoise, width, height, unet, clip1, clip2, vae and loras (JSON array).
+    pub fn to_chunks(&self) -> Vec<(String, String)> {
+        let mut fields = vec![
+            ("version", FORMAT_VERSION.to_string()),
+            ("prompt", self.prompt.clone()),
+            ("negative_prompt", self.negative_prompt.clone()),
+            ("steps", self.steps.to_string()),
+            ("cfg", self.cfg.to_string()),
+            ("sampler_name", self.sampler_name.clone()),
+            ("scheduler", self.scheduler.clone()),
+            ("denoise", self.denoise.to_string()),
+            ("width", self.width.to_string()),
+            ("height", self.height.to_string()),
+        ];
+        if let Some(seed) = self.seed {
+            fields.push(("seed", seed.to_string()));
+        }
+        if let Some(models) = &self.models {
+            fields.push(("unet", models.unet.clone()));
+            fields.push(("clip1", models.clip1.clone()));
+            fields.push(("clip2", models.clip2.clone()));
+            fields.push(("vae", models.vae.clone()));
+        }
+        if !self.loras.is_empty() {
+            fields.push((
+                "loras",
+                serde_json::to_string(&self.loras).unwrap_or_default(),
+            ));
+        }
+
+        fields
+            .into_iter()
+            .map(|(key, value)| (format!("{}{}", KEY_PREFIX, key), value))
+            .collect()
+    }
+
+    /// A1111 "parameters" text: prompt, `Negative prompt:` line, then `Key: value` pairs
+    pub fn to_a1111(&self) -> String {
+        let mut settings = vec![
+            format!("Steps: {}", self.steps),
+            format!("Sampler: {}", self.sampler_name),
+            format!("Schedule type: {}", self.scheduler),
+            format!("CFG scale: {}", self.cfg),
+        ];
+        if let Some(seed) = self.seed {
+            settings.push(format!("Seed: {}", seed));
+        }
+        settings.push(format!("Size: {}x{}", self.width, self.height));
+        if let Some(models) = &self.models {
+            settings.push(format!("Model: {}", models.unet));
+            settings.push(format!("VAE: {}", models.vae));
+            settings.push(format!(
+                "Text encoders: \"{}, {}\"",
+                models.clip1, models.clip2
+            ));
+        }
+        if self.denoise < 1.0 {
+            settings.push(format!("Denoising strength: {}", self.denoise));
+        }
+        if !self.loras.is_empty() {
+            let loras: Vec<String> = self
+                .loras
+                .iter()
+                .map(|l| format!("{}: {}", l.name, l.strength_model))
+                .collect();
+            settings.push(format!("LoRAs: \"{}\"", loras.join(", ")));
+        }
+
+        let mut text = self.prompt.clone();
+        if !self.negative_prompt.is_empty() {
+            text.push_str("\nNegative prompt: ");
+            text.push_str(&self.negative_prompt);
+        }
+        text.push('\n');
+        text.push_str(&settings.join(", "));
+        text
+    }
+
+    /// Chunks for the requested format
+    pub fn chunks_for(&self, format: MetadataFormat) -> Vec<(String, String)> {
+        let mut chunks = Vec::new();
+        if matches!(format, MetadataFormat::Newbie | MetadataFormat::Both) {
+            chunks.extend(self.to_chunks());
+        }
+        if matches!(format, MetadataFormat::A1111 | MetadataFormat::Both) {
+            chunks.push(("parameters".to_string(), self.to_a1111()));
+        }
+        chunks
+    }
+}
+
+// ============================================================================
+// PNG Chunks
+// ============================================================================
+
+/// Whether the data starts with the PNG signature
+pub fn is_png(data: &[u8]) -> bool {
+    data.starts_with(PNG_SIGNATURE)
+}
+
+/// Insert iTXt chunks before IEND, replacing existing text chunks with the same keywords
+pub fn embed_text_chunks(png: &[u8], chunks: &[(String, String)]) -> AppResult<Vec<u8>> {
+    if !is_png(png) {
+        return Err(AppError::InvalidRequest("Not a PNG image".to_string()));
+    }
+
+    let mut out = Vec::with_capacity(
+        png.len()
+            + chunks
+                .iter()
+                .map(|(k, v)| k.len() + v.len() + 17)
+                .sum::<usize>(),
+    );
+    out.extend_from_slice(PNG_SIGNATURE);
+
+    let mut offset = PNG_SIGNATURE.len();
+    while offset + 12 <= png.len() {
+        let length = u32::from_be_bytes(png[offset..offset + 4].try_into().unwrap()) as usize;
+        let end = offset + 12 + length;
+        if end > png.len() {
+            return Err(AppError::InvalidRequest("Truncated PNG chunk".to_string()));
+        }
+        let chunk_type = &png[offset + 4..offset + 8];
+        let data = &png[offset + 8..offset + 8 + length];
+
+        if chunk_type == b"IEND" {
+            for (keyword, text) in chunks {
+                write_chunk(&mut out, b"iTXt", &itxt_data(keyword, text));
+            }
+        }
+
+        let replaced = matches!(chunk_type, b"tEXt" | b"iTXt" | b"zTXt")
+            && chunk_keyword(data).is_some_and(|k| chunks.iter().any(|(key, _)| key == k));
+        if !replaced {
+            out.extend_from_slice(&png[offset..end]);
+        }
+
+        offset = end;
+        if chunk_type == b"IEND" {
+            return Ok(out);
+        }
+    }
+
+    Err(AppError::InvalidRequest("PNG is missing IEND".to_string()))
+}
+
+/// Read all tEXt, zTXt and iTXt chunks as (keyword, text) pairs
+pub fn read_text_chunks(png: &[u8]) -> AppResult<Vec<(String, String)>> {
+    if !is_png(png) {
+        return Err(AppError::InvalidRequest("Not a PNG image".to_string()));
+    }
+
+    let mut chunks = Vec::new();
+    let mut offset = PNG_SIGNATURE.len();
+    while offset + 12 <= png.len() {
+        let length = u32::from_be_bytes(png[offset..offset + 4].try_into().unwrap()) as usize;
+        let end = offset + 12 + length;
+        if end > png.len() {
+            break;
+        }
+        let chunk_type = &png[offset + 4..offset + 8];
+        let data = &png[offset + 8..offset + 8 + length];
+
+        let parsed = match chunk_type {
+            b"tEXt" => parse_text(data),
+            b"zTXt" => parse_ztxt(data),
+            b"iTXt" => parse_itxt(data),
+            b"IEND" => break,
+            _ => None,
+        };
+        if let Some(chunk) = parsed {
+            chunks.push(chunk);
+        }
+        offset = end;
+    }
+
+    Ok(chunks)
+}
+
+/// tEXt: keyword NUL latin-1 text
+fn parse_text(data: &[u8]) -> Option<(String, String)> {
+    let keyword = chunk_keyword(data)?;
+    let text = &data[keyword.len() + 1..];
+    Some((keyword.to_string(), latin1(text)))
+}
+
+/// zTXt: keyword NUL method zlib(latin-1 text)
+fn parse_ztxt(data: &[u8]) -> Option<(String, String)> {
+    let keyword = chunk_keyword(data)?;
+    let compressed = data.get(keyword.len() + 2..)?;
+    Some((keyword.to_string(), latin1(&inflate(compressed)?)))
+}
+
+/// iTXt: keyword NUL flag method language NUL translated NUL utf-8 text
+fn parse_itxt(data: &[u8]) -> Option<(String, String)> {
+    let keyword = chunk_keyword(data)?;
+    let rest = data.get(keyword.len() + 1..)?;
+    let compressed = *rest.first()? == 1;
+    let rest = rest.get(2..)?;
+    let language_end = rest.iter().position(|b| *b == 0)?;
+    let rest = &rest[language_end + 1..];
+    let translated_end = rest.iter().position(|b| *b == 0)?;
+    let text = &rest[translated_end + 1..];
+
+    let text = if compressed {
+        String::from_utf8(inflate(text)?).ok()?
+    } else {
+        String::from_utf8_lossy(text).into_owned()
+    };
+    Some((keyword.to_string(), text))
+}
+
+/// Decompress zlib data, `None` when it is corrupt or inflates past [`MAX_TEXT_CHUNK`]
+fn inflate(data: &[u8]) -> Option<Vec<u8>> {
+    use std::io::Read;
+    let mut out = Vec::new();
+    flate2::read::ZlibDecoder::new(data)
+        .take(MAX_TEXT_CHUNK + 1)
+        .read_to_end(&mut out)
+        .ok()?;
+    (out.len() as u64 <= MAX_TEXT_CHUNK).then_some(out)
+}
+
+fn latin1(data: &[u8]) -> String {
+    data.iter().map(|b| *b as char).collect()
+}
+
+/// Keyword of a text chunk (everything before the first NUL)
+fn chunk_keyword(data: &[u8]) -> Option<&str> {
+    let end = data.iter().position(|b| *b == 0)?;
+    std::str::from_utf8(&data[..end]).ok()
+}
+
+/// Uncompressed iTXt payload with empty language tag and translated keyword
+fn itxt_data(keyword: &str, text: &str) -> Vec<u8> {
+    let mut data = Vec::with_capacity(keyword.len() + text.len() + 5);
+    data.extend_from_slice(keyword.as_bytes());
+    data.push(0); // keyword terminator
+    data.push(0); // compression flag
+    data.push(0); // compression method
+    data.push(0); // language tag terminator
+    data.push(0); // translated keyword terminator
+    data.extend_from_slice(text.as_bytes());
+    data
+}
+
+fn write_chunk(out: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(chunk_type);
+    hasher.update(data);
+
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(chunk_type);
+    out.extend_from_slice(data);
+    out.extend_from_slice(&hasher.finalize().to_be_bytes());
+}
+
+// ============================================================================
+// Parameter Import
+// ============================================================================
+
+/// Parameters recovered from an image
+#[derive(Debug, Clone, Serialize)]
+pub struct ParsedImage {
+    /// Where the parameters came from: newbie, comfyui_prompt, comfyui_workflow or a1111
+    pub source: String,
+    /// Every recognised metadata source found in the file
+    pub found: Vec<String>,
+    /// Request that reproduces the image (seed included when known)
+    pub request: GenerateRequest,
+    /// Model files named in the metadata
+    pub models: Option<ResolvedModels>,
+}
+
+/// Extract generation parameters from PNG text chunks
+///
+/// Our own `newbie:*` chunks win over ComfyUI's `prompt` graph, which wins over its UI
+/// `workflow` and finally A1111 `parameters`. `template` is used to strip the prompt wrapping
+/// from texts read out of ComfyUI graphs.
+pub fn parse_parameters(
+    chunks: &[(String, String)],
+    template: &WorkflowTemplate,
+) -> Option<ParsedImage> {
+    let get = |key: &str| {
+        chunks
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    };
+
+    let mut found = Vec::new();
+    if chunks.iter().any(|(k, _)| k.starts_with(KEY_PREFIX)) {
+        found.push("newbie");
+    }
+    let prompt_graph = get("prompt").and_then(|v| serde_json::from_str::<Value>(v).ok());
+    if prompt_graph.is_some() {
+        found.push("comfyui_prompt");
+    }
+    let ui_workflow = get("workflow").and_then(|v| serde_json::from_str::<Value>(v).ok());
+    if ui_workflow.is_some() {
+        found.push("comfyui_workflow");
+    }
+    if get("parameters").is_some() {
+        found.push("a1111");
+    }
+
+    let (source, request, models) = if found.contains(&"newbie") {
+        let (request, models) = parse_newbie_chunks(&get);
+        ("newbie", request, models)
+    } else if let Some((request, models)) = prompt_graph
+        .as_ref()
+        .and_then(|graph| parse_api_graph(graph, template))
+    {
+        ("comfyui_prompt", request, models)
+    } else if let Some((request, models)) = ui_workflow
+        .as_ref()
+        .map(ui_workflow_to_api)
+        .and_then(|graph| parse_api_graph(&graph, template))
+    {
+        ("comfyui_workflow", request, models)
+    } else if let Some(text) = get("parameters") {
+        let (request, models) = parse_a1111(text);
+        ("a1111", request, models)
+    } else {
+        return None;
+    };
+
+    Some(ParsedImage {
+        source: source.to_string(),
+        found: found.into_iter().map(String::from).collect(),
+        request,
+        models,
+    })
+}
+
+/// Read our own `newbie:<field>` chunks
+fn parse_newbie_chunks<'a>(
+    get: &impl Fn(&str) -> Option<&'a str>,
+) -> (GenerateRequest, Option<ResolvedModels>) {
+    let field = |name: &str| get(&format!("{}{}", KEY_PREFIX, name));
+    let mut request = GenerateRequest::default();
+
+    if let Some(v) = field("prompt") {
+        request.prompt = v.to_string();
+    }
+    if let Some(v) = field("negative_prompt") {
+        request.negative_prompt = v.to_string();
+    }
+    if let Some(v) = field("seed").and_then(|v| v.parse().ok()) {
+        request.seed = v;
+    }
+    if let Some(v) = field("steps").and_then(|v| v.parse().ok()) {
+        request.steps = v;
+    }
+    if let Some(v) = field("cfg").and_then(|v| v.parse().ok()) {
+        request.cfg = v;
+    }
+    if let Some(v) = field("sampler_name") {
+        request.sampler_name = v.to_string();
+    }
+    if let Some(v) = field("scheduler") {
+        request.scheduler = v.to_string();
+    }
+    if let Some(v) = field("denoise").and_then(|v| v.parse().ok()) {
+        request.denoise = v;
+    }
+    if let Some(v) = field("width").and_then(|v| v.parse().ok()) {
+        request.width = v;
+    }
+    if let Some(v) = field("height").and_then(|v| v.parse().ok()) {
+        request.height = v;
+    }
+    if let Some(v) = field("loras").and_then(|v| serde_json::from_str(v).ok()) {
+        request.loras = v;
+    }
+
+    let models = field("unet").map(|unet| ResolvedModels {
+        unet: unet.to_string(),
+        clip1: field("clip1").unwrap_or_default().to_string(),
+        clip2: field("clip2").unwrap_or_default().to_string(),
+        vae: field("vae").unwrap_or_default().to_string(),
+    });
+
+    (request, models)
+}
+
+/// Walk an API-format graph from its first-pass KSampler
+fn parse_api_graph(
+    graph: &Value,
+    template: &WorkflowTemplate,
+) -> Option<(GenerateRequest, Option<ResolvedModels>)> {
+    let nodes = graph.as_object()?;
+    let is_sampler = |node: &Value| {
+        matches!(
+            node.get("class_type").and_then(|v| v.as_str()),
+            Some("KSampler") | Some("KSamplerAdvanced")
+        )
+    };
+
+    // Prefer the sampler that starts from an empty latent (the first pass of a hires graph)
+    let mut samplers: Vec<&String> = nodes
+        .iter()
+        .filter(|(_, node)| is_sampler(node))
+        .map(|(id, _)| id)
+        .collect();
+    samplers.sort_by_key(|id| id.parse::<u64>().unwrap_or(u64::MAX));
+    let sampler_id = samplers
+        .iter()
+        .find(|id| {
+            linked_node(graph, id, "latent_image")
+                .and_then(|(_, node)| node.get("class_type").and_then(|v| v.as_str()))
+                .is_some_and(|class| class.starts_with("Empty") && class.contains("Latent"))
+        })
+        .or(samplers.first())?;
+    let sampler = graph.get(sampler_id.as_str())?;
+
+    let mut request = GenerateRequest::default();
+    let input = |name: &str| sampler.get("inputs").and_then(|i| i.get(name));
+
+    if let Some(v) = input("seed")
+        .or(input("noise_seed"))
+        .and_then(|v| v.as_i64())
+    {
+        request.seed = v;
+    }
+    if let Some(v) = input("steps").and_then(|v| v.as_u64()) {
+        request.steps = v as u32;
+    }
+    if let Some(v) = input("cfg").and_then(|v| v.as_f64()) {
+        request.cfg = v as f32;
+    }
+    if let Some(v) = input("sampler_name").and_then(|v| v.as_str()) {
+        request.sampler_name = v.to_string();
+    }
+    if let Some(v) = input("scheduler").and_then(|v| v.as_str()) {
+        request.scheduler = v.to_string();
+    }
+    if let Some(v) = input("denoise").and_then(|v| v.as_f64()) {
+        request.denoise = v as f32;
+    }
+
+    if let Some(text) = conditioning_text(graph, sampler_id, "positive") {
+        request.prompt = template.unformat_prompt(&text);
+    }
+    if let Some(text) = conditioning_text(graph, sampler_id, "negative") {
+        request.negative_prompt = template.unformat_negative_prompt(&text);
+    }
+
+    if let Some((_, latent)) = linked_node(graph, sampler_id, "latent_image") {
+        let latent_input = |name: &str| {
+            latent
+                .get("inputs")
+                .and_then(|i| i.get(name))
+                .and_then(|v| v.as_u64())
+        };
+        if let Some(v) = latent_input("width") {
+            request.width = v as u32;
+        }
+        if let Some(v) = latent_input("height") {
+            request.height = v as u32;
+        }
+        if let Some(v) = latent_input("batch_size") {
+            request.batch_size = v as u32;
+        }
+    }
+
+    // Follow the model chain up to the UNET, collecting LoRAs on the way
+    let mut models = ResolvedModels::default();
+    let mut loras = Vec::new();
+    let mut current = sampler_id.to_string();
+    for _ in 0..32 {
+        let Some((id, node)) = linked_node(graph, &current, "model") else {
+            break;
+        };
+        let class = node.get("class_type").and_then(|v| v.as_str());
+        let inputs = node.get("inputs");
+        let text = |name: &str| {
+            inputs
+                .and_then(|i| i.get(name))
+                .and_then(|v| v.as_str())
+                .map(String::from)
+        };
+        match class {
+            Some("LoraLoaderModelOnly") | Some("LoraLoader") => {
+                if let Some(name) = text("lora_name") {
+                    let strength = inputs
+                        .and_then(|i| i.get("strength_model"))
+                        .and_then(|v| v.as_f64())
+                        .unwrap_or(1.0) as f32;
+                    // Loaders with zero strength are inactive leftovers
+                    if strength != 0.0 {
+                        loras.push(LoraRequest {
+                            name,
+                            strength_model: strength,
+                        });
+                    }
+                }
+            }
+            Some("UNETLoader") => {
+                models.unet = text("unet_name").unwrap_or_default();
+                break;
+            }
+            Some("CheckpointLoaderSimple") => {
+                models.unet = text("ckpt_name").unwrap_or_default();
+                break;
+            }
+            _ => {}
+        }
+        current = id;
+    }
+    loras.reverse();
+    request.loras = loras;
+
+    if let Some((_, clip)) = linked_node(graph, sampler_id, "positive")
+        .and_then(|(id, _)| linked_node(graph, &id, "clip"))
+    {
+        let inputs = clip.get("inputs");
+        let text = |name: &str| {
+            inputs
+                .and_then(|i| i.get(name))
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        models.clip1 = text("clip_name1");
+        models.clip2 = text("clip_name2");
+    }
+
+    if let Some(decode) = find_consumer(graph, sampler_id, "VAEDecode").or_else(|| {
+        nodes
+            .iter()
+            .find(|(_, n)| n.get("class_type").and_then(|v| v.as_str()) == Some("VAEDecode"))
+            .map(|(id, _)| id.clone())
+    }) {
+        if let Some((_, vae)) = linked_node(graph, &decode, "vae") {
+            models.vae = vae
+                .get("inputs")
+                .and_then(|i| i.get("vae_name"))
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string();
+        }
+    }
+
+    let models = (!models.unet.is_empty()).then_some(models);
+    Some((request, models))
+}
+
+/// The node linked into `input` of `node_id`
+fn linked_node<'a>(graph: &'a Value, node_id: &str, input: &str) -> Option<(String, &'a Value)> {
+    let link = graph.get(node_id)?.get("inputs")?.get(input)?.as_array()?;
+    let source = link.first()?;
+    let source_id = match source {
+        Value::String(id) => id.clone(),
+        Value::Number(id) => id.to_string(),
+        _ => return None,
+    };
+    let node = graph.get(&source_id)?;
+    Some((source_id, node))
+}
+
+/// First node of `class_type` that reads output 0 of `node_id`
+fn find_consumer(graph: &Value, node_id: &str, class_type: &str) -> Option<String> {
+    graph.as_object()?.iter().find_map(|(id, node)| {
+        let is_class = node.get("class_type").and_then(|v| v.as_str()) == Some(class_type);
+        let reads = node
+            .get("inputs")
+            .and_then(|v| v.as_object())
+            .is_some_and(|inputs| {
+                inputs.values().any(|v| {
+                    v.get(0).and_then(|v| v.as_str()) == Some(node_id)
+                        && v.get(1).and_then(|v| v.as_u64()) == Some(0)
+                })
+            });
+        (is_class && reads).then(|| id.clone())
+    })
+}
+
+/// Prompt text feeding a conditioning input, following text inputs that are links
+fn conditioning_text(graph: &Value, sampler_id: &str, input: &str) -> Option<String> {
+    let (encoder_id, encoder) = linked_node(graph, sampler_id, input)?;
+    match encoder.get("inputs")?.get("text")? {
+        Value::String(text) => Some(text.clone()),
+        Value::Array(_) => {
+            // Text from a primitive/string node: take its first string input
+            let (_, source) = linked_node(graph, &encoder_id, "text")?;
+            source
+                .get("inputs")?
+                .as_object()?
+                .values()
+                .find_map(|v| v.as_str().map(String::from))
+        }
+        _ => None,
+    }
+}
+
+/// Convert a ComfyUI UI-format workflow into API format for the nodes we understand
+///
+/// Widget values are positional in the UI format, so only known node classes get named inputs.
+fn ui_workflow_to_api(workflow: &Value) -> Value {
+    const WIDGETS: &[(&str, &[&str])] = &[
+        (
+            "KSampler",
+            &[
+                "seed",
+                "control_after_generate",
+                "steps",
+                "cfg",
+                "sampler_name",
+                "scheduler",
+                "denoise",
+            ],
+        ),
+        ("CLIPTextEncode", &["text"]),
+        ("EmptySD3LatentImage", &["width", "height", "batch_size"]),
+        ("EmptyLatentImage", &["width", "height", "batch_size"]),
+        ("UNETLoader", &["unet_name", "weight_dtype"]),
+        (
+            "DualCLIPLoader",
+            &["clip_name1", "clip_name2", "type", "device"],
+        ),
+        ("VAELoader", &["vae_name"]),
+        ("LoraLoaderModelOnly", &["lora_name", "strength_model"]),
+        ("CheckpointLoaderSimple", &["ckpt_name"]),
+    ];
+
+    // link id -> [from_node, from_slot]
+    let mut links = std::collections::HashMap::new();
+    for link in workflow
+        .get("links")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+    {
+        if let (Some(id), Some(from), Some(slot)) = (
+            link.get(0).and_then(|v| v.as_u64()),
+            link.get(1).and_then(|v| v.as_u64()),
+            link.get(2).and_then(|v| v.as_u64()),
+        ) {
+            links.insert(id, serde_json::json!([from.to_string(), slot]));
+        }
+    }
+
+    let mut api = serde_json::Map::new();
+    for node in workflow
+        .get("nodes")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+    {
+        let (Some(id), Some(class_type)) = (
+            node.get("id").and_then(|v| v.as_u64()),
+            node.get("type").and_then(|v| v.as_str()),
+        ) else {
+            continue;
+        };
+
+        let mut inputs = serde_json::Map::new();
+        if let Some(names) = WIDGETS
+            .iter()
+            .find(|(c, _)| *c == class_type)
+            .map(|(_, n)| n)
+        {
+            let values = node.get("widgets_values").and_then(|v| v.as_array());
+            for (name, value) in names.iter().zip(values.into_iter().flatten()) {
+                inputs.insert(name.to_string(), value.clone());
+            }
+        }
+        for input in node
+            .get("inputs")
+            .and_then(|v| v.as_array())
+            .into_iter()
+            .flatten()
+        {
+            if let (Some(name), Some(link)) = (
+                input.get("name").and_then(|v| v.as_str()),
+                input
+                    .get("link")
+                    .and_then(|v| v.as_u64())
+                    .and_then(|id| links.get(&id)),
+            ) {
+                inputs.insert(name.to_string(), link.clone());
+            }
+        }
+
+        api.insert(
+            id.to_string(),
+            serde_json::json!({ "class_type": class_type, "inputs": inputs }),
+        );
+    }
+
+    Value::Object(api)
+}
+
+/// Parse A1111 "parameters" text
+fn parse_a1111(text: &str) -> (GenerateRequest, Option<ResolvedModels>) {
+    let mut request = GenerateRequest::default();
+
+    // The settings line is the last line starting with "Steps: "
+    let lines: Vec<&str> = text.lines().collect();
+    let settings_index = lines
+        .iter()
+        .rposition(|l| l.trim_start().starts_with("Steps: "));
+    let prompt_lines = &lines[..settings_index.unwrap_or(lines.len())];
+
+    let mut prompt = Vec::new();
+    let mut negative = Vec::new();
+    let mut in_negative = false;
+    for line in prompt_lines {
+        if let Some(rest) = line.strip_prefix("Negative prompt:") {
+            in_negative = true;
+            negative.push(rest.trim_start());
+        } else if in_negative {
+            negative.push(line);
+        } else {
+            prompt.push(*line);
+        }
+    }
+    request.prompt = prompt.join("\n").trim().to_string();
+    request.negative_prompt = negative.join("\n").trim().to_string();
+
+    let Some(index) = settings_index else {
+        return (request, None);
+    };
+    let settings = split_a1111_settings(lines[index]);
+    let setting = |key: &str| {
+        settings
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    };
+
+    if let Some(v) = setting("Steps").and_then(|v| v.parse().ok()) {
+        request.steps = v;
+    }
+    if let Some(v) = setting("CFG scale").and_then(|v| v.parse().ok()) {
+        request.cfg = v;
+    }
+    if let Some(v) = setting("Seed").and_then(|v| v.parse().ok()) {
+        request.seed = v;
+    }
+    if let Some(v) = setting("Denoising strength").and_then(|v| v.parse().ok()) {
+        request.denoise = v;
+    }
+    if let Some((w, h)) = setting("Size").and_then(|v| v.split_once('x')) {
+        if let (Ok(w), Ok(h)) = (w.trim().parse(), h.trim().parse()) {
+            request.width = w;
+            request.height = h;
+        }
+    }
+    if let Some(sampler) = setting("Sampler") {
+        let (name, scheduler) = map_a1111_sampler(sampler);
+        request.sampler_name = name;
+        if let Some(scheduler) = scheduler {
+            request.scheduler = scheduler.to_string();
+        }
+    }
+    if let Some(v) = setting("Schedule type") {
+        request.scheduler = v.to_lowercase().replace(' ', "_");
+    }
+
+    let models = setting("Model").map(|unet| {
+        let (clip1, clip2) = setting("Text encoders")
+            .and_then(|v| v.split_once(", "))
+            .unwrap_or_default();
+        ResolvedModels {
+            unet: unet.to_string(),
+            clip1: clip1.to_string(),
+            clip2: clip2.to_string(),
+            vae: setting("VAE").unwrap_or_default().to_string(),
+        }
+    });
+
+    (request, models)
+}
+
+/// Split `Key: value, Key: "quoted, value", ...`
+fn split_a1111_settings(line: &str) -> Vec<(String, String)> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            ',' if !quoted => parts.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    parts.push(current);
+
+    parts
+        .iter()
+        .filter_map(|part| {
+            let (key, value) = part.split_once(':')?;
+            Some((
+                key.trim().to_string(),
+                value.trim().trim_matches('"').to_string(),
+            ))
+        })
+        .collect()
+}
+
+/// Map an A1111 sampler label to a ComfyUI sampler name and optional scheduler
+fn map_a1111_sampler(label: &str) -> (String, Option<&'static str>) {
+    let (base, scheduler) = match label.strip_suffix(" Karras") {
+        Some(base) => (base, Some("karras")),
+        None => (label, None),
+    };
+    let name = match base {
+        "Euler a" => "euler_ancestral",
+        "Euler" => "euler",
+        "Heun" => "heun",
+        "LMS" => "lms",
+        "DPM2" => "dpm_2",
+        "DPM2 a" => "dpm_2_ancestral",
+        "DPM++ 2S a" => "dpmpp_2s_ancestral",
+        "DPM++ 2M" => "dpmpp_2m",
+        "DPM++ SDE" => "dpmpp_sde",
+        "DPM++ 2M SDE" => "dpmpp_2m_sde",
+        "DPM++ 3M SDE" => "dpmpp_3m_sde",
+        "DDIM" => "ddim",
+        "UniPC" => "uni_pc",
+        "LCM" => "lcm",
+        // Already a ComfyUI name (e.g. written by this backend)
+        other => return (other.to_string(), scheduler),
+    };
+    (name.to_string(), scheduler)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::Write;
+
+    fn template() -> WorkflowTemplate {
+        WorkflowTemplate::from_json(include_str!("../templates/newbie.json")).unwrap()
+    }
+
+    fn parse(chunks: &[(String, String)]) -> ParsedImage {
+        parse_parameters(chunks, &template()).expect("no parameters found")
+    }
+
+    fn text_chunk(key: &str, value: impl ToString) -> (String, String) {
+        (key.to_string(), value.to_string())
+    }
+
+    fn parameters() -> GenerationParameters {
+        GenerationParameters {
+            prompt: "1girl, silver hair, \"night\" sky".to_string(),
+            negative_prompt: "lowres, bad hands".to_string(),
+            seed: Some(1234567890123),
+            steps: 28,
+            cfg: 4.5,
+            sampler_name: "res_multistep".to_string(),
+            scheduler: "linear_quadratic".to_string(),
+            denoise: 0.6,
+            width: 832,
+            height: 1216,
+            models: Some(ResolvedModels {
+                unet: "newbie-image.safetensors".to_string(),
+                clip1: "gemma_3_4b_it.safetensors".to_string(),
+                clip2: "jina_clip_v2.safetensors".to_string(),
+                vae: "ae.safetensors".to_string(),
+            }),
+            loras: vec![LoraRequest {
+                name: "styles/watercolor.safetensors".to_string(),
+                strength_model: 0.8,
+            }],
+        }
+    }
+
+    /// A 1x1 PNG with the given chunks before IEND
+    fn png_with(chunks: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut png = PNG_SIGNATURE.to_vec();
+        let mut ihdr = Vec::new();
+        ihdr.extend_from_slice(&1u32.to_be_bytes());
+        ihdr.extend_from_slice(&1u32.to_be_bytes());
+        ihdr.extend_from_slice(&[8, 0, 0, 0, 0]);
+        write_chunk(&mut png, b"IHDR", &ihdr);
+        for (chunk_type, data) in chunks {
+            write_chunk(&mut png, chunk_type, data);
+        }
+        write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    fn deflate(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn ztxt(keyword: &str, text: &[u8]) -> Vec<u8> {
+        let mut data = keyword.as_bytes().to_vec();
+        data.extend_from_slice(&[0, 0]);
+        data.extend_from_slice(&deflate(text));
+        data
+    }
+
+    #[test]
+    fn compressed_chunks_are_inflated() {
+        let mut itxt = b"Comment\0\x01\0\0\0".to_vec();
+        itxt.extend_from_slice(&deflate("caf\u{e9}".as_bytes()));
+        let png = png_with(&[
+            (b"zTXt", ztxt("parameters", b"1girl, smile")),
+            (b"iTXt", itxt),
+        ]);
+
+        let chunks = read_text_chunks(&png).unwrap();
+        assert_eq!(
+            chunks,
+            vec![
+                ("parameters".to_string(), "1girl, smile".to_string()),
+                ("Comment".to_string(), "caf\u{e9}".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn oversized_compressed_chunks_are_dropped() {
+        let bomb = vec![b'a'; MAX_TEXT_CHUNK as usize + 1];
+        let at_limit = vec![b'b'; MAX_TEXT_CHUNK as usize];
+        let png = png_with(&[
+            (b"zTXt", ztxt("bomb", &bomb)),
+            (b"zTXt", ztxt("fits", &at_limit)),
+            (b"tEXt", b"after\0still read".to_vec()),
+        ]);
+
+        let chunks = read_text_chunks(&png).unwrap();
+        let keys: Vec<&str> = chunks.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, ["fits", "after"]);
+        assert_eq!(chunks[0].1.len(), MAX_TEXT_CHUNK as usize);
+    }
+
+    #[test]
+    fn newbie_chunks_round_trip_through_a_png() {
+        let expected = parameters();
+        let png = embed_text_chunks(
+            &png_with(&[(b"tEXt", b"newbie:prompt\0stale".to_vec())]),
+            &expected.chunks_for(MetadataFormat::Both),
+        )
+        .unwrap();
+
+        let chunks = read_text_chunks(&png).unwrap();
+        // The stale chunk was replaced, not duplicated
+        assert_eq!(
+            chunks.iter().filter(|(k, _)| k == "newbie:prompt").count(),
+            1
+        );
+
+        let parsed = parse(&chunks);
+        assert_eq!(parsed.source, "newbie");
+        assert_eq!(parsed.found, ["newbie", "a1111"]);
+        let request = parsed.request;
+        assert_eq!(request.prompt, expected.prompt);
+        assert_eq!(request.negative_prompt, expected.negative_prompt);
+        assert_eq!(request.seed, 1234567890123);
+        assert_eq!(request.steps, 28);
+        assert_eq!(request.cfg, 4.5);
+        assert_eq!(request.sampler_name, "res_multistep");
+        assert_eq!(request.scheduler, "linear_quadratic");
+        assert_eq!(request.denoise, 0.6);
+        assert_eq!((request.width, request.height), (832, 1216));
+        assert_eq!(request.loras.len(), 1);
+        assert_eq!(request.loras[0].name, "styles/watercolor.safetensors");
+        assert_eq!(request.loras[0].strength_model, 0.8);
+        let models = parsed.models.unwrap();
+        assert_eq!(models.unet, "newbie-image.safetensors");
+        assert_eq!(models.clip2, "jina_clip_v2.safetensors");
+        assert_eq!(models.vae, "ae.safetensors");
+    }
+
+    #[test]
+    fn a1111_text_round_trips() {
+        let expected = parameters();
+        let png =
+            embed_text_chunks(&png_with(&[]), &expected.chunks_for(MetadataFormat::A1111)).unwrap();
+
+        let parsed = parse(&read_text_chunks(&png).unwrap());
+        assert_eq!(parsed.source, "a1111");
+        let request = parsed.request;
+        assert_eq!(request.prompt, expected.prompt);
+        assert_eq!(request.negative_prompt, expected.negative_prompt);
+        assert_eq!(request.seed, 1234567890123);
+        assert_eq!(request.steps, 28);
+        assert_eq!(request.cfg, 4.5);
+        assert_eq!(request.sampler_name, "res_multistep");
+        assert_eq!(request.scheduler, "linear_quadratic");
+        assert_eq!(request.denoise, 0.6);
+        assert_eq!((request.width, request.height), (832, 1216));
+        let models = parsed.models.unwrap();
+        assert_eq!(models.unet, "newbie-image.safetensors");
+        assert_eq!(models.clip1, "gemma_3_4b_it.safetensors");
+        assert_eq!(models.clip2, "jina_clip_v2.safetensors");
+        assert_eq!(models.vae, "ae.safetensors");
+    }
+
+    #[test]
+    fn a1111_text_from_webui() {
+        let text = "masterpiece, 1girl,\nsmile\n\
+            Negative prompt: lowres,\nbad hands\n\
+            Steps: 30, Sampler: DPM++ 2M Karras, CFG scale: 7, Seed: 42, Size: 512x768, \
+            Model hash: abc123, Model: anything-v5, \
+            Lora hashes: \"detail: 1a2b, style: 3c4d\", Denoising strength: 0.45, \
+            Version: v1.9.0";
+        let parsed = parse(&[text_chunk("parameters", text)]);
+
+        assert_eq!(parsed.source, "a1111");
+        let request = parsed.request;
+        assert_eq!(request.prompt, "masterpiece, 1girl,\nsmile");
+        assert_eq!(request.negative_prompt, "lowres,\nbad hands");
+        assert_eq!(request.steps, 30);
+        assert_eq!(request.sampler_name, "dpmpp_2m");
+        assert_eq!(request.scheduler, "karras");
+        assert_eq!(request.cfg, 7.0);
+        assert_eq!(request.seed, 42);
+        assert_eq!((request.width, request.height), (512, 768));
+        assert_eq!(request.denoise, 0.45);
+        assert_eq!(parsed.models.unwrap().unet, "anything-v5");
+    }
+
+    #[test]
+    fn a1111_settings_keep_quoted_commas() {
+        let settings = split_a1111_settings(
+            "Steps: 20, Lora hashes: \"a: 1, b: 2\", TI: \"x, y\", Size: 512x512",
+        );
+        let pairs: Vec<(&str, &str)> = settings
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        assert_eq!(
+            pairs,
+            [
+                ("Steps", "20"),
+                ("Lora hashes", "a: 1, b: 2"),
+                ("TI", "x, y"),
+                ("Size", "512x512"),
+            ]
+        );
+    }
+
+    #[test]
+    fn a1111_sampler_labels_map_to_comfyui_names() {
+        assert_eq!(
+            map_a1111_sampler("Euler a"),
+            ("euler_ancestral".to_string(), None)
+        );
+        assert_eq!(
+            map_a1111_sampler("DPM++ 2M SDE Karras"),
+            ("dpmpp_2m_sde".to_string(), Some("karras"))
+        );
+        assert_eq!(map_a1111_sampler("UniPC"), ("uni_pc".to_string(), None));
+        assert_eq!(
+            map_a1111_sampler("res_multistep"),
+            ("res_multistep".to_string(), None)
+        );
+    }
+
+    /// A hires graph as ComfyUI stores it in the `prompt` chunk
+    fn hires_prompt_graph() -> Value {
+        let template = template();
+        json!({
+            "1": { "class_type": "UNETLoader", "inputs": { "unet_name": "newbie-image.safetensors", "weight_dtype": "default" } },
+            "2": { "class_type": "LoraLoaderModelOnly", "inputs": { "lora_name": "a.safetensors", "strength_model": 0.7, "model": ["1", 0] } },
+            "3": { "class_type": "LoraLoaderModelOnly", "inputs": { "lora_name": "off.safetensors", "strength_model": 0.0, "model": ["2", 0] } },
+            "4": { "class_type": "LoraLoaderModelOnly", "inputs": { "lora_name": "b.safetensors", "strength_model": 1.2, "model": ["3", 0] } },
+            "5": { "class_type": "DualCLIPLoader", "inputs": { "clip_name1": "gemma_3_4b_it.safetensors", "clip_name2": "jina_clip_v2.safetensors", "type": "newbie" } },
+            "6": { "class_type": "CLIPTextEncode", "inputs": { "text": template.format_prompt("1girl, smile"), "clip": ["5", 0] } },
+            "7": { "class_type": "CLIPTextEncode", "inputs": { "text": template.format_negative_prompt("lowres"), "clip": ["5", 0] } },
+            "8": { "class_type": "EmptySD3LatentImage", "inputs": { "width": 832, "height": 1216, "batch_size": 2 } },
+            // Second pass, numbered before the first one
+            "10": { "class_type": "KSampler", "inputs": {
+                "seed": 7, "steps": 12, "cfg": 4.5, "sampler_name": "euler", "scheduler": "normal", "denoise": 0.4,
+                "model": ["4", 0], "positive": ["6", 0], "negative": ["7", 0], "latent_image": ["11", 0]
+            } },
+            "11": { "class_type": "LatentUpscaleBy", "inputs": { "upscale_method": "nearest-exact", "scale_by": 1.5, "samples": ["12", 0] } },
+            "12": { "class_type": "KSampler", "inputs": {
+                "seed": 99, "steps": 28, "cfg": 4.5, "sampler_name": "res_multistep", "scheduler": "linear_quadratic", "denoise": 1.0,
+                "model": ["4", 0], "positive": ["6", 0], "negative": ["7", 0], "latent_image": ["8", 0]
+            } },
+            "13": { "class_type": "VAELoader", "inputs": { "vae_name": "ae.safetensors" } },
+            "14": { "class_type": "VAEDecode", "inputs": { "samples": ["10", 0], "vae": ["13", 0] } },
+            "15": { "class_type": "SaveImage", "inputs": { "images": ["14", 0] } }
+        })
+    }
+
+    #[test]
+    fn comfyui_prompt_uses_the_first_pass_of_a_hires_graph() {
+        let parsed = parse(&[text_chunk("prompt", hires_prompt_graph())]);
+
+        assert_eq!(parsed.source, "comfyui_prompt");
+        let request = parsed.request;
+        assert_eq!(request.prompt, "1girl, smile");
+        assert_eq!(request.negative_prompt, "lowres");
+        assert_eq!(request.seed, 99);
+        assert_eq!(request.steps, 28);
+        assert_eq!(request.sampler_name, "res_multistep");
+        assert_eq!(request.denoise, 1.0);
+        assert_eq!((request.width, request.height), (832, 1216));
+        assert_eq!(request.batch_size, 2);
+        // Inactive loaders are skipped, the others come in chain order
+        let loras: Vec<(&str, f32)> = request
+            .loras
+            .iter()
+            .map(|l| (l.name.as_str(), l.strength_model))
+            .collect();
+        assert_eq!(loras, [("a.safetensors", 0.7), ("b.safetensors", 1.2)]);
+        let models = parsed.models.unwrap();
+        assert_eq!(models.unet, "newbie-image.safetensors");
+        assert_eq!(models.clip1, "gemma_3_4b_it.safetensors");
+        assert_eq!(models.clip2, "jina_clip_v2.safetensors");
+        assert_eq!(models.vae, "ae.safetensors");
+    }
+
+    #[test]
+    fn comfyui_workflow_is_converted_to_api_format() {
+        // [id, from node, from slot, to node, to slot, type]
+        let workflow = json!({
+            "nodes": [
+                { "id": 1, "type": "UNETLoader", "widgets_values": ["newbie-image.safetensors", "default"] },
+                { "id": 2, "type": "DualCLIPLoader", "widgets_values": ["gemma.safetensors", "jina.safetensors", "newbie", "default"] },
+                { "id": 3, "type": "CLIPTextEncode", "widgets_values": ["1girl"], "inputs": [{ "name": "clip", "link": 2 }] },
+                { "id": 4, "type": "CLIPTextEncode", "widgets_values": ["lowres"], "inputs": [{ "name": "clip", "link": 3 }] },
+                { "id": 5, "type": "EmptySD3LatentImage", "widgets_values": [1024, 1536, 1] },
+                { "id": 6, "type": "KSampler", "widgets_values": [5, "fixed", 20, 5.5, "euler", "simple", 1.0], "inputs": [
+                    { "name": "model", "link": 1 },
+                    { "name": "positive", "link": 4 },
+                    { "name": "negative", "link": 5 },
+                    { "name": "latent_image", "link": 6 }
+                ] },
+                { "id": 7, "type": "Note", "widgets_values": ["not a parameter"] }
+            ],
+            "links": [
+                [1, 1, 0, 6, 0, "MODEL"],
+                [2, 2, 0, 3, 0, "CLIP"],
+                [3, 2, 0, 4, 0, "CLIP"],
+                [4, 3, 0, 6, 1, "CONDITIONING"],
+                [5, 4, 0, 6, 2, "CONDITIONING"],
+                [6, 5, 0, 6, 3, "LATENT"]
+            ]
+        });
+
+        let api = ui_workflow_to_api(&workflow);
+        assert_eq!(api["6"]["inputs"]["steps"], 20);
+        assert_eq!(api["6"]["inputs"]["model"], json!(["1", 0]));
+        assert_eq!(api["7"]["inputs"], json!({}));
+
+        let parsed = parse(&[
+            text_chunk("workflow", &workflow),
+            text_chunk("parameters", "ignored\nSteps: 1"),
+        ]);
+        assert_eq!(parsed.source, "comfyui_workflow");
+        assert_eq!(parsed.found, ["comfyui_workflow", "a1111"]);
+        let request = parsed.request;
+        assert_eq!(request.prompt, "1girl");
+        assert_eq!(request.negative_prompt, "lowres");
+        assert_eq!(request.seed, 5);
+        assert_eq!(request.steps, 20);
+        assert_eq!(request.cfg, 5.5);
+        assert_eq!(request.scheduler, "simple");
+        assert_eq!((request.width, request.height), (1024, 1536));
+        let models = parsed.models.unwrap();
+        assert_eq!(models.unet, "newbie-image.safetensors");
+        assert_eq!(models.clip1, "gemma.safetensors");
+    }
+
+    #[test]
+    fn sources_are_tried_in_priority_order() {
+        let mut chunks = parameters().to_chunks();
+        chunks.push(text_chunk("prompt", hires_prompt_graph()));
+        let parsed = parse(&chunks);
+        assert_eq!(parsed.source, "newbie");
+        assert_eq!(parsed.found, ["newbie", "comfyui_prompt"]);
+
+        assert!(parse_parameters(&[text_chunk("Software", "GIMP")], &template()).is_none());
+    }
+}
@@ -1,6 +1,12 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+use crate::error::{AppError, AppResult, FieldError};
+use crate::history::GenerationRecord;
+use crate::jobs::{Job, JobPriority};
+use crate::seed::{ImageSeed, SeedMode};
+use crate::xml_prompt::StructuredPrompt;
+
 // ============================================================================
 // Request Models (from frontend)
 // ============================================================================
@@ -9,7 +15,11 @@ use std::collections::HashMap;
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GenerateRequest {
     /// Positive prompt text
+    #[serde(default)]
     pub prompt: String,
+    /// Characters and scene tags serialized to NewBie XML as the prompt, instead of `prompt`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub structured_prompt: Option<StructuredPrompt>,
     /// Negative prompt text
     #[serde(default)]
     pub negative_prompt: String,
@@ -28,6 +38,9 @@ pub struct GenerateRequest {
     /// Random seed (-1 for random)
     #[serde(default = "default_seed")]
     pub seed: i64,
+    /// How the seed is chosen (fixed for seed >= 0, random for -1 when omitted)
+    #[serde(default)]
+    pub seed_mode: Option<SeedMode>,
     /// Sampler name
     #[serde(default = "default_sampler")]
     pub sampler_name: String,
@@ -40,6 +53,99 @@ pub struct GenerateRequest {
     /// Batch size
     #[serde(default = "default_batch_size")]
     pub batch_size: u32,
+    /// Workflow template name (server default when omitted)
+    #[serde(default)]
+    pub template: Option<String>,
+    /// Diffusion model file, found by name among the installed ones when omitted
+    #[serde(default)]
+    pub unet: Option<String>,
+    /// First text encoder (Gemma 3), found by name when omitted
+    #[serde(default)]
+    pub clip1: Option<String>,
+    /// Second text encoder (Jina CLIP), found by name when omitted
+    #[serde(default)]
+    pub clip2: Option<String>,
+    /// VAE file, found by name when omitted
+    #[serde(default)]
+    pub vae: Option<String>,
+    /// UNETLoader weight dtype (`default`, `fp8_e4m3fn`, ...), the template's when omitted
+    #[serde(default)]
+    pub weight_dtype: Option<String>,
+    /// LoRAs chained between the model loader and the sampler, in order
+    #[serde(default)]
+    pub loras: Vec<LoraRequest>,
+    /// Optional second "hires fix" pass
+    #[serde(default)]
+    pub hires: Option<HiresRequest>,
+    /// Priority in the backend job queue
+    #[serde(default)]
+    pub priority: JobPriority,
+    /// WebSocket client (from the `connected` message) that receives the job's events
+    #[serde(default, skip_serializing)]
+    pub client_id: Option<String>,
+}
+
+/// A LoRA applied to the diffusion model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoraRequest {
+    /// LoRA file name as listed by ComfyUI
+    pub name: String,
+    /// Model strength
+    #[serde(default = "default_lora_strength")]
+    pub strength_model: f32,
+}
+
+impl Default for GenerateRequest {
+    fn default() -> Self {
+        Self {
+            prompt: String::new(),
+            structured_prompt: None,
+            negative_prompt: String::new(),
+            width: default_width(),
+            height: default_height(),
+            steps: default_steps(),
+            cfg: default_cfg(),
+            seed: default_seed(),
+            seed_mode: None,
+            sampler_name: default_sampler(),
+            scheduler: default_scheduler(),
+            denoise: default_denoise(),
+            batch_size: default_batch_size(),
+            template: None,
+            unet: None,
+            clip1: None,
+            clip2: None,
+            vae: None,
+            weight_dtype: None,
+            loras: Vec::new(),
+            hires: None,
+            priority: JobPriority::default(),
+            client_id: None,
+        }
+    }
+}
+
+impl GenerateRequest {
+    /// Replace a structured prompt by its XML text in `prompt`
+    ///
+    /// History, PNG metadata and search then see the text that was actually submitted.
+    pub fn apply_structured_prompt(&mut self) -> AppResult<()> {
+        let Some(structured) = self.structured_prompt.take() else {
+            return Ok(());
+        };
+        if !self.prompt.trim().is_empty() {
+            return Err(AppError::InvalidRequest(
+                "Give either 'prompt' or 'structured_prompt', not both".to_string(),
+            ));
+        }
+        if structured.is_empty() {
+            return Err(AppError::InvalidRequest(
+                "Structured prompt has no characters, tags or caption".to_string(),
+            ));
+        }
+        self.prompt = structured.to_xml();
+        Ok(())
+    }
 }
 
 fn default_width() -> u32 {
@@ -69,16 +175,198 @@ fn default_denoise() -> f32 {
 fn default_batch_size() -> u32 {
     1
 }
+fn default_lora_strength() -> f32 {
+    1.0
+}
+
+/// Source image for img2img, already uploaded to ComfyUI's input folder
+#[derive(Debug, Clone)]
+pub struct SourceImage {
+    /// Name as accepted by LoadImage (`subfolder/filename`)
+    pub image: String,
+    /// Scale the source to the requested width/height before encoding
+    pub resize: bool,
+    /// Inpainting mask; only the masked area is regenerated
+    pub mask: Option<InpaintMask>,
+}
+
+/// Inpainting mask settings
+#[derive(Debug, Clone)]
+pub struct InpaintMask {
+    pub source: MaskSource,
+    pub mode: InpaintMode,
+    /// Pixels to grow the mask by before sampling
+    pub grow: u32,
+}
+
+/// Where the inpainting mask comes from
+#[derive(Debug, Clone)]
+pub enum MaskSource {
+    /// Transparent pixels of the source image
+    Alpha,
+    /// A separately uploaded mask image, read from one channel
+    Image { image: String, channel: String },
+}
+
+/// How the masked latent is prepared
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InpaintMode {
+    /// VAEEncodeForInpaint: masked area is blanked, best with denoise 1.0
+    VaeEncodeForInpaint,
+    /// VAEEncode + SetLatentNoiseMask: keeps the original content under the mask
+    LatentNoiseMask,
+}
+
+/// Two-pass hires fix: upscale the first pass, then refine it with a second sampler
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HiresRequest {
+    /// Upscale factor applied to width and height
+    #[serde(default = "default_hires_scale")]
+    pub scale: f32,
+    /// How the first pass is upscaled
+    #[serde(default)]
+    pub method: HiresMethod,
+    /// Latent upscale interpolation (latent method)
+    #[serde(default = "default_latent_upscale_method")]
+    pub latent_upscale_method: String,
+    /// Upscale model file (model method)
+    #[serde(default)]
+    pub upscale_model: Option<String>,
+    /// Second pass sampling steps
+    #[serde(default = "default_hires_steps")]
+    pub steps: u32,
+    /// Second pass denoise strength
+    #[serde(default = "default_hires_denoise")]
+    pub denoise: f32,
+}
+
+/// Hires upscale method
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HiresMethod {
+    /// LatentUpscaleBy on the first pass latent
+    #[default]
+    Latent,
+    /// Decode, ImageUpscaleWithModel, rescale to target size and re-encode
+    Model,
+}
+
+fn default_hires_scale() -> f32 {
+    2.0
+}
+fn default_latent_upscale_method() -> String {
+    "nearest-exact".to_string()
+}
+fn default_hires_steps() -> u32 {
+    14
+}
+fn default_hires_denoise() -> f32 {
+    0.5
+}
+
+/// Standalone upscale of an existing ComfyUI image
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpscaleRequest {
+    /// Image filename, as used by `/api/images/{filename}`
+    pub filename: String,
+    /// Image subfolder
+    #[serde(default)]
+    pub subfolder: String,
+    /// Image folder type (output, input or temp)
+    #[serde(rename = "type", default = "default_image_type")]
+    pub image_type: String,
+    /// Upscale model file name
+    pub upscale_model: String,
+    /// Optional factor applied after the model upscale (e.g. 0.5 to turn 4x into 2x)
+    #[serde(default)]
+    pub rescale: Option<f32>,
+    /// Priority in the backend job queue
+    #[serde(default)]
+    pub priority: JobPriority,
+    /// ComfyUI instance holding the image, looked up in the history when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    /// WebSocket client that receives the job's events
+    #[serde(default, skip_serializing)]
+    pub client_id: Option<String>,
+}
+
+fn default_image_type() -> String {
+    "output".to_string()
+}
+
+/// X/Y/Z parameter sweep: every combination of the axis values applied to `base`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SweepRequest {
+    /// Parameters shared by every cell
+    pub base: GenerateRequest,
+    /// X, Y and Z axes in that order (one to three)
+    pub axes: Vec<SweepAxis>,
+}
+
+/// One axis of a sweep and the values it takes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "param", rename_all = "snake_case")]
+pub enum SweepAxis {
+    Cfg {
+        values: Vec<f32>,
+    },
+    Steps {
+        values: Vec<u32>,
+    },
+    Sampler {
+        values: Vec<String>,
+    },
+    Scheduler {
+        values: Vec<String>,
+    },
+    Seed {
+        values: Vec<i64>,
+    },
+    Denoise {
+        values: Vec<f32>,
+    },
+    /// Replace `search` in both prompts with each value
+    Prompt {
+        search: String,
+        values: Vec<String>,
+    },
+}
 
 // ============================================================================
 // Response Models (to frontend)
 // ============================================================================
 
+/// Model files a workflow was built with
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResolvedModels {
+    pub unet: String,
+    pub clip1: String,
+    pub clip2: String,
+    pub vae: String,
+}
+
+/// A workflow ready to queue plus the values resolved while building it
+#[derive(Debug, Clone)]
+pub struct BuiltWorkflow {
+    pub workflow: serde_json::Value,
+    pub seed: u64,
+    pub models: ResolvedModels,
+}
+
 /// Queue response after submitting a prompt
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueueResponse {
+    /// Job id, also used as the ComfyUI prompt_id
     pub prompt_id: String,
+    /// Position in the backend job queue when submitted (1 = next)
     pub number: u32,
+    /// Seed actually used (random and sequence seeds resolved)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// Seed and batch index of every image in the batch
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub image_seeds: Vec<ImageSeed>,
 }
 
 /// Generation progress update
@@ -121,6 +409,9 @@ pub struct ComfyUIPromptRequest {
     pub prompt: serde_json::Value,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub client_id: Option<String>,
+    /// Id to run the prompt under instead of one chosen by ComfyUI
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_id: Option<String>,
 }
 
 /// ComfyUI queue prompt response
@@ -147,6 +438,34 @@ pub struct PromptHistory {
     pub status: PromptStatus,
 }
 
+impl PromptHistory {
+    /// Images saved to the output folder (previews excluded)
+    pub fn output_images(&self) -> Vec<ImageResult> {
+        self.outputs
+            .values()
+            .flat_map(|output| output.images.iter())
+            .filter(|img| img.image_type == "output")
+            .map(|img| ImageResult {
+                filename: img.filename.clone(),
+                subfolder: img.subfolder.clone(),
+                image_type: img.image_type.clone(),
+            })
+            .collect()
+    }
+
+    /// Whether ComfyUI reported the execution as failed
+    pub fn failed(&self) -> bool {
+        self.status.status_str.as_deref() == Some("error")
+    }
+
+    /// Whether the execution was stopped by an interrupt, which ComfyUI also reports as failed
+    pub fn interrupted(&self) -> bool {
+        self.status.messages.iter().flatten().any(|message| {
+            message.get(0).and_then(|kind| kind.as_str()) == Some("execution_interrupted")
+        })
+    }
+}
+
 /// Prompt execution status
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct PromptStatus {
@@ -162,6 +481,27 @@ pub struct NodeOutput {
     pub images: Vec<GeneratedImage>,
 }
 
+/// ComfyUI image upload response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadedImage {
+    pub name: String,
+    #[serde(default)]
+    pub subfolder: String,
+    #[serde(rename = "type", default)]
+    pub image_type: String,
+}
+
+impl UploadedImage {
+    /// Name to use for the LoadImage `image` input
+    pub fn load_image_name(&self) -> String {
+        if self.subfolder.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{}/{}", self.subfolder, self.name)
+        }
+    }
+}
+
 /// ComfyUI system stats
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SystemStats {
@@ -195,12 +535,34 @@ pub struct QueueStatus {
     pub queue_pending: Vec<serde_json::Value>,
 }
 
+impl QueueStatus {
+    /// Whether `prompt_id` is executing
+    pub fn is_running(&self, prompt_id: &str) -> bool {
+        self.queue_running
+            .iter()
+            .any(|item| item_prompt_id(item) == Some(prompt_id))
+    }
+
+    /// Whether `prompt_id` is executing or waiting
+    pub fn contains(&self, prompt_id: &str) -> bool {
+        self.is_running(prompt_id)
+            || self
+                .queue_pending
+                .iter()
+                .any(|item| item_prompt_id(item) == Some(prompt_id))
+    }
+}
+
+/// Queue items are `[number, prompt_id, prompt, extra_data, outputs]`
+fn item_prompt_id(item: &serde_json::Value) -> Option<&str> {
+    item.get(1).and_then(|v| v.as_str())
+}
+
 // ============================================================================
 // WebSocket Message Models
 // ============================================================================
 
 /// WebSocket message types from ComfyUI
-#[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", content = "data")]
 pub enum ComfyUIMessage {
@@ -213,6 +575,7 @@ pub enum ComfyUIMessage {
         nodes: Vec<String>,
         prompt_id: String,
     },
+    /// `node` is `None` once the prompt finished executing
     #[serde(rename = "executing")]
     Executing {
         node: Option<String>,
@@ -231,6 +594,14 @@ pub enum ComfyUIMessage {
         output: NodeOutput,
         prompt_id: String,
     },
+    #[serde(rename = "execution_success")]
+    ExecutionSuccess { prompt_id: String },
+    #[serde(rename = "execution_interrupted")]
+    ExecutionInterrupted {
+        prompt_id: String,
+        node_id: Option<String>,
+        node_type: Option<String>,
+    },
     #[serde(rename = "execution_error")]
     ExecutionError {
         prompt_id: String,
@@ -240,13 +611,129 @@ pub enum ComfyUIMessage {
     },
 }
 
-#[allow(dead_code)]
+impl ComfyUIMessage {
+    /// Values of `type` parsed into a variant
+    pub const TYPES: &'static [&'static str] = &[
+        "status",
+        "execution_start",
+        "execution_cached",
+        "executing",
+        "progress",
+        "executed",
+        "execution_success",
+        "execution_interrupted",
+        "execution_error",
+    ];
+}
+
+/// Binary WebSocket frame from ComfyUI: a big-endian u32 event type, then its payload
+#[derive(Debug, Clone)]
+pub enum ComfyUIBinaryMessage {
+    /// Latent preview of the running prompt
+    Preview(PreviewImage),
+    /// Event types without a use here (e.g. progress text, unencoded previews)
+    Other(u32),
+}
+
+/// A decoded preview image
+#[derive(Debug, Clone)]
+pub struct PreviewImage {
+    pub mime_type: &'static str,
+    /// Prompt and node the preview belongs to, sent by newer ComfyUI versions only
+    pub prompt_id: Option<String>,
+    pub node_id: Option<String>,
+    pub data: Vec<u8>,
+}
+
+/// Metadata of a `PREVIEW_IMAGE_WITH_METADATA` frame
+#[derive(Debug, Deserialize)]
+struct PreviewMetadata {
+    prompt_id: Option<String>,
+    node_id: Option<String>,
+    image_type: Option<String>,
+}
+
+impl ComfyUIBinaryMessage {
+    const PREVIEW_IMAGE: u32 = 1;
+    const PREVIEW_IMAGE_WITH_METADATA: u32 = 4;
+
+    /// Parse a binary frame, `None` when it is truncated or malformed
+    pub fn parse(frame: &[u8]) -> Option<Self> {
+        let (event, payload) = split_u32(frame)?;
+        match event {
+            // [format: u32 (1 = JPEG, 2 = PNG)][image]
+            Self::PREVIEW_IMAGE => {
+                let (format, data) = split_u32(payload)?;
+                let mime_type = match format {
+                    1 => "image/jpeg",
+                    2 => "image/png",
+                    _ => return None,
+                };
+                Some(Self::Preview(PreviewImage::new(
+                    mime_type, None, None, data,
+                )))
+            }
+            // [metadata length: u32][metadata JSON][image]
+            Self::PREVIEW_IMAGE_WITH_METADATA => {
+                let (len, rest) = split_u32(payload)?;
+                let len = usize::try_from(len).ok().filter(|len| *len <= rest.len())?;
+                let (metadata, data) = rest.split_at(len);
+                let metadata: PreviewMetadata = serde_json::from_slice(metadata).ok()?;
+                let mime_type = match metadata.image_type.as_deref() {
+                    Some("image/png") => "image/png",
+                    _ => "image/jpeg",
+                };
+                Some(Self::Preview(PreviewImage::new(
+                    mime_type,
+                    metadata.prompt_id,
+                    metadata.node_id,
+                    data,
+                )))
+            }
+            other => Some(Self::Other(other)),
+        }
+    }
+}
+
+impl PreviewImage {
+    fn new(
+        declared: &'static str,
+        prompt_id: Option<String>,
+        node_id: Option<String>,
+        data: &[u8],
+    ) -> Self {
+        Self {
+            // The image's own signature wins over the header
+            mime_type: sniff_image_type(data).unwrap_or(declared),
+            prompt_id,
+            node_id,
+            data: data.to_vec(),
+        }
+    }
+}
+
+fn split_u32(data: &[u8]) -> Option<(u32, &[u8])> {
+    let (head, rest) = data.split_first_chunk::<4>()?;
+    Some((u32::from_be_bytes(*head), rest))
+}
+
+fn sniff_image_type(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StatusData {
     pub exec_info: ExecInfo,
 }
 
-#[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExecInfo {
     pub queue_remaining: u32,
@@ -267,6 +754,9 @@ pub enum FrontendMessage {
         prompt_id: String,
         queue_position: u32,
     },
+    /// Handed to a ComfyUI instance
+    #[serde(rename = "submitted")]
+    Submitted { prompt_id: String, instance: String },
     #[serde(rename = "started")]
     Started { prompt_id: String },
     #[serde(rename = "progress")]
@@ -281,19 +771,154 @@ pub enum FrontendMessage {
     Preview {
         prompt_id: String,
         image_data: String,
+        /// Node that produced the preview, when ComfyUI reports it
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        node: Option<String>,
     },
     #[serde(rename = "completed")]
     Completed {
         prompt_id: String,
         images: Vec<ImageResult>,
+        /// Seed the images were generated with, when known
+        #[serde(skip_serializing_if = "Option::is_none")]
+        seed: Option<u64>,
+    },
+    /// Nodes whose outputs were reused from ComfyUI's cache
+    #[serde(rename = "cached")]
+    Cached {
+        prompt_id: String,
+        nodes: Vec<String>,
+    },
+    /// Node now executing, `None` once every node ran
+    #[serde(rename = "executing")]
+    Executing {
+        prompt_id: String,
+        node: Option<String>,
+    },
+    /// Every node of the prompt ran
+    #[serde(rename = "success")]
+    Success { prompt_id: String },
+    /// Execution was stopped by an interrupt
+    #[serde(rename = "interrupted")]
+    Interrupted {
+        prompt_id: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        node_id: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        node_type: Option<String>,
     },
     #[serde(rename = "error")]
     Error {
         prompt_id: Option<String>,
         message: String,
+        /// Node that failed, for execution errors reported by ComfyUI
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        node_id: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        node_type: Option<String>,
     },
+    #[serde(rename = "cancelled")]
+    Cancelled { prompt_id: String },
     #[serde(rename = "queue_status")]
     QueueStatus { running: u32, pending: u32 },
+    #[serde(rename = "sweep_progress")]
+    SweepProgress {
+        sweep_id: String,
+        completed: usize,
+        total: usize,
+    },
+    #[serde(rename = "sweep_completed")]
+    SweepCompleted {
+        sweep_id: String,
+        /// Contact sheet URL, absent when the sweep failed
+        #[serde(skip_serializing_if = "Option::is_none")]
+        grid_url: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// Reply to a client message that succeeded
+    #[serde(rename = "ack")]
+    Ack {
+        request_id: Option<String>,
+        result: serde_json::Value,
+    },
+    #[serde(rename = "pong")]
+    Pong { request_id: Option<String> },
+    /// Current state of the client's jobs, sent on connect and after it fell behind on events
+    #[serde(rename = "snapshot")]
+    Snapshot {
+        reason: SnapshotReason,
+        /// Jobs waiting in the backend queue, in submission order
+        pending: Vec<Job>,
+        /// Jobs handed to ComfyUI, running ones with their last progress
+        active: Vec<Job>,
+        /// Most recently completed generations, newest first
+        recent: Vec<GenerationRecord>,
+    },
+    /// Reply to a client message that failed, with the HTTP status the REST API would use
+    #[serde(rename = "request_error")]
+    RequestError {
+        request_id: Option<String>,
+        status: u16,
+        message: String,
+        /// Rejected fields, for validation errors (status 422)
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        fields: Vec<FieldError>,
+    },
+}
+
+impl FrontendMessage {
+    /// Prompt the message is about, if any
+    pub fn prompt_id(&self) -> Option<&str> {
+        match self {
+            FrontendMessage::Queued { prompt_id, .. }
+            | FrontendMessage::Submitted { prompt_id, .. }
+            | FrontendMessage::Started { prompt_id }
+            | FrontendMessage::Progress { prompt_id, .. }
+            | FrontendMessage::Preview { prompt_id, .. }
+            | FrontendMessage::Completed { prompt_id, .. }
+            | FrontendMessage::Cancelled { prompt_id }
+            | FrontendMessage::Cached { prompt_id, .. }
+            | FrontendMessage::Executing { prompt_id, .. }
+            | FrontendMessage::Success { prompt_id }
+            | FrontendMessage::Interrupted { prompt_id, .. } => Some(prompt_id),
+            FrontendMessage::Error { prompt_id, .. } => prompt_id.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+/// Why a `snapshot` was sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SnapshotReason {
+    /// The client (re)connected
+    Connect,
+    /// Events were dropped because the client fell behind; the snapshot replaces them
+    Resync,
+}
+
+/// Messages a frontend sends via WebSocket
+///
+/// Any message may carry a `request_id` of the client's choosing, which is echoed in
+/// the `ack`, `pong` or `request_error` reply.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type")]
+pub enum ClientMessage {
+    /// Queue a text-to-image generation, as `POST /api/generate`
+    #[serde(rename = "generate")]
+    Generate { request: Box<GenerateRequest> },
+    /// Cancel a queued or running job, as `POST /api/jobs/{id}/cancel`
+    #[serde(rename = "cancel")]
+    Cancel { prompt_id: String },
+    /// Receive the events of a prompt submitted by another client
+    #[serde(rename = "subscribe")]
+    Subscribe { prompt_id: String },
+    /// Stop receiving the events of a prompt
+    #[serde(rename = "unsubscribe")]
+    Unsubscribe { prompt_id: String },
+    #[serde(rename = "ping")]
+    Ping,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -308,11 +933,13 @@ pub struct ImageResult {
 // Available Models
 // ============================================================================
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct AvailableModels {
     pub unet: Vec<String>,
     pub clip: Vec<String>,
     pub vae: Vec<String>,
+    pub lora: Vec<String>,
+    pub upscale: Vec<String>,
 }
 
 /// Find a model file by keywords (tries each in order, case-insensitive)
@@ -0,0 +1,238 @@
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::error::{AppError, AppResult, FieldError};
+use crate::models::{AvailableModels, GenerateRequest};
+use crate::util::now_millis;
+
+/// Node classes whose inputs the generation parameters are checked against
+const SAMPLER: &str = "KSampler";
+const LATENT: &str = "EmptySD3LatentImage";
+
+/// Latent stride of EmptySD3LatentImage when ComfyUI does not publish a `step`
+const DEFAULT_LATENT_STRIDE: u32 = 16;
+
+/// ComfyUI's `/object_info`: every installed node class with the schema of its inputs
+#[derive(Debug, Clone)]
+pub struct ObjectInfo {
+    nodes: Value,
+    /// Unix timestamp in milliseconds
+    fetched_at: u64,
+}
+
+/// What an instance can run, as listed by `/api/capabilities`
+#[derive(Debug, Clone, Serialize)]
+pub struct Capabilities {
+    #[serde(flatten)]
+    pub models: AvailableModels,
+    /// KSampler `sampler_name` values
+    pub samplers: Vec<String>,
+    /// KSampler `scheduler` values
+    pub schedulers: Vec<String>,
+    /// UNETLoader `weight_dtype` values
+    pub weight_dtypes: Vec<String>,
+    /// Installed node classes, sorted
+    pub node_classes: Vec<String>,
+    /// When ComfyUI was asked, Unix timestamp in milliseconds
+    pub fetched_at: u64,
+}
+
+/// Schema of one node input
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InputSpec {
+    /// Allowed values of a combo input
+    pub options: Option<Vec<String>>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub step: Option<f64>,
+}
+
+impl ObjectInfo {
+    pub fn new(nodes: Value) -> Self {
+        Self {
+            nodes,
+            fetched_at: now_millis(),
+        }
+    }
+
+    /// Installed node class names, sorted
+    pub fn node_classes(&self) -> Vec<String> {
+        let mut classes: Vec<String> = self
+            .nodes
+            .as_object()
+            .map(|nodes| nodes.keys().cloned().collect())
+            .unwrap_or_default();
+        classes.sort();
+        classes
+    }
+
+    /// Schema of a required or optional input, `None` when the node or input is unknown
+    pub fn input(&self, class_type: &str, input: &str) -> Option<InputSpec> {
+        let inputs = self.nodes.get(class_type)?.get("input")?;
+        let spec = ["required", "optional"]
+            .iter()
+            .find_map(|group| inputs.get(group)?.get(input))?;
+
+        // Combos are `[[options...]]`, or `["COMBO", {"options": [...]}]` in newer ComfyUI
+        let first = spec.get(0)?;
+        let settings = spec.get(1);
+        let options = first
+            .as_array()
+            .or_else(|| settings?.get("options")?.as_array())
+            .map(|list| {
+                list.iter()
+                    .filter_map(|v| v.as_str().map(String::from))
+                    .collect()
+            });
+        let number = |key: &str| settings.and_then(|s| s.get(key)).and_then(Value::as_f64);
+
+        Some(InputSpec {
+            options,
+            min: number("min"),
+            max: number("max"),
+            step: number("step"),
+        })
+    }
+
+    /// Option list of a combo input, empty when unknown
+    pub fn options(&self, class_type: &str, input: &str) -> Vec<String> {
+        self.input(class_type, input)
+            .and_then(|spec| spec.options)
+            .unwrap_or_default()
+    }
+
+    /// Model files offered by the loader nodes
+    pub fn models(&self) -> AvailableModels {
+        AvailableModels {
+            unet: self.options("UNETLoader", "unet_name"),
+            clip: self.options("DualCLIPLoader", "clip_name1"),
+            vae: self.options("VAELoader", "vae_name"),
+            lora: self.options("LoraLoaderModelOnly", "lora_name"),
+            upscale: self.options("UpscaleModelLoader", "model_name"),
+        }
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            models: self.models(),
+            samplers: self.options(SAMPLER, "sampler_name"),
+            schedulers: self.options(SAMPLER, "scheduler"),
+            weight_dtypes: self.options("UNETLoader", "weight_dtype"),
+            node_classes: self.node_classes(),
+            fetched_at: self.fetched_at,
+        }
+    }
+
+    /// Check the sampler and latent parameters of a request against the published schema
+    ///
+    /// Inputs ComfyUI does not describe are not checked.
+    pub fn validate(&self, request: &GenerateRequest) -> AppResult<()> {
+        // Request fields are named after the node inputs they feed
+        let mut checks = vec![
+            ("steps", SAMPLER, Param::Number(request.steps as f64)),
+            ("cfg", SAMPLER, Param::Number(request.cfg as f64)),
+            ("sampler_name", SAMPLER, Param::Text(&request.sampler_name)),
+            ("scheduler", SAMPLER, Param::Text(&request.scheduler)),
+            ("denoise", SAMPLER, Param::Number(request.denoise as f64)),
+            (
+                "batch_size",
+                LATENT,
+                Param::Number(request.batch_size as f64),
+            ),
+        ];
+        if let Some(weight_dtype) = &request.weight_dtype {
+            checks.push(("weight_dtype", "UNETLoader", Param::Text(weight_dtype)));
+        }
+        if let Some(hires) = &request.hires {
+            checks.push(("hires.steps", SAMPLER, Param::Number(hires.steps as f64)));
+            checks.push((
+                "hires.denoise",
+                SAMPLER,
+                Param::Number(hires.denoise as f64),
+            ));
+        }
+        let mut errors: Vec<FieldError> = checks
+            .iter()
+            .filter_map(|(field, class_type, value)| {
+                let input = field.rsplit('.').next().unwrap_or(field);
+                self.input(class_type, input)?.check(field, value)
+            })
+            .collect();
+
+        for (field, value) in [("width", request.width), ("height", request.height)] {
+            let Some(spec) = self.input(LATENT, field) else {
+                continue;
+            };
+            let stride = spec
+                .step
+                .map(|step| step as u32)
+                .filter(|step| *step > 1)
+                .unwrap_or(DEFAULT_LATENT_STRIDE);
+            let spec = InputSpec {
+                step: Some(stride as f64),
+                ..spec
+            };
+            if let Some(error) = spec.check(field, &Param::Number(value as f64)) {
+                errors.push(error);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(AppError::Validation(errors))
+        }
+    }
+}
+
+/// A request value checked against an [`InputSpec`]
+enum Param<'a> {
+    Number(f64),
+    Text(&'a str),
+}
+
+impl InputSpec {
+    fn check(&self, field: &str, value: &Param) -> Option<FieldError> {
+        match value {
+            Param::Text(text) => {
+                let options = self.options.as_ref()?;
+                (!options.iter().any(|o| o == text)).then(|| FieldError {
+                    message: format!("'{}' is not one of the values ComfyUI accepts", text),
+                    allowed: Some(options.clone()),
+                    ..FieldError::new(field)
+                })
+            }
+            Param::Number(number) => {
+                let below = self.min.is_some_and(|min| *number < min);
+                let above = self.max.is_some_and(|max| *number > max);
+                let off_step = self
+                    .step
+                    .filter(|step| *step >= 1.0)
+                    .is_some_and(|step| number % step != 0.0);
+                if !number.is_finite() || below || above {
+                    let message = match (self.min, self.max) {
+                        (Some(min), Some(max)) => format!("must be between {} and {}", min, max),
+                        (Some(min), None) => format!("must be at least {}", min),
+                        (None, Some(max)) => format!("must be at most {}", max),
+                        (None, None) => "must be a finite number".to_string(),
+                    };
+                    Some(FieldError {
+                        message,
+                        min: self.min,
+                        max: self.max,
+                        ..FieldError::new(field)
+                    })
+                } else if off_step {
+                    let step = self.step.unwrap_or_default();
+                    Some(FieldError {
+                        message: format!("must be a multiple of {}", step),
+                        multiple_of: Some(step),
+                        ..FieldError::new(field)
+                    })
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,413 @@
+use futures::future::join_all;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::comfyui::{AvailableModels, ComfyUIClient};
+use crate::config::Config;
+use crate::error::{AppError, AppResult};
+use crate::models::{PromptHistory, QueueStatus, SystemStats, UploadedImage};
+use crate::object_info::ObjectInfo;
+use crate::util::now_millis;
+
+/// Consecutive failed probes before an instance is taken out of rotation
+const FAILURES_BEFORE_DOWN: u32 = 2;
+
+// ============================================================================
+// Instances
+// ============================================================================
+
+/// Health and load of an instance as of its last probe
+#[derive(Debug, Clone, Serialize)]
+pub struct InstanceStatus {
+    pub name: String,
+    pub url: String,
+    pub healthy: bool,
+    /// Prompts running and waiting in the instance's own queue
+    pub running: usize,
+    pub pending: usize,
+    /// VRAM of the first device in bytes
+    pub vram_free: Option<u64>,
+    pub vram_total: Option<u64>,
+    pub last_error: Option<String>,
+    /// Unix timestamp in milliseconds
+    pub checked_at: Option<u64>,
+}
+
+struct Health {
+    healthy: bool,
+    failures: u32,
+    queue: Option<QueueStatus>,
+    stats: Option<SystemStats>,
+    last_error: Option<String>,
+    checked_at: Option<u64>,
+}
+
+/// One ComfyUI server of the pool
+#[derive(Clone)]
+pub struct ComfyUIInstance {
+    pub name: String,
+    pub client: ComfyUIClient,
+    /// client_id this backend uses on the instance's WebSocket
+    pub client_id: String,
+    health: Arc<RwLock<Health>>,
+}
+
+impl ComfyUIInstance {
+    fn new(name: String, client: ComfyUIClient) -> Self {
+        Self {
+            name,
+            client,
+            client_id: uuid::Uuid::new_v4().to_string(),
+            // Assumed up until a probe says otherwise
+            health: Arc::new(RwLock::new(Health {
+                healthy: true,
+                failures: 0,
+                queue: None,
+                stats: None,
+                last_error: None,
+                checked_at: None,
+            })),
+        }
+    }
+
+    pub async fn is_healthy(&self) -> bool {
+        self.health.read().await.healthy
+    }
+
+    /// The instance's queue as of the last probe
+    pub async fn queue(&self) -> Option<QueueStatus> {
+        self.health.read().await.queue.clone()
+    }
+
+    pub async fn status(&self) -> InstanceStatus {
+        let health = self.health.read().await;
+        let device = health.stats.as_ref().and_then(|s| s.devices.first());
+        InstanceStatus {
+            name: self.name.clone(),
+            url: self.client.get_url().await,
+            healthy: health.healthy,
+            running: health.queue.as_ref().map_or(0, |q| q.queue_running.len()),
+            pending: health.queue.as_ref().map_or(0, |q| q.queue_pending.len()),
+            vram_free: device.map(|d| d.vram_free),
+            vram_total: device.map(|d| d.vram_total),
+            last_error: health.last_error.clone(),
+            checked_at: health.checked_at,
+        }
+    }
+
+    /// Take the instance out of rotation after a failed request, returning whether it was up
+    pub async fn mark_down(&self, error: &AppError) -> bool {
+        let mut health = self.health.write().await;
+        health.failures = health.failures.max(FAILURES_BEFORE_DOWN);
+        health.last_error = Some(error.to_string());
+        health.queue = None;
+        let was_healthy = std::mem::replace(&mut health.healthy, false);
+        if was_healthy {
+            tracing::warn!("ComfyUI instance {} is down: {}", self.name, error);
+        }
+        was_healthy
+    }
+
+    /// Probe the instance, returning its new health when it changed
+    async fn check(&self) -> Option<bool> {
+        let result = self.client.probe().await;
+        let mut health = self.health.write().await;
+        health.checked_at = Some(now_millis());
+
+        match result {
+            Ok((queue, stats)) => {
+                health.failures = 0;
+                health.queue = Some(queue);
+                health.stats = Some(stats);
+                health.last_error = None;
+                if health.healthy {
+                    return None;
+                }
+                health.healthy = true;
+                tracing::info!("ComfyUI instance {} is back up", self.name);
+                // It may have been restarted with other nodes or models
+                self.client.invalidate_object_info().await;
+                Some(true)
+            }
+            Err(e) => {
+                health.failures += 1;
+                health.last_error = Some(e.to_string());
+                if !health.healthy || health.failures < FAILURES_BEFORE_DOWN {
+                    return None;
+                }
+                health.healthy = false;
+                health.queue = None;
+                tracing::warn!("ComfyUI instance {} is down: {}", self.name, e);
+                Some(false)
+            }
+        }
+    }
+
+    /// Balancing key: queued prompts (at least our own in-flight jobs), then most free VRAM
+    async fn load(&self, in_flight: usize) -> (usize, Reverse<u64>) {
+        let health = self.health.read().await;
+        let queued = health
+            .queue
+            .as_ref()
+            .map_or(0, |q| q.queue_running.len() + q.queue_pending.len());
+        let vram_free = health
+            .stats
+            .as_ref()
+            .and_then(|s| s.devices.first())
+            .map_or(0, |d| d.vram_free);
+        (queued.max(in_flight), Reverse(vram_free))
+    }
+}
+
+/// An instance that went down or came back during a round of probes
+pub struct HealthChange {
+    pub instance: String,
+    pub healthy: bool,
+}
+
+// ============================================================================
+// Pool
+// ============================================================================
+
+/// The ComfyUI instances jobs are spread over
+#[derive(Clone)]
+pub struct ComfyUIPool {
+    instances: Arc<Vec<ComfyUIInstance>>,
+}
+
+impl ComfyUIPool {
+    pub fn new(config: Arc<Config>) -> Self {
+        let instances = config
+            .comfyui_instances
+            .iter()
+            .map(|instance| {
+                ComfyUIInstance::new(
+                    instance.name.clone(),
+                    ComfyUIClient::new(config.clone(), &instance.url),
+                )
+            })
+            .collect();
+        Self {
+            instances: Arc::new(instances),
+        }
+    }
+
+    pub fn instances(&self) -> &[ComfyUIInstance] {
+        &self.instances
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ComfyUIInstance> {
+        self.instances.iter().find(|i| i.name == name)
+    }
+
+    /// Client of the first instance, for work that does not depend on a server
+    /// (building workflows, backend settings)
+    pub fn client(&self) -> &ComfyUIClient {
+        &self.instances[0].client
+    }
+
+    /// Healthy instances in configuration order
+    pub async fn healthy(&self) -> Vec<ComfyUIInstance> {
+        let mut healthy = Vec::new();
+        for instance in self.instances.iter() {
+            if instance.is_healthy().await {
+                healthy.push(instance.clone());
+            }
+        }
+        healthy
+    }
+
+    /// Probe every instance, returning those whose health changed
+    pub async fn check_health(&self) -> Vec<HealthChange> {
+        let results = join_all(self.instances.iter().map(|i| i.check())).await;
+        self.instances
+            .iter()
+            .zip(results)
+            .filter_map(|(instance, change)| {
+                change.map(|healthy| HealthChange {
+                    instance: instance.name.clone(),
+                    healthy,
+                })
+            })
+            .collect()
+    }
+
+    pub async fn statuses(&self) -> Vec<InstanceStatus> {
+        join_all(self.instances.iter().map(|i| i.status())).await
+    }
+
+    /// The least loaded healthy instance among `allowed` (any when empty) that holds fewer
+    /// than `max_in_flight` of our jobs
+    pub async fn select(
+        &self,
+        allowed: &[String],
+        in_flight: &HashMap<String, usize>,
+        max_in_flight: usize,
+    ) -> Option<ComfyUIInstance> {
+        let mut best: Option<((usize, Reverse<u64>), &ComfyUIInstance)> = None;
+        for instance in self.instances.iter() {
+            let ours = in_flight.get(&instance.name).copied().unwrap_or(0);
+            if ours >= max_in_flight
+                || (!allowed.is_empty() && !allowed.contains(&instance.name))
+                || !instance.is_healthy().await
+            {
+                continue;
+            }
+            let load = instance.load(ours).await;
+            if best.as_ref().is_none_or(|(best_load, _)| load < *best_load) {
+                best = Some((load, instance));
+            }
+        }
+        best.map(|(_, instance)| instance.clone())
+    }
+
+    /// Node schemas of the first healthy instance that answers
+    ///
+    /// Instances are expected to have the same nodes and models installed.
+    pub async fn get_object_info(&self) -> AppResult<Arc<ObjectInfo>> {
+        let mut last_error = None;
+        for instance in self.healthy().await {
+            match instance.client.get_object_info().await {
+                Ok(info) => return Ok(info),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(no_instance))
+    }
+
+    /// Refetch the node schemas of every healthy instance, returning the first answer
+    pub async fn refresh_object_info(&self) -> AppResult<Arc<ObjectInfo>> {
+        let healthy = self.healthy().await;
+        let results = join_all(healthy.iter().map(|i| i.client.refresh_object_info())).await;
+        let mut first = None;
+        let mut last_error = None;
+        for (instance, result) in healthy.iter().zip(results) {
+            match result {
+                Ok(info) => {
+                    first.get_or_insert(info);
+                }
+                Err(e) => {
+                    tracing::warn!("Refreshing object_info of {} failed: {}", instance.name, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        first.ok_or_else(|| last_error.unwrap_or_else(no_instance))
+    }
+
+    /// Models of the first healthy instance that answers
+    pub async fn get_available_models(&self) -> AppResult<AvailableModels> {
+        Ok(self.get_object_info().await?.models())
+    }
+
+    /// Upload an image to every healthy instance, returning the upload and the instances
+    /// holding it
+    pub async fn upload_image(
+        &self,
+        data: Vec<u8>,
+        filename: &str,
+    ) -> AppResult<(UploadedImage, Vec<String>)> {
+        let healthy = self.healthy().await;
+        let results = join_all(
+            healthy
+                .iter()
+                .map(|i| i.client.upload_image(data.clone(), filename)),
+        )
+        .await;
+
+        let mut uploaded = None;
+        let mut holders = Vec::new();
+        let mut last_error = None;
+        for (instance, result) in healthy.iter().zip(results) {
+            match result {
+                Ok(image) => {
+                    uploaded.get_or_insert(image);
+                    holders.push(instance.name.clone());
+                }
+                Err(e) => {
+                    tracing::warn!("Upload of {} to {} failed: {}", filename, instance.name, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        match uploaded {
+            Some(image) => Ok((image, holders)),
+            None => Err(last_error.unwrap_or_else(no_instance)),
+        }
+    }
+
+    /// Look a prompt up on the healthy instances, `hint` first
+    pub async fn get_history(
+        &self,
+        prompt_id: &str,
+        hint: Option<&str>,
+    ) -> AppResult<Option<PromptHistory>> {
+        let mut last_error = None;
+        for instance in self.candidates(hint).await {
+            match instance.client.get_history(prompt_id).await {
+                Ok(Some(history)) => return Ok(Some(history)),
+                Ok(None) => last_error = None,
+                Err(e) => last_error = Some(e),
+            }
+        }
+        match last_error {
+            Some(e) => Err(e),
+            None => Ok(None),
+        }
+    }
+
+    /// Fetch an image from the instance that made it, or from any healthy one when unknown
+    ///
+    /// Instances number their outputs independently, so an image of a known instance is never
+    /// looked up elsewhere: another instance may hold a different image of the same name.
+    pub async fn get_image(
+        &self,
+        instance: Option<&str>,
+        filename: &str,
+        subfolder: &str,
+        image_type: &str,
+    ) -> AppResult<Vec<u8>> {
+        if let Some(name) = instance {
+            let instance = self.get(name).ok_or_else(|| {
+                AppError::NotFound(format!("Unknown ComfyUI instance '{}'", name))
+            })?;
+            if !instance.is_healthy().await {
+                return Err(AppError::ComfyUIConnection(format!(
+                    "ComfyUI instance {} holding {} is down",
+                    name, filename
+                )));
+            }
+            return instance
+                .client
+                .get_image(filename, subfolder, image_type)
+                .await;
+        }
+
+        let mut last_error = None;
+        for instance in self.healthy().await {
+            match instance
+                .client
+                .get_image(filename, subfolder, image_type)
+                .await
+            {
+                Ok(data) => return Ok(data),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(no_instance))
+    }
+
+    /// Healthy instances, the hinted one first
+    async fn candidates(&self, hint: Option<&str>) -> Vec<ComfyUIInstance> {
+        let mut candidates = self.healthy().await;
+        candidates.sort_by_key(|i| Some(i.name.as_str()) != hint);
+        candidates
+    }
+}
+
+fn no_instance() -> AppError {
+    AppError::ComfyUIConnection("No ComfyUI instance is available".to_string())
+}
@@ -0,0 +1,220 @@
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::config::Config;
+use crate::events::Outbound;
+use crate::models::PreviewImage;
+
+/// Slowest preview rate a client can ask for, lower rates are raised to it
+const MIN_PREVIEW_FPS: f32 = 0.1;
+
+/// How live previews are resized and paced
+#[derive(Debug, Clone, Copy)]
+pub struct PreviewSettings {
+    /// Previews per second sent to a client at most, 0 disables previews
+    pub max_fps: f32,
+    /// Longest edge of forwarded previews in pixels, 0 keeps ComfyUI's size and format
+    pub max_size: u32,
+    /// JPEG quality of re-encoded previews
+    pub quality: u8,
+}
+
+impl PreviewSettings {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            max_fps: config.preview_max_fps,
+            max_size: config.preview_max_size,
+            quality: config.preview_quality,
+        }
+    }
+
+    /// Shortest gap between two previews sent to a client asking for `requested_fps`,
+    /// `None` when it gets no previews
+    pub fn interval(&self, requested_fps: Option<f32>) -> Option<Duration> {
+        let fps = requested_fps
+            .filter(|fps| fps.is_finite())
+            .map_or(self.max_fps, |fps| fps.min(self.max_fps));
+        (fps > 0.0).then(|| Duration::from_secs_f32(1.0 / fps.max(MIN_PREVIEW_FPS)))
+    }
+
+    /// Downscale a preview to `max_size` and re-encode it as JPEG
+    ///
+    /// Previews that cannot be decoded are passed through unchanged.
+    pub fn shrink(&self, preview: PreviewImage) -> PreviewImage {
+        if self.max_size == 0 {
+            return preview;
+        }
+        let image = match image::load_from_memory(&preview.data) {
+            Ok(image) => image,
+            Err(e) => {
+                tracing::debug!("Preview left as is, decoding failed: {}", e);
+                return preview;
+            }
+        };
+        let image = if image.width().max(image.height()) > self.max_size {
+            image.resize(self.max_size, self.max_size, FilterType::Triangle)
+        } else {
+            image
+        };
+
+        let mut data = Vec::new();
+        let encoder = JpegEncoder::new_with_quality(&mut data, self.quality);
+        if let Err(e) = image.to_rgb8().write_with_encoder(encoder) {
+            tracing::debug!("Preview left as is, encoding failed: {}", e);
+            return preview;
+        }
+        PreviewImage {
+            mime_type: "image/jpeg",
+            data,
+            ..preview
+        }
+    }
+}
+
+/// Paces the previews sent to one client
+///
+/// A preview arriving too early is held back, replacing the one held before it, and goes out
+/// when the interval ends or just before the next event of its prompt.
+pub struct PreviewThrottle {
+    interval: Duration,
+    next: Instant,
+    pending: Option<Outbound>,
+}
+
+impl PreviewThrottle {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            next: Instant::now(),
+            pending: None,
+        }
+    }
+
+    /// The preview if it is due, otherwise it is held back
+    pub fn offer(&mut self, preview: Outbound) -> Option<Outbound> {
+        let now = Instant::now();
+        if now < self.next {
+            self.pending = Some(preview);
+            return None;
+        }
+        self.next = now + self.interval;
+        self.pending = None;
+        Some(preview)
+    }
+
+    /// The held preview once its interval ended; never completes while none is held
+    pub async fn due(&mut self) -> Outbound {
+        if self.pending.is_none() {
+            return std::future::pending().await;
+        }
+        tokio::time::sleep_until(self.next).await;
+        self.next = Instant::now() + self.interval;
+        self.pending.take().expect("held preview")
+    }
+
+    /// The held preview of `prompt_id`, to send ahead of an event of the same prompt
+    pub fn flush(&mut self, prompt_id: Option<&str>) -> Option<Outbound> {
+        let held = self.pending.as_ref()?.prompt_id.as_deref();
+        if prompt_id.is_none() || held != prompt_id {
+            return None;
+        }
+        self.next = Instant::now() + self.interval;
+        self.pending.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preview(prompt_id: &str, step: u32) -> Outbound {
+        Outbound {
+            id: 0,
+            owner: None,
+            prompt_id: Some(prompt_id.to_string()),
+            json: format!("{}:{}", prompt_id, step),
+            linked_json: None,
+        }
+    }
+
+    fn json(preview: Option<Outbound>) -> Option<String> {
+        preview.map(|p| p.json)
+    }
+
+    fn settings(max_fps: f32) -> PreviewSettings {
+        PreviewSettings {
+            max_fps,
+            max_size: 0,
+            quality: 75,
+        }
+    }
+
+    #[test]
+    fn interval_is_capped_by_the_server() {
+        let settings = settings(4.0);
+        assert_eq!(settings.interval(None), Some(Duration::from_millis(250)));
+        assert_eq!(
+            settings.interval(Some(2.0)),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(
+            settings.interval(Some(100.0)),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(
+            settings.interval(Some(f32::NAN)),
+            Some(Duration::from_millis(250))
+        );
+    }
+
+    #[test]
+    fn tiny_or_non_positive_rates() {
+        let settings = settings(4.0);
+        assert_eq!(
+            settings.interval(Some(1e-20)),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(
+            settings.interval(Some(f32::MIN_POSITIVE)),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(settings.interval(Some(0.0)), None);
+        assert_eq!(settings.interval(Some(-1.0)), None);
+        assert_eq!(self::settings(0.0).interval(Some(2.0)), None);
+    }
+
+    #[tokio::test]
+    async fn the_newest_held_preview_goes_out_when_the_interval_ends() {
+        let mut throttle = PreviewThrottle::new(Duration::from_millis(50));
+        assert_eq!(
+            json(throttle.offer(preview("a", 1))).as_deref(),
+            Some("a:1")
+        );
+        assert_eq!(json(throttle.offer(preview("a", 2))), None);
+        assert_eq!(json(throttle.offer(preview("a", 3))), None);
+
+        let started = Instant::now();
+        assert_eq!(throttle.due().await.json, "a:3");
+        assert!(started.elapsed() >= Duration::from_millis(40));
+
+        // Nothing held, nothing due
+        let nothing = tokio::time::timeout(Duration::from_millis(100), throttle.due()).await;
+        assert!(nothing.is_err());
+    }
+
+    #[tokio::test]
+    async fn held_previews_go_out_before_the_next_event_of_their_prompt() {
+        let mut throttle = PreviewThrottle::new(Duration::from_millis(100));
+        throttle.offer(preview("a", 1));
+        throttle.offer(preview("a", 2));
+
+        assert_eq!(json(throttle.flush(Some("b"))), None);
+        assert_eq!(json(throttle.flush(None)), None);
+        assert_eq!(json(throttle.flush(Some("a"))).as_deref(), Some("a:2"));
+        assert_eq!(json(throttle.flush(Some("a"))), None);
+        // The flushed preview counts against the rate
+        assert_eq!(json(throttle.offer(preview("a", 3))), None);
+    }
+}
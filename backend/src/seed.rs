@@ -0,0 +1,251 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::error::{AppError, AppResult};
+use crate::models::GenerateRequest;
+
+/// Exclusive upper bound for generated seeds, kept below 2^53 so JavaScript clients stay exact
+pub const MAX_RANDOM_SEED: u64 = 1_000_000_000_000_000;
+
+/// Forget remembered sequences once this many are tracked
+const MAX_SEQUENCES: usize = 1024;
+
+/// How the seed of a submission is chosen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SeedMode {
+    /// Use `seed` as given
+    Fixed,
+    /// Draw a new seed every time, ignoring `seed`
+    Random,
+    /// Start at `seed` and add one on every repeated submission
+    Increment,
+    /// Start at `seed` and subtract one on every repeated submission
+    Decrement,
+}
+
+impl SeedMode {
+    /// The explicit mode, or fixed/random depending on the sign of `seed`
+    pub fn of(request: &GenerateRequest) -> Self {
+        request.seed_mode.unwrap_or(if request.seed < 0 {
+            SeedMode::Random
+        } else {
+            SeedMode::Fixed
+        })
+    }
+}
+
+/// Seed of one image in a batch
+///
+/// ComfyUI draws the noise of a whole batch from one generator, so an image is reproduced by
+/// the batch seed together with its index.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageSeed {
+    pub seed: u64,
+    pub batch_index: u32,
+}
+
+/// Seeds for each image of a batch generated with `seed`
+pub fn image_seeds(seed: u64, batch_size: u32) -> Vec<ImageSeed> {
+    (0..batch_size.max(1))
+        .map(|batch_index| ImageSeed { seed, batch_index })
+        .collect()
+}
+
+/// A fresh random seed
+pub fn random_seed() -> u64 {
+    rand::thread_rng().gen_range(0..MAX_RANDOM_SEED)
+}
+
+/// Resolves seeds and remembers increment/decrement sequences between submissions
+///
+/// Sequences are keyed by the submitting client (or, without one, the template and prompt)
+/// and the starting seed, so resubmitting the same request continues the sequence and
+/// changing `seed` starts a new one.
+#[derive(Clone, Default)]
+pub struct SeedTracker {
+    sequences: Arc<Mutex<HashMap<SequenceKey, u64>>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct SequenceKey {
+    owner: String,
+    start: i64,
+    up: bool,
+}
+
+impl SequenceKey {
+    fn new(request: &GenerateRequest, up: bool) -> Self {
+        let owner = match &request.client_id {
+            Some(client_id) => format!("client:{}", client_id),
+            None => format!(
+                "prompt:{}\n{}",
+                request.template.as_deref().unwrap_or_default(),
+                request.prompt
+            ),
+        };
+        Self {
+            owner,
+            start: request.seed,
+            up,
+        }
+    }
+}
+
+impl SeedTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolve the seed to use for `request`
+    pub async fn resolve(&self, request: &GenerateRequest) -> AppResult<u64> {
+        let mode = SeedMode::of(request);
+        match mode {
+            SeedMode::Fixed => u64::try_from(request.seed).map_err(|_| {
+                AppError::InvalidRequest("Seed mode 'fixed' requires a seed >= 0".to_string())
+            }),
+            SeedMode::Random => Ok(random_seed()),
+            SeedMode::Increment | SeedMode::Decrement => {
+                let up = mode == SeedMode::Increment;
+                // Random starts stay in the random range, given ones in the range of `seed`
+                let limit = if request.seed < 0 {
+                    MAX_RANDOM_SEED
+                } else {
+                    i64::MAX as u64 + 1
+                };
+                let key = SequenceKey::new(request, up);
+                let mut sequences = self.sequences.lock().await;
+                let seed = match sequences.get(&key) {
+                    Some(&last) if up => (last + 1) % limit,
+                    Some(&last) => last.checked_sub(1).unwrap_or(limit - 1),
+                    None if request.seed < 0 => random_seed(),
+                    None => request.seed as u64,
+                };
+                if sequences.len() >= MAX_SEQUENCES {
+                    sequences.clear();
+                }
+                sequences.insert(key, seed);
+                Ok(seed)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(seed: i64, mode: SeedMode, client_id: &str) -> GenerateRequest {
+        GenerateRequest {
+            seed,
+            seed_mode: Some(mode),
+            client_id: Some(client_id.to_string()),
+            ..GenerateRequest::default()
+        }
+    }
+
+    async fn sequence(tracker: &SeedTracker, request: &GenerateRequest, n: usize) -> Vec<u64> {
+        let mut seeds = Vec::new();
+        for _ in 0..n {
+            seeds.push(tracker.resolve(request).await.unwrap());
+        }
+        seeds
+    }
+
+    #[tokio::test]
+    async fn increment_and_decrement_continue_from_the_start_seed() {
+        let tracker = SeedTracker::new();
+        let up = request(5, SeedMode::Increment, "a");
+        let down = request(5, SeedMode::Decrement, "a");
+
+        assert_eq!(sequence(&tracker, &up, 3).await, [5, 6, 7]);
+        assert_eq!(sequence(&tracker, &down, 3).await, [5, 4, 3]);
+        assert_eq!(sequence(&tracker, &up, 1).await, [8]);
+        // A new start seed starts a new sequence
+        assert_eq!(
+            sequence(&tracker, &request(100, SeedMode::Increment, "a"), 2).await,
+            [100, 101]
+        );
+    }
+
+    #[tokio::test]
+    async fn clients_have_their_own_sequences() {
+        let tracker = SeedTracker::new();
+        let alice = request(0, SeedMode::Increment, "alice");
+        let bob = request(0, SeedMode::Increment, "bob");
+
+        assert_eq!(sequence(&tracker, &alice, 2).await, [0, 1]);
+        assert_eq!(sequence(&tracker, &bob, 2).await, [0, 1]);
+        assert_eq!(sequence(&tracker, &alice, 1).await, [2]);
+
+        // Without a client, the prompt tells submissions apart
+        let anonymous = |prompt: &str| GenerateRequest {
+            client_id: None,
+            prompt: prompt.to_string(),
+            ..request(0, SeedMode::Increment, "")
+        };
+        assert_eq!(sequence(&tracker, &anonymous("cat"), 2).await, [0, 1]);
+        assert_eq!(sequence(&tracker, &anonymous("dog"), 1).await, [0]);
+    }
+
+    #[tokio::test]
+    async fn large_start_seeds_are_not_folded_into_the_random_range() {
+        let tracker = SeedTracker::new();
+        let up = request(2_000_000_000_000_000, SeedMode::Increment, "a");
+        assert_eq!(
+            sequence(&tracker, &up, 2).await,
+            [2_000_000_000_000_000, 2_000_000_000_000_001]
+        );
+    }
+
+    #[tokio::test]
+    async fn sequences_wrap_around() {
+        let tracker = SeedTracker::new();
+        let max = i64::MAX as u64;
+        assert_eq!(
+            sequence(&tracker, &request(i64::MAX, SeedMode::Increment, "a"), 2).await,
+            [max, 0]
+        );
+        assert_eq!(
+            sequence(&tracker, &request(0, SeedMode::Decrement, "a"), 2).await,
+            [0, max]
+        );
+    }
+
+    #[tokio::test]
+    async fn random_starts_stay_in_the_random_range() {
+        let tracker = SeedTracker::new();
+        let up = request(-1, SeedMode::Increment, "a");
+        let seeds = sequence(&tracker, &up, 3).await;
+        assert!(seeds.iter().all(|seed| *seed < MAX_RANDOM_SEED));
+        for pair in seeds.windows(2) {
+            assert_eq!(pair[1], (pair[0] + 1) % MAX_RANDOM_SEED);
+        }
+    }
+
+    #[tokio::test]
+    async fn fixed_seeds_must_not_be_negative() {
+        let tracker = SeedTracker::new();
+        assert_eq!(
+            tracker
+                .resolve(&request(9, SeedMode::Fixed, "a"))
+                .await
+                .unwrap(),
+            9
+        );
+        assert!(tracker
+            .resolve(&request(-1, SeedMode::Fixed, "a"))
+            .await
+            .is_err());
+        assert!(
+            tracker
+                .resolve(&request(-1, SeedMode::Random, "a"))
+                .await
+                .unwrap()
+                < MAX_RANDOM_SEED
+        );
+    }
+}
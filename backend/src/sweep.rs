@@ -0,0 +1,762 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::events::{EventSender, Outbound};
+
+use crate::error::{AppError, AppResult};
+use crate::grid::{render_contact_sheet, ContactSheet, LabelFont};
+use crate::history::GenerationStatus;
+use crate::models::{FrontendMessage, GenerateRequest, ImageResult, SweepAxis, SweepRequest};
+use crate::pool::ComfyUIPool;
+use crate::seed::SeedMode;
+use crate::util::now_millis;
+
+/// Upper bound on the number of combinations in one sweep
+pub const MAX_SWEEP_CELLS: usize = 100;
+
+/// Finished sweeps kept in memory; their contact sheets stay on disk
+const MAX_FINISHED_SWEEPS: usize = 50;
+
+// ============================================================================
+// Axes
+// ============================================================================
+
+impl SweepAxis {
+    /// Request field name, as used in the `param` tag
+    pub fn param(&self) -> &'static str {
+        match self {
+            SweepAxis::Cfg { .. } => "cfg",
+            SweepAxis::Steps { .. } => "steps",
+            SweepAxis::Sampler { .. } => "sampler",
+            SweepAxis::Scheduler { .. } => "scheduler",
+            SweepAxis::Seed { .. } => "seed",
+            SweepAxis::Denoise { .. } => "denoise",
+            SweepAxis::Prompt { .. } => "prompt",
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            SweepAxis::Cfg { values } | SweepAxis::Denoise { values } => values.len(),
+            SweepAxis::Steps { values } => values.len(),
+            SweepAxis::Sampler { values }
+            | SweepAxis::Scheduler { values }
+            | SweepAxis::Prompt { values, .. } => values.len(),
+            SweepAxis::Seed { values } => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Grid label of the value at `index`
+    pub fn label(&self, index: usize) -> String {
+        match self {
+            SweepAxis::Cfg { values } => format!("CFG: {}", values[index]),
+            SweepAxis::Steps { values } => format!("Steps: {}", values[index]),
+            SweepAxis::Sampler { values } => format!("Sampler: {}", values[index]),
+            SweepAxis::Scheduler { values } => format!("Scheduler: {}", values[index]),
+            SweepAxis::Seed { values } => format!("Seed: {}", values[index]),
+            SweepAxis::Denoise { values } => format!("Denoise: {}", values[index]),
+            SweepAxis::Prompt { values, .. } => values[index].clone(),
+        }
+    }
+
+    /// Set the value at `index` on `request`
+    fn apply(&self, request: &mut GenerateRequest, index: usize) {
+        match self {
+            SweepAxis::Cfg { values } => request.cfg = values[index],
+            SweepAxis::Steps { values } => request.steps = values[index],
+            SweepAxis::Sampler { values } => request.sampler_name = values[index].clone(),
+            SweepAxis::Scheduler { values } => request.scheduler = values[index].clone(),
+            SweepAxis::Seed { values } => {
+                request.seed = values[index];
+                request.seed_mode = Some(SeedMode::Fixed);
+            }
+            SweepAxis::Denoise { values } => request.denoise = values[index],
+            SweepAxis::Prompt { search, values } => {
+                request.prompt = request.prompt.replace(search.as_str(), &values[index]);
+                request.negative_prompt = request
+                    .negative_prompt
+                    .replace(search.as_str(), &values[index]);
+            }
+        }
+    }
+
+    fn validate(&self) -> AppResult<()> {
+        let invalid = |message: String| Err(AppError::InvalidRequest(message));
+        if self.is_empty() {
+            return invalid(format!("Sweep axis '{}' has no values", self.param()));
+        }
+        match self {
+            SweepAxis::Cfg { values } if values.iter().any(|v| !v.is_finite() || *v < 0.0) => {
+                invalid("Sweep cfg values must be finite and >= 0".to_string())
+            }
+            SweepAxis::Steps { values } if values.iter().any(|v| *v == 0 || *v > 200) => {
+                invalid("Sweep steps must be between 1 and 200".to_string())
+            }
+            SweepAxis::Sampler { values } | SweepAxis::Scheduler { values }
+                if values.iter().any(|v| v.trim().is_empty()) =>
+            {
+                invalid(format!("Sweep {} names cannot be empty", self.param()))
+            }
+            SweepAxis::Seed { values } if values.iter().any(|v| *v < 0) => {
+                invalid("Sweep seeds must be >= 0".to_string())
+            }
+            SweepAxis::Denoise { values } if values.iter().any(|v| !(*v > 0.0 && *v <= 1.0)) => {
+                invalid("Sweep denoise values must be greater than 0 and at most 1".to_string())
+            }
+            SweepAxis::Prompt { search, .. } if search.is_empty() => {
+                invalid("Sweep prompt axis needs a non-empty search text".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Every combination of the axis values applied to `base`, x varying fastest
+///
+/// Returns the value index on each axis together with the cell's request.
+pub fn expand(
+    request: &SweepRequest,
+    base: &GenerateRequest,
+) -> AppResult<Vec<(Vec<usize>, GenerateRequest)>> {
+    if request.axes.is_empty() || request.axes.len() > 3 {
+        return Err(AppError::InvalidRequest(
+            "A sweep needs one to three axes".to_string(),
+        ));
+    }
+    for axis in &request.axes {
+        axis.validate()?;
+    }
+    let total = request
+        .axes
+        .iter()
+        .try_fold(1usize, |total, axis| total.checked_mul(axis.len()))
+        .filter(|total| *total <= MAX_SWEEP_CELLS)
+        .ok_or_else(|| {
+            AppError::InvalidRequest(format!(
+                "A sweep is limited to {} combinations",
+                MAX_SWEEP_CELLS
+            ))
+        })?;
+
+    let cells = (0..total)
+        .map(|n| {
+            let mut rest = n;
+            let mut cell = base.clone();
+            let index: Vec<usize> = request
+                .axes
+                .iter()
+                .map(|axis| {
+                    let i = rest % axis.len();
+                    rest /= axis.len();
+                    axis.apply(&mut cell, i);
+                    i
+                })
+                .collect();
+            (index, cell)
+        })
+        .collect();
+    Ok(cells)
+}
+
+// ============================================================================
+// Jobs
+// ============================================================================
+
+/// Lifecycle of a sweep
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SweepStatus {
+    /// Cells are queued or generating
+    Running,
+    /// All cells finished, the contact sheet is being rendered
+    Assembling,
+    Completed,
+    Error,
+}
+
+/// Parameter and value labels of one axis
+#[derive(Debug, Clone, Serialize)]
+pub struct SweepAxisSummary {
+    pub param: String,
+    pub labels: Vec<String>,
+}
+
+/// One combination of a sweep
+#[derive(Debug, Clone, Serialize)]
+pub struct SweepCell {
+    /// Value index on each axis (x, y, z)
+    pub index: Vec<usize>,
+    /// ComfyUI prompt_id once queued
+    pub prompt_id: Option<String>,
+    pub seed: Option<u64>,
+    pub status: GenerationStatus,
+    /// First output image
+    pub image: Option<ImageResult>,
+    pub error: Option<String>,
+    /// ComfyUI instance the cell was submitted to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+}
+
+impl SweepCell {
+    fn finished(&self) -> bool {
+        matches!(
+            self.status,
+            GenerationStatus::Completed | GenerationStatus::Error | GenerationStatus::Interrupted
+        )
+    }
+}
+
+/// A sweep tracked as one job
+#[derive(Debug, Clone, Serialize)]
+pub struct SweepJob {
+    pub id: String,
+    pub status: SweepStatus,
+    pub axes: Vec<SweepAxisSummary>,
+    pub cells: Vec<SweepCell>,
+    /// Cells that finished, successfully or not
+    pub completed: usize,
+    pub total: usize,
+    /// Contact sheet URL once assembled
+    pub grid_url: Option<String>,
+    /// Unix timestamps in milliseconds
+    pub created_at: u64,
+    pub completed_at: Option<u64>,
+    pub error: Option<String>,
+    /// WebSocket client receiving the sweep's events
+    #[serde(skip)]
+    pub owner: Option<String>,
+}
+
+impl SweepJob {
+    /// New running job for the expanded cells of `request`
+    pub fn new(request: &SweepRequest, indices: Vec<Vec<usize>>) -> Self {
+        let total = indices.len();
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            status: SweepStatus::Running,
+            axes: request
+                .axes
+                .iter()
+                .map(|axis| SweepAxisSummary {
+                    param: axis.param().to_string(),
+                    labels: (0..axis.len()).map(|i| axis.label(i)).collect(),
+                })
+                .collect(),
+            cells: indices
+                .into_iter()
+                .map(|index| SweepCell {
+                    index,
+                    prompt_id: None,
+                    seed: None,
+                    status: GenerationStatus::Queued,
+                    image: None,
+                    instance: None,
+                    error: None,
+                })
+                .collect(),
+            completed: 0,
+            total,
+            grid_url: None,
+            created_at: now_millis(),
+            completed_at: None,
+            error: None,
+            owner: request.base.client_id.clone(),
+        }
+    }
+
+    fn labels(&self, axis: usize) -> &[String] {
+        self.axes
+            .get(axis)
+            .map(|a| a.labels.as_slice())
+            .unwrap_or_default()
+    }
+}
+
+// ============================================================================
+// Manager
+// ============================================================================
+
+/// Change applied to a cell when its prompt reports back
+type CellUpdate = Box<dyn FnOnce(&mut SweepCell) + Send>;
+
+#[derive(Default)]
+struct SweepInner {
+    /// Jobs in creation order
+    jobs: Vec<SweepJob>,
+    /// prompt_id -> (job id, cell index)
+    prompts: HashMap<String, (String, usize)>,
+}
+
+/// Tracks sweep jobs and renders their contact sheets when all cells finish
+#[derive(Clone)]
+pub struct SweepManager {
+    pool: ComfyUIPool,
+    dir: PathBuf,
+    font: Arc<LabelFont>,
+    event_tx: EventSender,
+    inner: Arc<Mutex<SweepInner>>,
+}
+
+impl SweepManager {
+    /// Contact sheets are written to `dir`, labels use `font` when given
+    pub fn new(
+        pool: ComfyUIPool,
+        dir: impl Into<PathBuf>,
+        font: Option<&str>,
+        event_tx: EventSender,
+    ) -> Self {
+        Self {
+            pool,
+            dir: dir.into(),
+            font: Arc::new(LabelFont::load(font)),
+            event_tx,
+            inner: Arc::new(Mutex::new(SweepInner::default())),
+        }
+    }
+
+    /// Start tracking a new job
+    pub async fn create(&self, job: SweepJob) {
+        let mut inner = self.inner.lock().await;
+        let finished = inner
+            .jobs
+            .iter()
+            .filter(|j| matches!(j.status, SweepStatus::Completed | SweepStatus::Error))
+            .count();
+        if finished >= MAX_FINISHED_SWEEPS {
+            if let Some(oldest) = inner
+                .jobs
+                .iter()
+                .position(|j| matches!(j.status, SweepStatus::Completed | SweepStatus::Error))
+            {
+                let removed = inner.jobs.remove(oldest);
+                inner.prompts.retain(|_, (id, _)| *id != removed.id);
+            }
+        }
+        inner.jobs.push(job);
+    }
+
+    /// Record the prompt a cell was queued as
+    pub async fn attach(&self, job_id: &str, cell: usize, prompt_id: &str, seed: u64) {
+        let mut inner = self.inner.lock().await;
+        if let Some(c) = find_cell(&mut inner.jobs, job_id, cell) {
+            c.prompt_id = Some(prompt_id.to_string());
+            c.seed = Some(seed);
+        }
+        inner
+            .prompts
+            .insert(prompt_id.to_string(), (job_id.to_string(), cell));
+    }
+
+    /// Mark a cell that could not be queued
+    pub async fn fail(&self, job_id: &str, cell: usize, error: String) {
+        self.finish_cell(job_id, cell, |c| {
+            c.status = GenerationStatus::Error;
+            c.error = Some(error);
+        })
+        .await;
+    }
+
+    pub async fn get(&self, id: &str) -> Option<SweepJob> {
+        let inner = self.inner.lock().await;
+        inner.jobs.iter().find(|j| j.id == id).cloned()
+    }
+
+    /// All tracked jobs, newest first
+    pub async fn list(&self) -> Vec<SweepJob> {
+        let inner = self.inner.lock().await;
+        inner.jobs.iter().rev().cloned().collect()
+    }
+
+    /// Path of a job's contact sheet
+    pub fn grid_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.png", id))
+    }
+
+    /// Update the cell a frontend event refers to
+    pub async fn apply_event(&self, message: &FrontendMessage) {
+        let (prompt_id, update): (&str, CellUpdate) = match message {
+            FrontendMessage::Submitted {
+                prompt_id,
+                instance,
+            } => {
+                let instance = instance.clone();
+                (prompt_id, Box::new(move |c| c.instance = Some(instance)))
+            }
+            FrontendMessage::Started { prompt_id } => (
+                prompt_id,
+                Box::new(|c| c.status = GenerationStatus::Running),
+            ),
+            FrontendMessage::Completed {
+                prompt_id, images, ..
+            } => {
+                let image = images.first().cloned();
+                (
+                    prompt_id,
+                    Box::new(move |c| c.image = c.image.take().or(image)),
+                )
+            }
+            // `completed` is sent for every output node, only `success` ends the prompt
+            FrontendMessage::Success { prompt_id } => (
+                prompt_id,
+                Box::new(|c| {
+                    if !c.finished() {
+                        c.status = GenerationStatus::Completed;
+                    }
+                }),
+            ),
+            FrontendMessage::Error {
+                prompt_id: Some(prompt_id),
+                message,
+                ..
+            } => {
+                let message = message.clone();
+                (
+                    prompt_id,
+                    Box::new(move |c| {
+                        c.status = GenerationStatus::Error;
+                        c.error = Some(message);
+                    }),
+                )
+            }
+            FrontendMessage::Cancelled { prompt_id } => (
+                prompt_id,
+                Box::new(|c| {
+                    c.status = GenerationStatus::Error;
+                    c.error = Some("Cancelled".to_string());
+                }),
+            ),
+            // A cancelled cell is interrupted too, keep its error
+            FrontendMessage::Interrupted { prompt_id, .. } => (
+                prompt_id,
+                Box::new(|c| {
+                    if !c.finished() {
+                        c.status = GenerationStatus::Interrupted;
+                        c.error = Some("Interrupted".to_string());
+                    }
+                }),
+            ),
+            _ => return,
+        };
+
+        let target = self.inner.lock().await.prompts.get(prompt_id).cloned();
+        if let Some((job_id, cell)) = target {
+            self.finish_cell(&job_id, cell, update).await;
+        }
+    }
+
+    /// Catch up on cells whose events were missed, e.g. while ComfyUI's websocket was down
+    pub async fn refresh(&self, id: &str) {
+        let pending: Vec<(usize, String, Option<String>)> = match self.get(id).await {
+            Some(job) if job.status == SweepStatus::Running => job
+                .cells
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| !c.finished())
+                .filter_map(|(i, c)| c.prompt_id.clone().map(|p| (i, p, c.instance.clone())))
+                .collect(),
+            _ => return,
+        };
+
+        for (cell, prompt_id, instance) in pending {
+            let Ok(Some(history)) = self.pool.get_history(&prompt_id, instance.as_deref()).await
+            else {
+                continue;
+            };
+            let image = history.output_images().into_iter().next();
+            if history.interrupted() {
+                self.finish_cell(id, cell, |c| {
+                    c.status = GenerationStatus::Interrupted;
+                    c.error = Some("Interrupted".to_string());
+                })
+                .await;
+            } else if history.failed() {
+                self.finish_cell(id, cell, |c| {
+                    c.status = GenerationStatus::Error;
+                    c.error = Some("Execution failed".to_string());
+                })
+                .await;
+            } else if history.status.completed == Some(true) {
+                self.finish_cell(id, cell, move |c| {
+                    c.status = GenerationStatus::Completed;
+                    c.image = c.image.take().or(image);
+                })
+                .await;
+            }
+        }
+    }
+
+    /// Update a cell, then report progress and assemble the sheet once every cell finished
+    async fn finish_cell(&self, job_id: &str, cell: usize, update: impl FnOnce(&mut SweepCell)) {
+        let mut inner = self.inner.lock().await;
+        let Some(job) = inner.jobs.iter_mut().find(|j| j.id == job_id) else {
+            return;
+        };
+        if job.status != SweepStatus::Running {
+            return;
+        }
+        let Some(c) = job.cells.get_mut(cell) else {
+            return;
+        };
+        update(c);
+
+        let completed = job.cells.iter().filter(|c| c.finished()).count();
+        if completed == job.completed {
+            return;
+        }
+        job.completed = completed;
+        self.broadcast(
+            &FrontendMessage::SweepProgress {
+                sweep_id: job.id.clone(),
+                completed,
+                total: job.total,
+            },
+            job.owner.clone(),
+        );
+
+        if completed < job.total {
+            return;
+        }
+        if job.cells.iter().all(|c| c.image.is_none()) {
+            job.status = SweepStatus::Error;
+            job.completed_at = Some(now_millis());
+            job.error = Some("No cell produced an image".to_string());
+            self.broadcast(
+                &FrontendMessage::SweepCompleted {
+                    sweep_id: job.id.clone(),
+                    grid_url: None,
+                    error: job.error.clone(),
+                },
+                job.owner.clone(),
+            );
+            return;
+        }
+
+        job.status = SweepStatus::Assembling;
+        let job = job.clone();
+        let manager = self.clone();
+        tokio::spawn(async move { manager.assemble(job).await });
+    }
+
+    /// Render and store the contact sheet of a finished job
+    async fn assemble(&self, job: SweepJob) {
+        let result = self.render(&job).await;
+
+        let mut inner = self.inner.lock().await;
+        let Some(stored) = inner.jobs.iter_mut().find(|j| j.id == job.id) else {
+            return;
+        };
+        stored.completed_at = Some(now_millis());
+        match result {
+            Ok(()) => {
+                tracing::info!("Sweep {} contact sheet ready", job.id);
+                stored.status = SweepStatus::Completed;
+                stored.grid_url = Some(format!("/api/sweeps/{}/grid", job.id));
+            }
+            Err(e) => {
+                tracing::error!("Sweep {} contact sheet failed: {}", job.id, e);
+                stored.status = SweepStatus::Error;
+                stored.error = Some(e.to_string());
+            }
+        }
+        self.broadcast(
+            &FrontendMessage::SweepCompleted {
+                sweep_id: job.id.clone(),
+                grid_url: stored.grid_url.clone(),
+                error: stored.error.clone(),
+            },
+            job.owner.clone(),
+        );
+    }
+
+    async fn render(&self, job: &SweepJob) -> AppResult<()> {
+        let mut cells = Vec::with_capacity(job.cells.len());
+        for cell in &job.cells {
+            let Some(image) = &cell.image else {
+                cells.push(None);
+                continue;
+            };
+            let decoded = match self
+                .pool
+                .get_image(
+                    cell.instance.as_deref(),
+                    &image.filename,
+                    &image.subfolder,
+                    &image.image_type,
+                )
+                .await
+            {
+                Ok(data) => image::load_from_memory(&data)
+                    .map_err(|e| tracing::warn!("Cannot decode {}: {}", image.filename, e))
+                    .ok(),
+                Err(e) => {
+                    tracing::warn!("Cannot fetch {}: {}", image.filename, e);
+                    None
+                }
+            };
+            cells.push(decoded);
+        }
+
+        let font = self.font.clone();
+        let x_labels = job.labels(0).to_vec();
+        let y_labels = job.labels(1).to_vec();
+        let z_labels = job.labels(2).to_vec();
+        let png = tokio::task::spawn_blocking(move || {
+            render_contact_sheet(
+                &font,
+                &ContactSheet {
+                    x_labels: &x_labels,
+                    y_labels: &y_labels,
+                    z_labels: &z_labels,
+                    cells,
+                },
+            )
+        })
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))??;
+
+        tokio::fs::create_dir_all(&self.dir).await.map_err(|e| {
+            AppError::Internal(format!("Cannot create {}: {}", self.dir.display(), e))
+        })?;
+        let path = self.grid_path(&job.id);
+        tokio::fs::write(&path, png)
+            .await
+            .map_err(|e| AppError::Internal(format!("Cannot write {}: {}", path.display(), e)))
+    }
+
+    fn broadcast(&self, message: &FrontendMessage, owner: Option<String>) {
+        if let Some(event) = Outbound::new(message, owner) {
+            self.event_tx.send(event);
+        }
+    }
+}
+
+fn find_cell<'a>(jobs: &'a mut [SweepJob], job_id: &str, cell: usize) -> Option<&'a mut SweepCell> {
+    jobs.iter_mut()
+        .find(|j| j.id == job_id)
+        .and_then(|j| j.cells.get_mut(cell))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sweep(axes: Vec<SweepAxis>) -> SweepRequest {
+        SweepRequest {
+            base: GenerateRequest::default(),
+            axes,
+        }
+    }
+
+    fn base() -> GenerateRequest {
+        GenerateRequest {
+            prompt: "1girl, red dress".to_string(),
+            negative_prompt: "red background".to_string(),
+            seed: 7,
+            seed_mode: Some(SeedMode::Increment),
+            ..GenerateRequest::default()
+        }
+    }
+
+    #[test]
+    fn x_varies_fastest() {
+        let request = sweep(vec![
+            SweepAxis::Cfg {
+                values: vec![3.0, 5.0],
+            },
+            SweepAxis::Steps {
+                values: vec![10, 20, 30],
+            },
+        ]);
+        let cells = expand(&request, &base()).unwrap();
+
+        let indexes: Vec<&[usize]> = cells.iter().map(|(i, _)| i.as_slice()).collect();
+        assert_eq!(indexes, [[0, 0], [1, 0], [0, 1], [1, 1], [0, 2], [1, 2]]);
+        let values: Vec<(f32, u32)> = cells.iter().map(|(_, c)| (c.cfg, c.steps)).collect();
+        assert_eq!(
+            values,
+            [
+                (3.0, 10),
+                (5.0, 10),
+                (3.0, 20),
+                (5.0, 20),
+                (3.0, 30),
+                (5.0, 30)
+            ]
+        );
+    }
+
+    #[test]
+    fn prompt_axis_replaces_in_both_prompts() {
+        let request = sweep(vec![SweepAxis::Prompt {
+            search: "red".to_string(),
+            values: vec!["red".to_string(), "blue".to_string()],
+        }]);
+        let cells = expand(&request, &base()).unwrap();
+
+        assert_eq!(cells[0].1.prompt, "1girl, red dress");
+        assert_eq!(cells[1].1.prompt, "1girl, blue dress");
+        assert_eq!(cells[1].1.negative_prompt, "blue background");
+    }
+
+    #[test]
+    fn seed_axis_pins_the_seed() {
+        let request = sweep(vec![
+            SweepAxis::Seed { values: vec![1, 2] },
+            SweepAxis::Sampler {
+                values: vec!["euler".to_string(), "res_multistep".to_string()],
+            },
+        ]);
+        let cells = expand(&request, &base()).unwrap();
+
+        let seeds: Vec<i64> = cells.iter().map(|(_, c)| c.seed).collect();
+        assert_eq!(seeds, [1, 2, 1, 2]);
+        assert!(cells
+            .iter()
+            .all(|(_, c)| c.seed_mode == Some(SeedMode::Fixed)));
+        assert_eq!(cells[3].1.sampler_name, "res_multistep");
+    }
+
+    #[test]
+    fn cell_count_is_limited() {
+        let steps = |n: u32| SweepAxis::Steps {
+            values: (1..=n).collect(),
+        };
+        assert_eq!(
+            expand(&sweep(vec![steps(10), steps(10)]), &base())
+                .unwrap()
+                .len(),
+            MAX_SWEEP_CELLS
+        );
+        assert!(expand(&sweep(vec![steps(10), steps(10), steps(2)]), &base()).is_err());
+        assert!(expand(&sweep(vec![steps(101)]), &base()).is_err());
+    }
+
+    #[test]
+    fn invalid_axes_are_rejected() {
+        let steps = || SweepAxis::Steps { values: vec![10] };
+        assert!(expand(&sweep(vec![]), &base()).is_err());
+        assert!(expand(&sweep(vec![steps(), steps(), steps(), steps()]), &base()).is_err());
+        assert!(expand(&sweep(vec![SweepAxis::Steps { values: vec![] }]), &base()).is_err());
+        assert!(expand(
+            &sweep(vec![SweepAxis::Denoise { values: vec![0.0] }]),
+            &base()
+        )
+        .is_err());
+        assert!(expand(
+            &sweep(vec![SweepAxis::Prompt {
+                search: String::new(),
+                values: vec!["x".to_string()]
+            }]),
+            &base()
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,361 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::error::{AppError, AppResult};
+use crate::workflow::find_node_by_class;
+
+/// Built-in NewBie template, used when the templates directory does not override it
+const BUILTIN_TEMPLATE: &str = include_str!("../templates/newbie.json");
+
+/// Request fields a template may bind to node inputs
+pub const BINDABLE_FIELDS: &[&str] = &[
+    "seed",
+    "steps",
+    "cfg",
+    "sampler_name",
+    "scheduler",
+    "denoise",
+    "width",
+    "height",
+    "batch_size",
+    "prompt",
+    "negative_prompt",
+    "unet",
+    "clip1",
+    "clip2",
+    "vae",
+    "weight_dtype",
+];
+
+// ============================================================================
+// Template Definition
+// ============================================================================
+
+/// Target(s) of a binding, written as `"<node_id>.<input_name>"`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum InputRef {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl InputRef {
+    fn targets(&self) -> Vec<&str> {
+        match self {
+            InputRef::One(target) => vec![target.as_str()],
+            InputRef::Many(targets) => targets.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+/// An API-format ComfyUI workflow plus the mapping from request fields to node inputs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowTemplate {
+    /// Unique template name, selected via `GenerateRequest::template`
+    pub name: String,
+    /// Human readable description
+    #[serde(default)]
+    pub description: String,
+    /// Format for the positive prompt, `{prompt}` is replaced by the request prompt
+    #[serde(default)]
+    pub prompt_format: Option<String>,
+    /// Format for the negative prompt, `{negative_prompt}` is replaced by the request text
+    #[serde(default)]
+    pub negative_format: Option<String>,
+    /// Negative prompt used when the request leaves it empty
+    #[serde(default)]
+    pub default_negative_prompt: Option<String>,
+    /// Request field -> node input(s)
+    pub bindings: HashMap<String, InputRef>,
+    /// API-format workflow graph
+    pub workflow: Value,
+}
+
+/// Template listing entry for the frontend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TemplateSummary {
+    pub name: String,
+    pub description: String,
+    pub bindings: Vec<String>,
+    pub default: bool,
+}
+
+impl WorkflowTemplate {
+    /// Parse and validate a template from JSON text
+    pub fn from_json(text: &str) -> AppResult<Self> {
+        let template: WorkflowTemplate = serde_json::from_str(text)?;
+        template.validate()?;
+        Ok(template)
+    }
+
+    /// Check that the workflow is API format and every binding points at an existing node
+    fn validate(&self) -> AppResult<()> {
+        let nodes = self.workflow.as_object().ok_or_else(|| {
+            AppError::InvalidRequest(format!(
+                "Template '{}': workflow must be an API-format JSON object",
+                self.name
+            ))
+        })?;
+
+        for (field, input_ref) in &self.bindings {
+            if !BINDABLE_FIELDS.contains(&field.as_str()) {
+                return Err(AppError::InvalidRequest(format!(
+                    "Template '{}': unknown binding field '{}'",
+                    self.name, field
+                )));
+            }
+            for target in input_ref.targets() {
+                let (node_id, _) = split_target(target).ok_or_else(|| {
+                    AppError::InvalidRequest(format!(
+                        "Template '{}': binding '{}' must look like '<node>.<input>', got '{}'",
+                        self.name, field, target
+                    ))
+                })?;
+                if !nodes.contains_key(node_id) {
+                    return Err(AppError::InvalidRequest(format!(
+                        "Template '{}': binding '{}' references missing node '{}'",
+                        self.name, field, node_id
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Node ids targeted by a binding field
+    pub fn bound_nodes(&self, field: &str) -> Vec<String> {
+        self.bindings
+            .get(field)
+            .map(|input_ref| {
+                input_ref
+                    .targets()
+                    .into_iter()
+                    .filter_map(split_target)
+                    .map(|(node_id, _)| node_id.to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// The sampler node: the target of the `seed` binding, else the first KSampler
+    pub fn sampler_node(&self) -> Option<String> {
+        self.bound_nodes("seed")
+            .into_iter()
+            .next()
+            .or_else(|| find_node_by_class(&self.workflow, "KSampler"))
+    }
+
+    /// Apply the positive prompt format
+    pub fn format_prompt(&self, prompt: &str) -> String {
+        match &self.prompt_format {
+            Some(format) => format.replace("{prompt}", prompt),
+            None => prompt.to_string(),
+        }
+    }
+
+    /// Apply the negative prompt format, falling back to the template default when empty
+    pub fn format_negative_prompt(&self, negative_prompt: &str) -> String {
+        let text = if negative_prompt.is_empty() {
+            self.default_negative_prompt.as_deref().unwrap_or_default()
+        } else {
+            negative_prompt
+        };
+        match &self.negative_format {
+            Some(format) => format.replace("{negative_prompt}", text),
+            None => text.to_string(),
+        }
+    }
+
+    /// Strip the positive prompt format from an encoded text, the inverse of `format_prompt`
+    pub fn unformat_prompt(&self, text: &str) -> String {
+        match &self.prompt_format {
+            Some(format) => strip_format(format, "{prompt}", text),
+            None => text.to_string(),
+        }
+    }
+
+    /// Strip the negative prompt format; the template default comes back as an empty string
+    pub fn unformat_negative_prompt(&self, text: &str) -> String {
+        let text = match &self.negative_format {
+            Some(format) => strip_format(format, "{negative_prompt}", text),
+            None => text.to_string(),
+        };
+        if self.default_negative_prompt.as_deref() == Some(text.as_str()) {
+            String::new()
+        } else {
+            text
+        }
+    }
+
+    /// Render the workflow with the given field values
+    ///
+    /// Fields without a binding in this template are ignored.
+    pub fn render(&self, values: &HashMap<&str, Value>) -> Value {
+        let mut workflow = self.workflow.clone();
+
+        for (field, input_ref) in &self.bindings {
+            let Some(value) = values.get(field.as_str()) else {
+                continue;
+            };
+            for target in input_ref.targets() {
+                let Some((node_id, input)) = split_target(target) else {
+                    continue;
+                };
+                if let Some(inputs) = workflow
+                    .get_mut(node_id)
+                    .and_then(|node| node.as_object_mut())
+                    .map(|node| {
+                        node.entry("inputs")
+                            .or_insert_with(|| Value::Object(Map::new()))
+                    })
+                    .and_then(|inputs| inputs.as_object_mut())
+                {
+                    inputs.insert(input.to_string(), value.clone());
+                }
+            }
+        }
+
+        workflow
+    }
+
+    fn summary(&self, default_name: &str) -> TemplateSummary {
+        let mut bindings: Vec<String> = self.bindings.keys().cloned().collect();
+        bindings.sort();
+        TemplateSummary {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            bindings,
+            default: self.name == default_name,
+        }
+    }
+}
+
+/// Remove the text around `placeholder` in `format` from `text` when present
+fn strip_format(format: &str, placeholder: &str, text: &str) -> String {
+    let Some((prefix, suffix)) = format.split_once(placeholder) else {
+        return text.to_string();
+    };
+    let text = text.strip_prefix(prefix).unwrap_or(text);
+    text.strip_suffix(suffix).unwrap_or(text).to_string()
+}
+
+/// Split `"<node_id>.<input_name>"`
+fn split_target(target: &str) -> Option<(&str, &str)> {
+    let (node_id, input) = target.split_once('.')?;
+    if node_id.is_empty() || input.is_empty() {
+        return None;
+    }
+    Some((node_id, input))
+}
+
+// ============================================================================
+// Template Store
+// ============================================================================
+
+/// Workflow templates loaded from the templates directory
+#[derive(Clone)]
+pub struct TemplateStore {
+    dir: PathBuf,
+    default_name: String,
+    templates: Arc<RwLock<HashMap<String, Arc<WorkflowTemplate>>>>,
+}
+
+impl TemplateStore {
+    /// Create a store and load all templates from `dir`
+    pub fn load(dir: impl Into<PathBuf>, default_name: &str) -> Self {
+        let dir = dir.into();
+        let templates = load_templates(&dir);
+        tracing::info!(
+            "Loaded {} workflow template(s) from {}",
+            templates.len(),
+            dir.display()
+        );
+
+        Self {
+            dir,
+            default_name: default_name.to_string(),
+            templates: Arc::new(RwLock::new(templates)),
+        }
+    }
+
+    /// Re-read the templates directory, returning the number of templates loaded
+    pub async fn reload(&self) -> usize {
+        let templates = load_templates(&self.dir);
+        let count = templates.len();
+        *self.templates.write().await = templates;
+        tracing::info!("Reloaded {} workflow template(s)", count);
+        count
+    }
+
+    /// Get a template by name, or the default template when no name is given
+    pub async fn get(&self, name: Option<&str>) -> AppResult<Arc<WorkflowTemplate>> {
+        let name = name.unwrap_or(&self.default_name);
+        self.templates
+            .read()
+            .await
+            .get(name)
+            .cloned()
+            .ok_or_else(|| AppError::NotFound(format!("Workflow template '{}' not found", name)))
+    }
+
+    /// List all loaded templates
+    pub async fn list(&self) -> Vec<TemplateSummary> {
+        let mut list: Vec<TemplateSummary> = self
+            .templates
+            .read()
+            .await
+            .values()
+            .map(|t| t.summary(&self.default_name))
+            .collect();
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        list
+    }
+}
+
+/// Load the built-in template plus every `*.json` file in `dir`
+///
+/// Invalid files are logged and skipped so one broken template does not take down the rest.
+fn load_templates(dir: &Path) -> HashMap<String, Arc<WorkflowTemplate>> {
+    let mut templates = HashMap::new();
+
+    let builtin = WorkflowTemplate::from_json(BUILTIN_TEMPLATE)
+        .expect("Built-in workflow template must be valid");
+    templates.insert(builtin.name.clone(), Arc::new(builtin));
+
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            tracing::debug!("Templates directory {} not readable: {}", dir.display(), e);
+            return templates;
+        }
+    };
+
+    for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        let loaded = std::fs::read_to_string(&path)
+            .map_err(|e| AppError::Internal(e.to_string()))
+            .and_then(|text| WorkflowTemplate::from_json(&text));
+        match loaded {
+            Ok(template) => {
+                tracing::debug!(
+                    "Loaded template '{}' from {}",
+                    template.name,
+                    path.display()
+                );
+                templates.insert(template.name.clone(), Arc::new(template));
+            }
+            Err(e) => {
+                tracing::warn!("Skipping template {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    templates
+}
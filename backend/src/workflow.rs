@@ -0,0 +1,106 @@
+use serde_json::{json, Value};
+
+/// Find the id of the first node with the given class_type (lowest numeric id first)
+pub fn find_node_by_class(workflow: &Value, class_type: &str) -> Option<String> {
+    let mut ids: Vec<&String> = workflow
+        .as_object()?
+        .iter()
+        .filter(|(_, node)| node.get("class_type").and_then(|v| v.as_str()) == Some(class_type))
+        .map(|(id, _)| id)
+        .collect();
+    ids.sort_by_key(|id| id.parse::<u64>().unwrap_or(u64::MAX));
+    ids.first().map(|id| id.to_string())
+}
+
+/// Next free numeric node id
+pub fn next_node_id(workflow: &Value) -> u64 {
+    workflow
+        .as_object()
+        .map(|nodes| {
+            nodes
+                .keys()
+                .filter_map(|id| id.parse::<u64>().ok())
+                .max()
+                .unwrap_or(0)
+        })
+        .unwrap_or(0)
+        + 1
+}
+
+/// Add a node and return its id
+pub fn add_node(workflow: &mut Value, class_type: &str, title: &str, inputs: Value) -> String {
+    let id = next_node_id(workflow).to_string();
+    if let Some(nodes) = workflow.as_object_mut() {
+        nodes.insert(
+            id.clone(),
+            json!({
+                "inputs": inputs,
+                "class_type": class_type,
+                "_meta": {"title": title}
+            }),
+        );
+    }
+    id
+}
+
+/// Read a node input
+pub fn get_input<'a>(workflow: &'a Value, node_id: &str, input: &str) -> Option<&'a Value> {
+    workflow.get(node_id)?.get("inputs")?.get(input)
+}
+
+/// Set a node input, returning false when the node does not exist
+pub fn set_input(workflow: &mut Value, node_id: &str, input: &str, value: Value) -> bool {
+    match workflow
+        .get_mut(node_id)
+        .and_then(|node| node.get_mut("inputs"))
+        .and_then(|inputs| inputs.as_object_mut())
+    {
+        Some(inputs) => {
+            inputs.insert(input.to_string(), value);
+            true
+        }
+        None => false,
+    }
+}
+
+/// A link to output `slot` of `node_id`
+pub fn link(node_id: &str, slot: u32) -> Value {
+    json!([node_id, slot])
+}
+
+/// Ids of the nodes with an input linked to output `slot` of `node_id`
+pub fn consumers_of(workflow: &Value, node_id: &str, slot: u32) -> Vec<String> {
+    let source = link(node_id, slot);
+    workflow
+        .as_object()
+        .map(|nodes| {
+            nodes
+                .iter()
+                .filter(|(_, node)| {
+                    node.get("inputs")
+                        .and_then(|v| v.as_object())
+                        .is_some_and(|inputs| inputs.values().any(|v| *v == source))
+                })
+                .map(|(id, _)| id.clone())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Replace every input of `nodes` that equals link `from` with link `to`
+pub fn redirect_links(workflow: &mut Value, nodes: &[String], from: &Value, to: &Value) {
+    for node_id in nodes {
+        let Some(inputs) = workflow
+            .get_mut(node_id.as_str())
+            .and_then(|node| node.get_mut("inputs"))
+            .and_then(|v| v.as_object_mut())
+        else {
+            continue;
+        };
+        for value in inputs.values_mut() {
+            if value == from {
+                *value = to.clone();
+            }
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use serde::{Deserialize, Serialize};
+
+/// A prompt in NewBie's XML layout: one block per character, then the scene-wide tags
+///
+/// Serializes to the layout of the frontend's structured editor, except that values are
+/// trimmed and escaped where the editor copies them verbatim.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StructuredPrompt {
+    #[serde(default)]
+    pub characters: Vec<Character>,
+    #[serde(default)]
+    pub general_tags: GeneralTags,
+    /// Free-form natural language description appended after the tags
+    #[serde(default)]
+    pub caption: String,
+}
+
+/// One `<character_N>` block; empty fields are left out
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Character {
+    /// Character name, `character_N` when empty
+    pub name: String,
+    /// e.g. `1girl`, `1boy`
+    pub gender: String,
+    pub appearance: String,
+    pub clothing: String,
+    pub expression: String,
+    pub action: String,
+    pub position: String,
+}
+
+/// The `<general_tags>` block; empty fields are left out
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GeneralTags {
+    pub count: String,
+    pub artists: String,
+    pub style: String,
+    pub background: String,
+    pub lighting: String,
+    pub atmosphere: String,
+    pub objects: String,
+    pub other: String,
+}
+
+impl StructuredPrompt {
+    /// Whether serializing would produce no text
+    pub fn is_empty(&self) -> bool {
+        self.characters.is_empty()
+            && self
+                .general_tags
+                .fields()
+                .iter()
+                .all(|(_, v)| v.trim().is_empty())
+            && self.caption.trim().is_empty()
+    }
+
+    /// NewBie XML, with `&`, `<` and `>` in the values escaped
+    pub fn to_xml(&self) -> String {
+        let mut blocks = Vec::new();
+
+        for (index, character) in self.characters.iter().enumerate() {
+            let tag = format!("character_{}", index + 1);
+            let name = match character.name.trim() {
+                "" => tag.clone(),
+                name => name.to_string(),
+            };
+            let mut block = format!("<{}>\n{}", tag, element("n", &name));
+            for (key, value) in character.fields() {
+                block.push_str(&element(key, value));
+            }
+            block.push_str(&format!("</{}>", tag));
+            blocks.push(block);
+        }
+
+        let tags: String = self
+            .general_tags
+            .fields()
+            .iter()
+            .map(|(key, value)| element(key, value))
+            .collect();
+        if !tags.is_empty() {
+            blocks.push(format!("<general_tags>\n{}</general_tags>", tags));
+        }
+
+        let caption = self.caption.trim();
+        if !caption.is_empty() {
+            blocks.push(format!("<caption>{}</caption>", escape(caption)));
+        }
+
+        blocks.join("\n\n")
+    }
+}
+
+impl Character {
+    fn fields(&self) -> [(&'static str, &str); 6] {
+        [
+            ("gender", &self.gender),
+            ("appearance", &self.appearance),
+            ("clothing", &self.clothing),
+            ("expression", &self.expression),
+            ("action", &self.action),
+            ("position", &self.position),
+        ]
+    }
+}
+
+impl GeneralTags {
+    fn fields(&self) -> [(&'static str, &str); 8] {
+        [
+            ("count", &self.count),
+            ("artists", &self.artists),
+            ("style", &self.style),
+            ("background", &self.background),
+            ("lighting", &self.lighting),
+            ("atmosphere", &self.atmosphere),
+            ("objects", &self.objects),
+            ("other", &self.other),
+        ]
+    }
+}
+
+/// `<key>value</key>` and a newline, nothing for a blank value
+fn element(key: &str, value: &str) -> String {
+    let value = value.trim();
+    if value.is_empty() {
+        return String::new();
+    }
+    format!("<{}>{}</{}>\n", key, escape(value), key)
+}
+
+/// Escape the characters that would open or close a tag
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_covers_tag_characters() {
+        assert_eq!(escape("a & b"), "a &amp; b");
+        assert_eq!(escape("<tag>"), "&lt;tag&gt;");
+        // `&` first, so entities are not escaped twice
+        assert_eq!(escape("&lt;"), "&amp;lt;");
+        assert_eq!(escape("1girl, smile"), "1girl, smile");
+    }
+
+    #[test]
+    fn blank_fields_count_as_empty() {
+        let mut prompt = StructuredPrompt::default();
+        assert!(prompt.is_empty());
+
+        prompt.general_tags.style = "  ".to_string();
+        prompt.caption = "\n".to_string();
+        assert!(prompt.is_empty());
+        assert_eq!(prompt.to_xml(), "");
+
+        prompt.general_tags.lighting = "backlight".to_string();
+        assert!(!prompt.is_empty());
+
+        // A character block is written even when all its fields are blank
+        let unnamed = StructuredPrompt {
+            characters: vec![Character::default()],
+            ..StructuredPrompt::default()
+        };
+        assert!(!unnamed.is_empty());
+        assert_eq!(
+            unnamed.to_xml(),
+            "<character_1>\n<n>character_1</n>\n</character_1>"
+        );
+    }
+
+    #[test]
+    fn values_are_trimmed_and_escaped() {
+        let prompt = StructuredPrompt {
+            characters: vec![Character {
+                name: " Miku ".to_string(),
+                gender: "1girl".to_string(),
+                action: "holding <sign>".to_string(),
+                ..Character::default()
+            }],
+            general_tags: GeneralTags {
+                artists: " a & b ".to_string(),
+                ..GeneralTags::default()
+            },
+            caption: " Night. ".to_string(),
+        };
+        assert_eq!(
+            prompt.to_xml(),
+            "<character_1>\n<n>Miku</n>\n<gender>1girl</gender>\n\
+             <action>holding &lt;sign&gt;</action>\n</character_1>\n\n\
+             <general_tags>\n<artists>a &amp; b</artists>\n</general_tags>\n\n\
+             <caption>Night.</caption>"
+        );
+    }
+}
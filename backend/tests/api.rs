@@ -0,0 +1,765 @@
+//! End-to-end tests of the HTTP, WebSocket and SSE API against a mock ComfyUI
+
+mod common;
+
+use common::{types, MockComfyUI, Script, TestApp, EVENT_TIMEOUT};
+use futures::StreamExt;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+fn generate_body(client_id: &str) -> Value {
+    json!({
+        "prompt": "1girl, silver hair, night sky",
+        "negative_prompt": "lowres",
+        "width": 832,
+        "height": 1216,
+        "steps": 3,
+        "seed": 1234,
+        "client_id": client_id
+    })
+}
+
+#[tokio::test]
+async fn health_and_status_report_the_instance() {
+    let app = TestApp::start().await;
+
+    let health: Value = app.get("/health").await.json().await.unwrap();
+    assert_eq!(health["status"], "ok");
+    assert_eq!(health["comfyui"], true);
+    assert_eq!(health["instances"][0]["name"], "mock");
+
+    let status: Value = app.get("/api/status").await.json().await.unwrap();
+    assert_eq!(status["comfyui"]["connected"], true);
+    assert_eq!(status["comfyui"]["devices"][0]["name"], "cuda:0 Mock GPU");
+}
+
+#[tokio::test]
+async fn models_come_from_object_info() {
+    let app = TestApp::start().await;
+
+    let models: Value = app.get("/api/models").await.json().await.unwrap();
+    assert_eq!(models["unet"], json!(["newbie-image.safetensors"]));
+    assert_eq!(models["lora"], json!(["styles/watercolor.safetensors"]));
+    assert_eq!(models["upscale"], json!(["4x-AnimeSharp.pth"]));
+}
+
+#[tokio::test]
+async fn capabilities_are_cached_until_refreshed() {
+    let app = TestApp::start().await;
+
+    let capabilities: Value = app.get("/api/capabilities").await.json().await.unwrap();
+    assert_eq!(capabilities["unet"], json!(["newbie-image.safetensors"]));
+    assert!(capabilities["samplers"]
+        .as_array()
+        .unwrap()
+        .contains(&json!("res_multistep")));
+    assert!(capabilities["schedulers"]
+        .as_array()
+        .unwrap()
+        .contains(&json!("linear_quadratic")));
+    assert!(capabilities["node_classes"]
+        .as_array()
+        .unwrap()
+        .contains(&json!("KSampler")));
+
+    // Models, validation and workflow building reuse the cached answer
+    app.get("/api/models").await;
+    let queued = app
+        .post_json("/api/generate", &generate_body("cache-client"))
+        .await;
+    assert_eq!(queued.status(), 200);
+    assert_eq!(app.comfyui.object_info_requests(), 1);
+
+    let refreshed = app.post_json("/api/capabilities/refresh", &json!({})).await;
+    assert_eq!(refreshed.status(), 200);
+    assert_eq!(app.comfyui.object_info_requests(), 2);
+
+    // Pointing the instance elsewhere drops the cache
+    let other = MockComfyUI::start().await;
+    app.post_json("/api/comfyui-url", &json!({ "url": other.url }))
+        .await;
+    app.get("/api/capabilities").await;
+    assert_eq!(other.object_info_requests(), 1);
+}
+
+#[tokio::test]
+async fn generate_streams_events_and_records_history() {
+    let app = TestApp::start().await;
+    let mut ws = app.connect_ws("").await;
+
+    let response = app
+        .post_json("/api/generate", &generate_body(&ws.client_id))
+        .await;
+    assert_eq!(response.status(), 200);
+    let queued: Value = response.json().await.unwrap();
+    let prompt_id = queued["prompt_id"].as_str().unwrap().to_string();
+    assert_eq!(queued["seed"], 1234);
+
+    let events = ws.collect_until(&prompt_id, "success").await;
+    let kinds = types(&events);
+    for kind in [
+        "queued",
+        "submitted",
+        "started",
+        "progress",
+        "preview",
+        "completed",
+    ] {
+        assert!(kinds.contains(&kind), "missing {} in {:?}", kind, kinds);
+    }
+    let position = |kind: &str| kinds.iter().position(|k| *k == kind).unwrap();
+    assert!(position("queued") < position("submitted"));
+    assert!(position("submitted") < position("progress"));
+    assert!(position("progress") < position("completed"));
+
+    let progress = events.iter().filter(|e| e["type"] == "progress").count();
+    assert_eq!(progress, 3);
+    let preview = events.iter().find(|e| e["type"] == "preview").unwrap();
+    assert!(preview["image_data"]
+        .as_str()
+        .unwrap()
+        .starts_with("data:image/png;base64,"));
+
+    let completed = events.iter().find(|e| e["type"] == "completed").unwrap();
+    assert_eq!(completed["seed"], 1234);
+    let image = completed["images"][0]["filename"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert!(image.starts_with("ComfyUI_"));
+
+    // The workflow sent to ComfyUI carries the request
+    let submitted = app.comfyui.prompts();
+    assert_eq!(submitted.len(), 1);
+    assert_eq!(submitted[0].prompt_id, prompt_id);
+    assert_eq!(submitted[0].input("KSampler", "seed"), Some(&json!(1234)));
+    assert_eq!(
+        submitted[0].input("EmptySD3LatentImage", "width"),
+        Some(&json!(832))
+    );
+    assert_eq!(
+        submitted[0].input("UNETLoader", "unet_name"),
+        Some(&json!("newbie-image.safetensors"))
+    );
+
+    let record: Value = app
+        .get(&format!("/api/generations/{}", prompt_id))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(record["status"], "completed");
+    assert_eq!(record["images"][0]["filename"], image.as_str());
+
+    // Served images carry the generation parameters
+    let png = app
+        .get(&format!("/api/images/{}", image))
+        .await
+        .bytes()
+        .await
+        .unwrap();
+    assert!(png.starts_with(b"\x89PNG"));
+    assert!(png.windows(7).any(|w| w == b"newbie:"));
+}
+
+#[tokio::test]
+async fn throttled_previews_keep_the_newest_frame() {
+    let comfyui = MockComfyUI::with_script(Script {
+        steps: 4,
+        step_delay: Duration::from_millis(50),
+        ..Script::default()
+    })
+    .await;
+    let app = TestApp::with_comfyui(comfyui).await;
+    // One preview a second, far slower than the steps
+    let mut ws = app.connect_ws("preview_fps=1").await;
+
+    let queued: Value = app
+        .post_json("/api/generate", &generate_body(&ws.client_id))
+        .await
+        .json()
+        .await
+        .unwrap();
+    let prompt_id = queued["prompt_id"].as_str().unwrap();
+
+    // The first frame goes out at once; of the ones skipped after it, the newest is sent
+    // when the next event of the prompt arrives instead of being dropped
+    let events = ws.collect_until(prompt_id, "success").await;
+    let kinds = types(&events);
+    let previews = kinds.iter().filter(|k| **k == "preview").count();
+    assert!(previews >= 3, "previews dropped: {:?}", kinds);
+}
+
+#[tokio::test]
+async fn img2img_uploads_the_source_image() {
+    let app = TestApp::start().await;
+    let mut ws = app.connect_ws("").await;
+
+    let mut request = generate_body(&ws.client_id);
+    request["denoise"] = json!(0.6);
+    let form = reqwest::multipart::Form::new()
+        .text("request", request.to_string())
+        .part(
+            "image",
+            reqwest::multipart::Part::bytes(common::mock_comfyui::png_bytes(32))
+                .file_name("source.png"),
+        );
+    let response = app
+        .http
+        .post(format!("{}/api/img2img", app.url))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let queued: Value = response.json().await.unwrap();
+    let prompt_id = queued["prompt_id"].as_str().unwrap();
+
+    ws.collect_until(prompt_id, "completed").await;
+
+    let uploads = app.comfyui.uploads();
+    assert_eq!(uploads.len(), 1);
+    let submitted = &app.comfyui.prompts()[0];
+    assert!(submitted.has_node("LoadImage"));
+    assert_eq!(
+        submitted.input("LoadImage", "image"),
+        Some(&json!(uploads[0]))
+    );
+    let denoise = submitted
+        .input("KSampler", "denoise")
+        .unwrap()
+        .as_f64()
+        .unwrap();
+    assert!((denoise - 0.6).abs() < 1e-6);
+}
+
+#[tokio::test]
+async fn parse_image_reads_embedded_parameters() {
+    let app = TestApp::start().await;
+    let parse = |png: Vec<u8>| {
+        let form = reqwest::multipart::Form::new().part(
+            "image",
+            reqwest::multipart::Part::bytes(png).file_name("shared.png"),
+        );
+        app.http
+            .post(format!("{}/api/parse-image", app.url))
+            .multipart(form)
+            .send()
+    };
+
+    let parameters = "1girl, smile\nNegative prompt: lowres\n\
+        Steps: 24, Sampler: Euler a, CFG scale: 5, Seed: 77, Size: 640x960";
+    let png = backend::metadata::embed_text_chunks(
+        &common::mock_comfyui::png_bytes(8),
+        &[("parameters".to_string(), parameters.to_string())],
+    )
+    .unwrap();
+    let response = parse(png).await.unwrap();
+    assert_eq!(response.status(), 200);
+    let parsed: Value = response.json().await.unwrap();
+    assert_eq!(parsed["source"], "a1111");
+    assert_eq!(parsed["request"]["prompt"], "1girl, smile");
+    assert_eq!(parsed["request"]["negative_prompt"], "lowres");
+    assert_eq!(parsed["request"]["steps"], 24);
+    assert_eq!(parsed["request"]["sampler_name"], "euler_ancestral");
+    assert_eq!(parsed["request"]["seed"], 77);
+    assert_eq!(parsed["request"]["width"], 640);
+
+    let response = parse(common::mock_comfyui::png_bytes(8)).await.unwrap();
+    assert_eq!(response.status(), 404);
+}
+
+#[tokio::test]
+async fn cancelling_a_running_job_interrupts_comfyui() {
+    let comfyui = MockComfyUI::with_script(Script {
+        steps: 50,
+        step_delay: Duration::from_millis(50),
+        ..Script::default()
+    })
+    .await;
+    let app = TestApp::with_comfyui(comfyui).await;
+    let mut ws = app.connect_ws("").await;
+
+    let queued: Value = app
+        .post_json("/api/generate", &generate_body(&ws.client_id))
+        .await
+        .json()
+        .await
+        .unwrap();
+    let prompt_id = queued["prompt_id"].as_str().unwrap().to_string();
+    ws.collect_until(&prompt_id, "progress").await;
+
+    let response = app
+        .post_json(&format!("/api/jobs/{}/cancel", prompt_id), &json!({}))
+        .await;
+    assert_eq!(response.status(), 200);
+
+    ws.collect_until(&prompt_id, "cancelled").await;
+    assert_eq!(app.comfyui.interrupts(), 1);
+
+    let record: Value = app
+        .get(&format!("/api/generations/{}", prompt_id))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(record["status"], "cancelled");
+}
+
+#[tokio::test]
+async fn jobs_and_history_finish_on_success_not_on_the_first_output() {
+    let comfyui = MockComfyUI::with_script(Script {
+        steps: 1,
+        output_delay: Duration::from_millis(300),
+        ..Script::default()
+    })
+    .await;
+    let app = TestApp::with_comfyui(comfyui).await;
+    let mut ws = app.connect_ws("").await;
+
+    let queued: Value = app
+        .post_json("/api/generate", &generate_body(&ws.client_id))
+        .await
+        .json()
+        .await
+        .unwrap();
+    let prompt_id = queued["prompt_id"].as_str().unwrap().to_string();
+
+    // The template's PreviewImage node still runs after SaveImage reported the image
+    ws.collect_until(&prompt_id, "completed").await;
+    let job: Value = app
+        .get(&format!("/api/jobs/{}", prompt_id))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(job["status"], "running");
+    let record: Value = app
+        .get(&format!("/api/generations/{}", prompt_id))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(record["status"], "running");
+    assert_eq!(record["images"].as_array().unwrap().len(), 1);
+
+    ws.collect_until(&prompt_id, "success").await;
+    let job: Value = app
+        .get(&format!("/api/jobs/{}", prompt_id))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(job["status"], "completed");
+    let record: Value = app
+        .get(&format!("/api/generations/{}", prompt_id))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(record["status"], "completed");
+    assert!(record["completed_at"].is_u64());
+}
+
+#[tokio::test]
+async fn invalid_requests_are_rejected_before_reaching_comfyui() {
+    let app = TestApp::start().await;
+
+    let response = app
+        .post_json("/api/generate", &json!({ "prompt": "1girl", "width": 8 }))
+        .await;
+    assert_eq!(response.status(), 400);
+    let body: Value = response.json().await.unwrap();
+    assert!(body["error"].as_str().is_some());
+
+    assert!(app.comfyui.prompts().is_empty());
+}
+
+#[tokio::test]
+async fn parameters_are_checked_against_object_info() {
+    let app = TestApp::start().await;
+
+    let mut request = generate_body("schema-client");
+    request["sampler_name"] = json!("eulr");
+    request["cfg"] = json!(250.0);
+    request["steps"] = json!(0);
+    request["width"] = json!(1000);
+    let response = app.post_json("/api/generate", &request).await;
+    assert_eq!(response.status(), 422);
+
+    let body: Value = response.json().await.unwrap();
+    let fields = body["fields"].as_array().unwrap();
+    let field = |name: &str| {
+        fields
+            .iter()
+            .find(|f| f["field"] == name)
+            .unwrap_or_else(|| panic!("no error for {} in {}", name, body))
+    };
+    assert!(field("sampler_name")["allowed"]
+        .as_array()
+        .unwrap()
+        .contains(&json!("euler")));
+    assert_eq!(field("cfg")["max"], 100.0);
+    assert_eq!(field("steps")["min"], 1.0);
+    assert_eq!(field("width")["multiple_of"], 16.0);
+    assert_eq!(fields.len(), 4);
+
+    // The same details reach WebSocket clients
+    let mut ws = app.connect_ws("").await;
+    request["scheduler"] = json!("karas");
+    request["sampler_name"] = json!("euler");
+    request["cfg"] = json!(4.5);
+    request["steps"] = json!(20);
+    request["width"] = json!(1024);
+    ws.send(json!({ "type": "generate", "request_id": "bad", "request": request }))
+        .await;
+    let error = ws.next_of("request_error").await;
+    assert_eq!(error["status"], 422);
+    assert_eq!(error["fields"][0]["field"], "scheduler");
+
+    assert!(app.comfyui.prompts().is_empty());
+}
+
+#[tokio::test]
+async fn models_can_be_chosen_explicitly() {
+    let app = TestApp::start().await;
+
+    let mut request = generate_body("model-client");
+    request["clip1"] = json!("jina_clip_v2.safetensors");
+    request["clip2"] = json!("gemma_3_4b_it.safetensors");
+    request["weight_dtype"] = json!("fp8_e4m3fn");
+    let response = app.post_json("/api/generate", &request).await;
+    assert_eq!(response.status(), 200);
+
+    let submitted = &app.comfyui.prompts()[0];
+    assert_eq!(
+        submitted.input("DualCLIPLoader", "clip_name1"),
+        Some(&json!("jina_clip_v2.safetensors"))
+    );
+    assert_eq!(
+        submitted.input("DualCLIPLoader", "clip_name2"),
+        Some(&json!("gemma_3_4b_it.safetensors"))
+    );
+    assert_eq!(
+        submitted.input("UNETLoader", "weight_dtype"),
+        Some(&json!("fp8_e4m3fn"))
+    );
+
+    request["weight_dtype"] = json!("fp4");
+    request["vae"] = json!("sdxl_vae.safetensors");
+    let response = app.post_json("/api/generate", &request).await;
+    assert_eq!(response.status(), 422);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["fields"][0]["field"], "weight_dtype");
+
+    request["weight_dtype"] = json!("default");
+    let response = app.post_json("/api/generate", &request).await;
+    assert_eq!(response.status(), 422);
+    let body: Value = response.json().await.unwrap();
+    assert!(body["error"].as_str().unwrap().starts_with("Model missing"));
+    assert_eq!(body["fields"][0]["field"], "vae");
+    assert_eq!(
+        body["fields"][0]["allowed"],
+        json!(["diffusion_pytorch_model.safetensors"])
+    );
+    assert_eq!(app.comfyui.prompts().len(), 1);
+}
+
+#[tokio::test]
+async fn missing_models_are_reported_instead_of_guessed() {
+    let app = TestApp::start().await;
+    app.comfyui
+        .set_options("UNETLoader", "unet_name", &["sdxl_base.safetensors"]);
+
+    let response = app
+        .post_json("/api/generate", &generate_body("missing-client"))
+        .await;
+    assert_eq!(response.status(), 422);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["fields"][0]["field"], "unet");
+    assert_eq!(
+        body["fields"][0]["allowed"],
+        json!(["sdxl_base.safetensors"])
+    );
+    assert!(app.comfyui.prompts().is_empty());
+
+    // Naming the file explicitly is enough
+    let mut request = generate_body("missing-client");
+    request["unet"] = json!("sdxl_base.safetensors");
+    let response = app.post_json("/api/generate", &request).await;
+    assert_eq!(response.status(), 200);
+}
+
+#[tokio::test]
+async fn structured_prompts_are_sent_as_newbie_xml() {
+    let app = TestApp::start().await;
+
+    let structured = json!({
+        "characters": [
+            {
+                "name": "Hatsune Miku",
+                "gender": "1girl",
+                "appearance": "long twintails, aqua eyes",
+                "action": "holding <microphone> & waving"
+            },
+            { "gender": "1boy", "clothing": "suit" }
+        ],
+        "general_tags": { "style": "anime", "background": "concert stage" },
+        "caption": "Two singers on stage."
+    });
+    let response = app
+        .post_json(
+            "/api/generate",
+            &json!({ "structured_prompt": structured, "seed": 7 }),
+        )
+        .await;
+    assert_eq!(response.status(), 200);
+
+    let expected = "<character_1>\n\
+        <n>Hatsune Miku</n>\n\
+        <gender>1girl</gender>\n\
+        <appearance>long twintails, aqua eyes</appearance>\n\
+        <action>holding &lt;microphone&gt; &amp; waving</action>\n\
+        </character_1>\n\n\
+        <character_2>\n\
+        <n>character_2</n>\n\
+        <gender>1boy</gender>\n\
+        <clothing>suit</clothing>\n\
+        </character_2>\n\n\
+        <general_tags>\n\
+        <style>anime</style>\n\
+        <background>concert stage</background>\n\
+        </general_tags>\n\n\
+        <caption>Two singers on stage.</caption>";
+    let submitted = &app.comfyui.prompts()[0];
+    let text = submitted.workflow["61"]["inputs"]["text"].as_str().unwrap();
+    assert!(text.ends_with(expected), "unexpected prompt text {}", text);
+
+    // History keeps the text that was submitted
+    let record: Value = app
+        .get(&format!("/api/generations/{}", submitted.prompt_id))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(record["request"]["prompt"], expected);
+    assert!(record["request"].get("structured_prompt").is_none());
+
+    let response = app
+        .post_json(
+            "/api/generate",
+            &json!({ "prompt": "1girl", "structured_prompt": structured }),
+        )
+        .await;
+    assert_eq!(response.status(), 400);
+    let response = app
+        .post_json("/api/generate", &json!({ "structured_prompt": {} }))
+        .await;
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn rejected_sweeps_leave_seed_sequences_alone() {
+    let app = TestApp::start().await;
+
+    let mut base = generate_body("sweep-client");
+    base["seed_mode"] = json!("increment");
+    let response = app
+        .post_json(
+            "/api/sweeps",
+            &json!({
+                "base": base,
+                "axes": [{ "param": "sampler", "values": ["euler", "not_a_sampler"] }]
+            }),
+        )
+        .await;
+    assert!(response.status().is_client_error());
+    assert!(app.comfyui.prompts().is_empty());
+
+    // The sequence still starts at the given seed
+    let queued: Value = app
+        .post_json("/api/generate", &base)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(queued["seed"], 1234);
+}
+
+#[tokio::test]
+async fn sweeps_queue_every_combination_and_render_a_grid() {
+    let app = TestApp::start().await;
+    let mut ws = app.connect_ws("").await;
+
+    let response = app
+        .post_json(
+            "/api/sweeps",
+            &json!({
+                "base": generate_body(&ws.client_id),
+                "axes": [
+                    { "param": "cfg", "values": [3.0, 5.0] },
+                    { "param": "seed", "values": [11, 22] }
+                ]
+            }),
+        )
+        .await;
+    assert_eq!(response.status(), 200);
+    let sweep: Value = response.json().await.unwrap();
+    let sweep_id = sweep["id"].as_str().unwrap().to_string();
+    assert_eq!(sweep["total"], 4);
+
+    let mut progress = Vec::new();
+    let completed = loop {
+        let message = ws.next().await;
+        if message["sweep_id"] != sweep_id.as_str() {
+            continue;
+        }
+        match message["type"].as_str() {
+            Some("sweep_progress") => progress.push(message["completed"].as_u64().unwrap()),
+            Some("sweep_completed") => break message,
+            _ => {}
+        }
+    };
+    assert_eq!(progress, [1, 2, 3, 4]);
+    let grid_url = completed["grid_url"].as_str().unwrap();
+    assert_eq!(grid_url, format!("/api/sweeps/{}/grid", sweep_id));
+
+    // Cells are queued x first, with the seed axis pinning each seed
+    let submitted: Vec<(f64, u64)> = app
+        .comfyui
+        .prompts()
+        .iter()
+        .map(|p| {
+            (
+                p.input("KSampler", "cfg").unwrap().as_f64().unwrap(),
+                p.input("KSampler", "seed").unwrap().as_u64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(submitted, [(3.0, 11), (5.0, 11), (3.0, 22), (5.0, 22)]);
+
+    let grid = app.get(grid_url).await;
+    assert_eq!(grid.status(), 200);
+    assert!(grid.bytes().await.unwrap().starts_with(b"\x89PNG"));
+}
+
+#[tokio::test]
+async fn websocket_requests_get_replies() {
+    let app = TestApp::start().await;
+    let mut ws = app.connect_ws("").await;
+
+    ws.send(json!({ "type": "ping", "request_id": "p1" })).await;
+    let pong = ws.next_of("pong").await;
+    assert_eq!(pong["request_id"], "p1");
+
+    let mut request = generate_body(&ws.client_id);
+    request.as_object_mut().unwrap().remove("client_id");
+    ws.send(json!({ "type": "generate", "request_id": "g1", "request": request }))
+        .await;
+    let ack = ws.next_of("ack").await;
+    assert_eq!(ack["request_id"], "g1");
+    let prompt_id = ack["result"]["prompt_id"].as_str().unwrap().to_string();
+
+    ws.collect_until(&prompt_id, "completed").await;
+}
+
+#[tokio::test]
+async fn other_clients_only_see_prompts_they_watch() {
+    let app = TestApp::start().await;
+    let mut owner = app.connect_ws("").await;
+    let mut watcher = app.connect_ws("watch=all").await;
+    let mut bystander = app.connect_ws("").await;
+
+    let queued: Value = app
+        .post_json("/api/generate", &generate_body(&owner.client_id))
+        .await
+        .json()
+        .await
+        .unwrap();
+    let prompt_id = queued["prompt_id"].as_str().unwrap().to_string();
+
+    owner.collect_until(&prompt_id, "completed").await;
+    watcher.collect_until(&prompt_id, "completed").await;
+
+    // Prompts without an owner only reach the watchers
+    let mut ownerless = generate_body("");
+    ownerless.as_object_mut().unwrap().remove("client_id");
+    let queued: Value = app
+        .post_json("/api/generate", &ownerless)
+        .await
+        .json()
+        .await
+        .unwrap();
+    let ownerless_id = queued["prompt_id"].as_str().unwrap().to_string();
+    watcher.collect_until(&ownerless_id, "completed").await;
+
+    // Queue status goes to everyone, prompt events only to the owner and watchers
+    bystander.send(json!({ "type": "ping" })).await;
+    loop {
+        let next = bystander.next().await;
+        assert!(next.get("prompt_id").is_none(), "unexpected event {}", next);
+        if next["type"] == "pong" {
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn server_sent_events_follow_a_prompt() {
+    let app = TestApp::start().await;
+
+    let response = app.get("/api/events?clientId=sse-client").await;
+    assert_eq!(response.status(), 200);
+    assert!(response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/event-stream"));
+    let mut stream = response.bytes_stream();
+
+    let queued: Value = app
+        .post_json("/api/generate", &generate_body("sse-client"))
+        .await
+        .json()
+        .await
+        .unwrap();
+    let prompt_id = queued["prompt_id"].as_str().unwrap().to_string();
+
+    let mut body = String::new();
+    let mut events = Vec::new();
+    while !events
+        .iter()
+        .any(|e: &Value| e["type"] == "completed" && e["prompt_id"] == prompt_id.as_str())
+    {
+        let chunk = tokio::time::timeout(EVENT_TIMEOUT, stream.next())
+            .await
+            .unwrap_or_else(|_| panic!("timed out waiting for server-sent events: {}", body))
+            .expect("event stream closed")
+            .unwrap();
+        body.push_str(&String::from_utf8_lossy(&chunk));
+        events = body
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .map(|data| serde_json::from_str(data).unwrap())
+            .collect();
+    }
+
+    let kinds = types(&events);
+    assert_eq!(kinds[..2], ["connected", "snapshot"]);
+    assert!(kinds.contains(&"progress"));
+    // Previews are linked rather than inlined
+    let preview = events.iter().find(|e| e["type"] == "preview").unwrap();
+    assert!(preview.get("image_data").is_none());
+    assert!(preview["image_url"]
+        .as_str()
+        .unwrap()
+        .starts_with(&format!("/api/previews/{}", prompt_id)));
+    // Every event but the previews carries an id to resume from
+    let ids = body.lines().filter(|line| line.starts_with("id: ")).count();
+    assert_eq!(
+        ids,
+        events.len() - 1 - kinds.iter().filter(|k| **k == "preview").count()
+    );
+
+    let preview = app.get(&format!("/api/previews/{}", prompt_id)).await;
+    assert_eq!(preview.status(), 200);
+}
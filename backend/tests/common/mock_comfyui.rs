@@ -0,0 +1,715 @@
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        Multipart, Path, Query, Request, State, WebSocketUpgrade,
+    },
+    http::StatusCode,
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, Notify};
+
+/// How the fake server runs a prompt
+#[derive(Debug, Clone)]
+pub struct Script {
+    /// Sampler steps, each sending a `progress` event and a binary preview
+    pub steps: u32,
+    /// Pause before every step
+    pub step_delay: Duration,
+    /// Pause before every output node
+    pub output_delay: Duration,
+}
+
+impl Default for Script {
+    fn default() -> Self {
+        Self {
+            steps: 3,
+            step_delay: Duration::from_millis(20),
+            output_delay: Duration::ZERO,
+        }
+    }
+}
+
+/// A prompt received on `POST /prompt`
+#[derive(Debug, Clone)]
+pub struct SubmittedPrompt {
+    pub prompt_id: String,
+    pub number: u32,
+    pub client_id: Option<String>,
+    pub workflow: Value,
+}
+
+impl SubmittedPrompt {
+    /// Input `name` of the first node of `class_type`
+    pub fn input(&self, class_type: &str, name: &str) -> Option<&Value> {
+        self.workflow
+            .as_object()?
+            .values()
+            .find(|node| node["class_type"] == class_type)?
+            .get("inputs")?
+            .get(name)
+    }
+
+    /// Whether the workflow has a node of `class_type`
+    pub fn has_node(&self, class_type: &str) -> bool {
+        self.workflow
+            .as_object()
+            .is_some_and(|nodes| nodes.values().any(|node| node["class_type"] == class_type))
+    }
+}
+
+/// A frame for the WebSocket clients, `None` target meaning every client
+type Frame = (Option<String>, Message);
+
+#[derive(Default)]
+struct Inner {
+    submitted: Vec<SubmittedPrompt>,
+    pending: VecDeque<SubmittedPrompt>,
+    running: Option<SubmittedPrompt>,
+    history: serde_json::Map<String, Value>,
+    uploads: Vec<String>,
+    interrupts: usize,
+    object_info: Value,
+    object_info_requests: usize,
+    /// Edge of the images served by `/view`, telling apart instances in tests
+    image_size: u32,
+    next_number: u32,
+    next_image: u32,
+}
+
+struct MockState {
+    script: Script,
+    inner: Mutex<Inner>,
+    frames: broadcast::Sender<Frame>,
+    listeners: AtomicUsize,
+    work: Notify,
+    interrupted: AtomicBool,
+    /// Every request fails and WebSockets are closed while set
+    down: AtomicBool,
+    went_down: Notify,
+}
+
+impl MockState {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn send_json(&self, target: Option<&str>, message: Value) {
+        let _ = self.frames.send((
+            target.map(str::to_string),
+            Message::Text(message.to_string()),
+        ));
+    }
+
+    fn send_status(&self) {
+        let remaining = {
+            let inner = self.lock();
+            inner.pending.len() + usize::from(inner.running.is_some())
+        };
+        self.send_json(
+            None,
+            json!({
+                "type": "status",
+                "data": { "status": { "exec_info": { "queue_remaining": remaining } } }
+            }),
+        );
+    }
+}
+
+/// In-process stand-in for a ComfyUI server
+///
+/// Prompts run one at a time following the [`Script`], sending the events a real server
+/// sends to the submitting client: `execution_start`, `execution_cached`, `executing`,
+/// `progress` with a binary PNG preview per step, `executed` for every output node,
+/// then `executing` with a null node and `execution_success`.
+#[derive(Clone)]
+pub struct MockComfyUI {
+    pub url: String,
+    state: Arc<MockState>,
+}
+
+impl MockComfyUI {
+    pub async fn start() -> Self {
+        Self::with_script(Script::default()).await
+    }
+
+    pub async fn with_script(script: Script) -> Self {
+        let state = Arc::new(MockState {
+            script,
+            inner: Mutex::new(Inner {
+                object_info: object_info(),
+                image_size: 16,
+                ..Inner::default()
+            }),
+            frames: broadcast::channel(256).0,
+            listeners: AtomicUsize::new(0),
+            work: Notify::new(),
+            interrupted: AtomicBool::new(false),
+            down: AtomicBool::new(false),
+            went_down: Notify::new(),
+        });
+
+        let app = Router::new()
+            .route("/system_stats", get(system_stats))
+            .route("/object_info", get(get_object_info))
+            .route("/prompt", post(queue_prompt))
+            .route("/queue", get(get_queue).post(edit_queue))
+            .route("/history", get(all_history))
+            .route("/history/:prompt_id", get(prompt_history))
+            .route("/view", get(view))
+            .route("/interrupt", post(interrupt))
+            .route("/upload/image", post(upload_image))
+            .route("/ws", get(websocket))
+            .layer(middleware::from_fn_with_state(state.clone(), unless_down))
+            .with_state(state.clone());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        tokio::spawn(run_prompts(state.clone()));
+
+        Self { url, state }
+    }
+
+    /// Wait until a client is connected to `/ws`
+    pub async fn wait_for_listener(&self) {
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        while self.state.listeners.load(Ordering::SeqCst) == 0 {
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "backend never connected to the mock ComfyUI WebSocket"
+            );
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    /// Every prompt received so far, in order
+    pub fn prompts(&self) -> Vec<SubmittedPrompt> {
+        self.state.lock().submitted.clone()
+    }
+
+    /// Names of the uploaded images
+    pub fn uploads(&self) -> Vec<String> {
+        self.state.lock().uploads.clone()
+    }
+
+    /// Replace the options of a combo input in `/object_info`, e.g. the installed models
+    pub fn set_options(&self, class_type: &str, input: &str, options: &[&str]) {
+        self.state.lock().object_info[class_type]["input"]["required"][input] = json!([options]);
+    }
+
+    /// Number of `GET /object_info` calls
+    pub fn object_info_requests(&self) -> usize {
+        self.state.lock().object_info_requests
+    }
+
+    /// Number of `POST /interrupt` calls that stopped a running prompt
+    pub fn interrupts(&self) -> usize {
+        self.state.lock().interrupts
+    }
+
+    /// Serve `size`x`size` images from `/view`
+    pub fn set_image_size(&self, size: u32) {
+        self.state.lock().image_size = size;
+    }
+
+    /// Simulate a crashed server: answer every request with 503 and drop the WebSockets
+    pub fn go_down(&self) {
+        self.state.down.store(true, Ordering::SeqCst);
+        self.state.went_down.notify_waiters();
+    }
+}
+
+async fn unless_down(
+    State(state): State<Arc<MockState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if state.down.load(Ordering::SeqCst) {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+    next.run(request).await
+}
+
+/// `/object_info` for the node classes the backend uses
+pub fn object_info() -> Value {
+    json!({
+        "KSampler": { "input": { "required": {
+            "model": ["MODEL"],
+            "seed": ["INT", { "default": 0, "min": 0, "max": 18446744073709551615u64 }],
+            "steps": ["INT", { "default": 20, "min": 1, "max": 10000 }],
+            "cfg": ["FLOAT", { "default": 8.0, "min": 0.0, "max": 100.0, "step": 0.1, "round": 0.01 }],
+            "sampler_name": [["euler", "euler_ancestral", "dpmpp_2m", "res_multistep"]],
+            "scheduler": [["normal", "karras", "simple", "linear_quadratic"]],
+            "positive": ["CONDITIONING"],
+            "negative": ["CONDITIONING"],
+            "latent_image": ["LATENT"],
+            "denoise": ["FLOAT", { "default": 1.0, "min": 0.0, "max": 1.0, "step": 0.01 }]
+        } } },
+        "EmptySD3LatentImage": { "input": { "required": {
+            "width": ["INT", { "default": 1024, "min": 16, "max": 16384, "step": 16 }],
+            "height": ["INT", { "default": 1024, "min": 16, "max": 16384, "step": 16 }],
+            "batch_size": ["INT", { "default": 1, "min": 1, "max": 4096 }]
+        } } },
+        "UNETLoader": { "input": { "required": {
+            "unet_name": [["newbie-image.safetensors"]],
+            "weight_dtype": [["default", "fp8_e4m3fn", "fp8_e4m3fn_fast", "fp8_e5m2"]]
+        } } },
+        "DualCLIPLoader": { "input": { "required": {
+            "clip_name1": [["gemma_3_4b_it.safetensors", "jina_clip_v2.safetensors"]],
+            "clip_name2": [["gemma_3_4b_it.safetensors", "jina_clip_v2.safetensors"]],
+            "type": [["sdxl", "sd3", "flux", "newbie"]]
+        } } },
+        "VAELoader": { "input": { "required": {
+            "vae_name": [["diffusion_pytorch_model.safetensors"]]
+        } } },
+        "LoraLoaderModelOnly": { "input": { "required": {
+            "model": ["MODEL"],
+            "lora_name": [["styles/watercolor.safetensors"]],
+            "strength_model": ["FLOAT", { "default": 1.0, "min": -100.0, "max": 100.0, "step": 0.01 }]
+        } } },
+        "UpscaleModelLoader": { "input": { "required": {
+            "model_name": [["4x-AnimeSharp.pth"]]
+        } } },
+        "LatentUpscaleBy": { "input": { "required": {
+            "samples": ["LATENT"],
+            "upscale_method": [["nearest-exact", "bilinear", "area", "bicubic", "bislerp"]],
+            "scale_by": ["FLOAT", { "default": 1.5, "min": 0.01, "max": 8.0, "step": 0.01 }]
+        } } },
+        "CLIPTextEncode": { "input": { "required": {
+            "text": ["STRING", { "multiline": true }],
+            "clip": ["CLIP"]
+        } } },
+        "VAEDecode": { "input": { "required": { "samples": ["LATENT"], "vae": ["VAE"] } } },
+        "VAEEncode": { "input": { "required": { "pixels": ["IMAGE"], "vae": ["VAE"] } } },
+        "RescaleCFG": { "input": { "required": {
+            "model": ["MODEL"],
+            "multiplier": ["FLOAT", { "default": 0.7, "min": 0.0, "max": 1.0, "step": 0.01 }]
+        } } },
+        "LoadImage": { "input": { "required": { "image": [[]] } } },
+        "SaveImage": { "input": { "required": { "images": ["IMAGE"] } } },
+        "PreviewImage": { "input": { "required": { "images": ["IMAGE"] } } }
+    })
+}
+
+/// A small opaque PNG
+pub fn png_bytes(size: u32) -> Vec<u8> {
+    let image = image::RgbImage::from_pixel(size, size, image::Rgb([200, 120, 40]));
+    let mut data = Vec::new();
+    image
+        .write_to(
+            &mut std::io::Cursor::new(&mut data),
+            image::ImageFormat::Png,
+        )
+        .unwrap();
+    data
+}
+
+async fn get_object_info(State(state): State<Arc<MockState>>) -> Json<Value> {
+    let mut inner = state.lock();
+    inner.object_info_requests += 1;
+    Json(inner.object_info.clone())
+}
+
+async fn system_stats() -> Json<Value> {
+    Json(json!({
+        "system": { "os": "posix", "python_version": "3.11.9", "embedded_python": false },
+        "devices": [{
+            "name": "cuda:0 Mock GPU",
+            "type": "cuda",
+            "index": 0,
+            "vram_total": 25769803776u64,
+            "vram_free": 21474836480u64,
+            "torch_vram_total": 0,
+            "torch_vram_free": 0
+        }]
+    }))
+}
+
+#[derive(Deserialize)]
+struct PromptRequest {
+    prompt: Value,
+    client_id: Option<String>,
+    prompt_id: Option<String>,
+}
+
+async fn queue_prompt(
+    State(state): State<Arc<MockState>>,
+    Json(request): Json<PromptRequest>,
+) -> impl IntoResponse {
+    if request
+        .prompt
+        .as_object()
+        .is_none_or(|nodes| nodes.is_empty())
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": { "type": "prompt_no_outputs", "message": "Prompt has no outputs" },
+                "node_errors": {}
+            })),
+        );
+    }
+
+    let prompt = {
+        let mut inner = state.lock();
+        inner.next_number += 1;
+        let prompt = SubmittedPrompt {
+            prompt_id: request
+                .prompt_id
+                .unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+            number: inner.next_number,
+            client_id: request.client_id,
+            workflow: request.prompt,
+        };
+        inner.submitted.push(prompt.clone());
+        inner.pending.push_back(prompt.clone());
+        prompt
+    };
+    state.send_status();
+    state.work.notify_one();
+
+    (
+        StatusCode::OK,
+        Json(json!({
+            "prompt_id": prompt.prompt_id,
+            "number": prompt.number,
+            "node_errors": {}
+        })),
+    )
+}
+
+fn queue_item(prompt: &SubmittedPrompt) -> Value {
+    json!([prompt.number, prompt.prompt_id, prompt.workflow, {}, []])
+}
+
+async fn get_queue(State(state): State<Arc<MockState>>) -> Json<Value> {
+    let inner = state.lock();
+    Json(json!({
+        "queue_running": inner.running.iter().map(queue_item).collect::<Vec<_>>(),
+        "queue_pending": inner.pending.iter().map(queue_item).collect::<Vec<_>>()
+    }))
+}
+
+#[derive(Deserialize)]
+struct QueueEdit {
+    #[serde(default)]
+    delete: Vec<String>,
+    #[serde(default)]
+    clear: bool,
+}
+
+async fn edit_queue(
+    State(state): State<Arc<MockState>>,
+    Json(edit): Json<QueueEdit>,
+) -> StatusCode {
+    {
+        let mut inner = state.lock();
+        if edit.clear {
+            inner.pending.clear();
+        }
+        inner
+            .pending
+            .retain(|p| !edit.delete.contains(&p.prompt_id));
+    }
+    state.send_status();
+    StatusCode::OK
+}
+
+async fn all_history(State(state): State<Arc<MockState>>) -> Json<Value> {
+    Json(Value::Object(state.lock().history.clone()))
+}
+
+async fn prompt_history(
+    State(state): State<Arc<MockState>>,
+    Path(prompt_id): Path<String>,
+) -> Json<Value> {
+    let inner = state.lock();
+    let mut found = serde_json::Map::new();
+    if let Some(entry) = inner.history.get(&prompt_id) {
+        found.insert(prompt_id, entry.clone());
+    }
+    Json(Value::Object(found))
+}
+
+#[derive(Deserialize)]
+struct ViewQuery {
+    filename: String,
+}
+
+async fn view(
+    State(state): State<Arc<MockState>>,
+    Query(query): Query<ViewQuery>,
+) -> impl IntoResponse {
+    if !query.filename.ends_with(".png") {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let size = state.lock().image_size;
+    Ok((
+        [(axum::http::header::CONTENT_TYPE, "image/png")],
+        png_bytes(size),
+    ))
+}
+
+#[derive(Deserialize)]
+struct InterruptRequest {
+    prompt_id: Option<String>,
+}
+
+async fn interrupt(State(state): State<Arc<MockState>>, body: axum::body::Bytes) -> StatusCode {
+    let target = serde_json::from_slice::<InterruptRequest>(&body)
+        .ok()
+        .and_then(|r| r.prompt_id);
+    let mut inner = state.lock();
+    let matches = inner
+        .running
+        .as_ref()
+        .is_some_and(|running| target.as_ref().is_none_or(|id| *id == running.prompt_id));
+    if matches {
+        inner.interrupts += 1;
+        state.interrupted.store(true, Ordering::SeqCst);
+    }
+    StatusCode::OK
+}
+
+async fn upload_image(
+    State(state): State<Arc<MockState>>,
+    mut multipart: Multipart,
+) -> Result<Json<Value>, StatusCode> {
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|_| StatusCode::BAD_REQUEST)?
+    {
+        if field.name() != Some("image") {
+            continue;
+        }
+        let name = field
+            .file_name()
+            .map(str::to_string)
+            .ok_or(StatusCode::BAD_REQUEST)?;
+        let data = field.bytes().await.map_err(|_| StatusCode::BAD_REQUEST)?;
+        if data.is_empty() {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        state.lock().uploads.push(name.clone());
+        return Ok(Json(
+            json!({ "name": name, "subfolder": "", "type": "input" }),
+        ));
+    }
+    Err(StatusCode::BAD_REQUEST)
+}
+
+#[derive(Deserialize)]
+struct WsQuery {
+    #[serde(rename = "clientId")]
+    client_id: Option<String>,
+}
+
+async fn websocket(
+    State(state): State<Arc<MockState>>,
+    Query(query): Query<WsQuery>,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    let client_id = query
+        .client_id
+        .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string());
+    ws.on_upgrade(move |socket| forward_frames(socket, state, client_id))
+}
+
+async fn forward_frames(socket: WebSocket, state: Arc<MockState>, client_id: String) {
+    let (mut sender, mut receiver) = socket.split();
+    let mut frames = state.frames.subscribe();
+
+    let remaining = {
+        let inner = state.lock();
+        inner.pending.len() + usize::from(inner.running.is_some())
+    };
+    let hello = json!({
+        "type": "status",
+        "data": { "status": { "exec_info": { "queue_remaining": remaining } }, "sid": client_id }
+    });
+    if sender.send(Message::Text(hello.to_string())).await.is_err() {
+        return;
+    }
+    state.listeners.fetch_add(1, Ordering::SeqCst);
+
+    loop {
+        tokio::select! {
+            frame = frames.recv() => match frame {
+                Ok((target, message)) => {
+                    if target.as_ref().is_some_and(|target| *target != client_id) {
+                        continue;
+                    }
+                    if sender.send(message).await.is_err() {
+                        break;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = state.went_down.notified() => break,
+            incoming = receiver.next() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+    state.listeners.fetch_sub(1, Ordering::SeqCst);
+}
+
+/// Run queued prompts one at a time
+async fn run_prompts(state: Arc<MockState>) {
+    loop {
+        let prompt = {
+            let mut inner = state.lock();
+            let next = inner.pending.pop_front();
+            inner.running = next.clone();
+            next
+        };
+        let Some(prompt) = prompt else {
+            state.work.notified().await;
+            continue;
+        };
+        state.interrupted.store(false, Ordering::SeqCst);
+        let entry = execute(&state, &prompt).await;
+        {
+            let mut inner = state.lock();
+            inner.running = None;
+            inner.history.insert(prompt.prompt_id.clone(), entry);
+        }
+        state.send_status();
+    }
+}
+
+/// Send the events of one prompt, returning its history entry
+async fn execute(state: &MockState, prompt: &SubmittedPrompt) -> Value {
+    let target = prompt.client_id.as_deref();
+    let id = prompt.prompt_id.as_str();
+    let nodes_of = |class_type: &str| -> Vec<String> {
+        prompt
+            .workflow
+            .as_object()
+            .map(|nodes| {
+                nodes
+                    .iter()
+                    .filter(|(_, node)| node["class_type"] == class_type)
+                    .map(|(id, _)| id.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let sampler = nodes_of("KSampler")
+        .into_iter()
+        .next()
+        .unwrap_or_else(|| "1".to_string());
+
+    state.send_json(
+        target,
+        json!({ "type": "execution_start", "data": { "prompt_id": id } }),
+    );
+    state.send_json(
+        target,
+        json!({ "type": "execution_cached", "data": { "nodes": [], "prompt_id": id } }),
+    );
+    state.send_json(
+        target,
+        json!({ "type": "executing", "data": { "node": sampler, "prompt_id": id } }),
+    );
+
+    let preview = {
+        // [event type 1 = PREVIEW_IMAGE][format 2 = PNG][image]
+        let mut frame = 1u32.to_be_bytes().to_vec();
+        frame.extend_from_slice(&2u32.to_be_bytes());
+        frame.extend_from_slice(&png_bytes(8));
+        frame
+    };
+    for step in 1..=state.script.steps {
+        tokio::time::sleep(state.script.step_delay).await;
+        if state.interrupted.swap(false, Ordering::SeqCst) {
+            state.send_json(
+                target,
+                json!({
+                    "type": "execution_interrupted",
+                    "data": {
+                        "prompt_id": id,
+                        "node_id": sampler,
+                        "node_type": "KSampler",
+                        "executed": []
+                    }
+                }),
+            );
+            return json!({
+                "prompt": queue_item(prompt),
+                "outputs": {},
+                "status": {
+                    "status_str": "error",
+                    "completed": false,
+                    "messages": [["execution_interrupted", { "prompt_id": id }]]
+                }
+            });
+        }
+        state.send_json(
+            target,
+            json!({
+                "type": "progress",
+                "data": { "value": step, "max": state.script.steps, "prompt_id": id, "node": sampler }
+            }),
+        );
+        let _ = state
+            .frames
+            .send((target.map(str::to_string), Message::Binary(preview.clone())));
+    }
+
+    let mut outputs = serde_json::Map::new();
+    for (class_type, folder) in [("SaveImage", "output"), ("PreviewImage", "temp")] {
+        for node in nodes_of(class_type) {
+            tokio::time::sleep(state.script.output_delay).await;
+            let filename = {
+                let mut inner = state.lock();
+                inner.next_image += 1;
+                format!("ComfyUI_{:05}_.png", inner.next_image)
+            };
+            let output = json!({
+                "images": [{ "filename": filename, "subfolder": "", "type": folder }]
+            });
+            state.send_json(
+                target,
+                json!({ "type": "executing", "data": { "node": node, "prompt_id": id } }),
+            );
+            state.send_json(
+                target,
+                json!({
+                    "type": "executed",
+                    "data": { "node": node, "display_node": node, "output": output, "prompt_id": id }
+                }),
+            );
+            outputs.insert(node, output);
+        }
+    }
+
+    state.send_json(
+        target,
+        json!({ "type": "executing", "data": { "node": null, "prompt_id": id } }),
+    );
+    state.send_json(
+        target,
+        json!({ "type": "execution_success", "data": { "prompt_id": id } }),
+    );
+
+    json!({
+        "prompt": queue_item(prompt),
+        "outputs": outputs,
+        "status": { "status_str": "success", "completed": true, "messages": [] }
+    })
+}
@@ -0,0 +1,195 @@
+//! Shared harness for the integration tests: the backend router served on a random port
+//! in front of a [`MockComfyUI`]
+
+#![allow(dead_code)]
+
+pub mod mock_comfyui;
+
+use backend::api::{create_router, AppState};
+use backend::config::{Config, InstanceConfig};
+use backend::metadata::MetadataFormat;
+use futures::{SinkExt, StreamExt};
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+pub use mock_comfyui::{MockComfyUI, Script};
+
+/// Longest wait for an expected event
+pub const EVENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The backend, its mock ComfyUI and a scratch data directory
+pub struct TestApp {
+    pub url: String,
+    /// The first instance
+    pub comfyui: MockComfyUI,
+    pub state: AppState,
+    pub http: reqwest::Client,
+    data_dir: PathBuf,
+}
+
+impl TestApp {
+    pub async fn start() -> Self {
+        Self::with_comfyui(MockComfyUI::start().await).await
+    }
+
+    pub async fn with_comfyui(comfyui: MockComfyUI) -> Self {
+        Self::with_instances(vec![("mock", comfyui)]).await
+    }
+
+    /// The backend spreading jobs over several named mock instances
+    pub async fn with_instances(instances: Vec<(&str, MockComfyUI)>) -> Self {
+        let data_dir = std::env::temp_dir().join(format!("backend-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&data_dir).unwrap();
+
+        let config = Config {
+            host: "127.0.0.1".to_string(),
+            port: 0,
+            comfyui_instances: instances
+                .iter()
+                .map(|(name, comfyui)| InstanceConfig {
+                    name: name.to_string(),
+                    url: comfyui.url.clone(),
+                })
+                .collect(),
+            public_base_url: "http://localhost".to_string(),
+            cors_origins: Vec::new(),
+            templates_dir: concat!(env!("CARGO_MANIFEST_DIR"), "/templates").to_string(),
+            default_template: "newbie".to_string(),
+            history_path: data_dir.join("history.jsonl").display().to_string(),
+            png_metadata: MetadataFormat::default(),
+            sweeps_dir: data_dir.join("sweeps").display().to_string(),
+            label_font: None,
+            max_in_flight: 2,
+            // Forward every preview, the mock sends them faster than any sane cap
+            preview_max_fps: 1000.0,
+            preview_max_size: 0,
+            preview_quality: 75,
+            object_info_ttl: 300,
+        };
+
+        let state = AppState::new(Arc::new(config));
+        state.spawn_workers();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = create_router(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        // Events only flow once the backend listens to the mocks' WebSockets
+        for (_, comfyui) in &instances {
+            comfyui.wait_for_listener().await;
+        }
+
+        Self {
+            url,
+            comfyui: instances[0].1.clone(),
+            state,
+            http: reqwest::Client::new(),
+            data_dir,
+        }
+    }
+
+    pub async fn get(&self, path: &str) -> reqwest::Response {
+        self.http
+            .get(format!("{}{}", self.url, path))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_json(&self, path: &str, body: &Value) -> reqwest::Response {
+        self.http
+            .post(format!("{}{}", self.url, path))
+            .json(body)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    /// Open `/ws` with a query string and consume the `connected` and `snapshot` greeting
+    pub async fn connect_ws(&self, query: &str) -> WsClient {
+        let url = format!("{}/ws?{}", self.url.replacen("http", "ws", 1), query);
+        let (stream, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let mut client = WsClient {
+            stream,
+            client_id: String::new(),
+        };
+        let connected = client.next_of("connected").await;
+        client.client_id = connected["client_id"].as_str().unwrap().to_string();
+        client.next_of("snapshot").await;
+        client
+    }
+}
+
+impl Drop for TestApp {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.data_dir);
+    }
+}
+
+/// A frontend connected to the backend's `/ws`
+pub struct WsClient {
+    stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    pub client_id: String,
+}
+
+impl WsClient {
+    pub async fn send(&mut self, message: Value) {
+        self.stream
+            .send(Message::Text(message.to_string()))
+            .await
+            .unwrap();
+    }
+
+    /// Next JSON message
+    pub async fn next(&mut self) -> Value {
+        loop {
+            let message = tokio::time::timeout(EVENT_TIMEOUT, self.stream.next())
+                .await
+                .expect("timed out waiting for a WebSocket message")
+                .expect("WebSocket closed")
+                .unwrap();
+            if let Message::Text(text) = message {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    /// Next message of type `kind`, skipping the others
+    pub async fn next_of(&mut self, kind: &str) -> Value {
+        loop {
+            let message = self.next().await;
+            if message["type"] == kind {
+                return message;
+            }
+        }
+    }
+
+    /// Messages for `prompt_id` up to and including the one of type `last`
+    pub async fn collect_until(&mut self, prompt_id: &str, last: &str) -> Vec<Value> {
+        let mut messages = Vec::new();
+        loop {
+            let message = self.next().await;
+            if message["prompt_id"] != prompt_id {
+                continue;
+            }
+            let done = message["type"] == last;
+            messages.push(message);
+            if done {
+                return messages;
+            }
+        }
+    }
+}
+
+/// Types of `messages`, in order
+pub fn types(messages: &[Value]) -> Vec<&str> {
+    messages
+        .iter()
+        .map(|m| m["type"].as_str().unwrap_or_default())
+        .collect()
+}
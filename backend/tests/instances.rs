@@ -0,0 +1,127 @@
+//! Jobs spread over two mock ComfyUI instances
+
+mod common;
+
+use common::{MockComfyUI, Script, TestApp};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+fn generate_body(client_id: &str) -> Value {
+    json!({
+        "prompt": "1girl, silver hair, night sky",
+        "negative_prompt": "lowres",
+        "steps": 3,
+        "seed": 1234,
+        "client_id": client_id
+    })
+}
+
+async fn two_instances(a: MockComfyUI, b: MockComfyUI) -> TestApp {
+    a.set_image_size(16);
+    b.set_image_size(24);
+    TestApp::with_instances(vec![("a", a), ("b", b)]).await
+}
+
+async fn generate(app: &TestApp, client_id: &str) -> String {
+    let queued: Value = app
+        .post_json("/api/generate", &generate_body(client_id))
+        .await
+        .json()
+        .await
+        .unwrap();
+    queued["prompt_id"].as_str().unwrap().to_string()
+}
+
+/// Edge of the image served by `/api/images`, telling the instances apart
+async fn image_size(app: &TestApp, path: &str) -> u32 {
+    let response = app.get(path).await;
+    assert_eq!(response.status(), 200, "GET {}", path);
+    let data = response.bytes().await.unwrap();
+    image::load_from_memory(&data).unwrap().width()
+}
+
+#[tokio::test]
+async fn jobs_go_to_the_least_loaded_instance() {
+    let script = Script {
+        step_delay: Duration::from_millis(200),
+        ..Script::default()
+    };
+    let app = two_instances(
+        MockComfyUI::with_script(script.clone()).await,
+        MockComfyUI::with_script(script).await,
+    )
+    .await;
+    let mut ws = app.connect_ws("").await;
+
+    let first = generate(&app, &ws.client_id).await;
+    let second = generate(&app, &ws.client_id).await;
+
+    // Both run at once, so their events interleave
+    let mut instances = [None, None];
+    let mut filenames = [None, None];
+    while filenames.iter().any(Option::is_none) {
+        let event = ws.next().await;
+        let Some(index) = [&first, &second]
+            .iter()
+            .position(|id| event["prompt_id"] == id.as_str())
+        else {
+            continue;
+        };
+        match event["type"].as_str() {
+            Some("submitted") => instances[index] = event["instance"].as_str().map(str::to_string),
+            Some("completed") => filenames[index] = Some(event["images"][0]["filename"].clone()),
+            _ => {}
+        }
+    }
+    let instances = instances.map(Option::unwrap);
+    let filenames = filenames.map(Option::unwrap);
+    assert_eq!(instances, ["a", "b"]);
+
+    // Both instances number their outputs from 1, the record says which one made an image
+    assert_eq!(filenames[0], filenames[1]);
+    let filename = filenames[0].as_str().unwrap();
+    for (instance, size) in [("a", 16), ("b", 24)] {
+        let path = format!("/api/images/{}?instance={}", filename, instance);
+        assert_eq!(image_size(&app, &path).await, size);
+    }
+    assert_eq!(app.comfyui.prompts().len(), 1);
+}
+
+#[tokio::test]
+async fn jobs_move_off_an_instance_that_goes_down() {
+    let a = MockComfyUI::with_script(Script {
+        steps: 100,
+        step_delay: Duration::from_millis(100),
+        ..Script::default()
+    })
+    .await;
+    let b = MockComfyUI::start().await;
+    let app = two_instances(a.clone(), b.clone()).await;
+    let mut ws = app.connect_ws("").await;
+
+    let prompt_id = generate(&app, &ws.client_id).await;
+    let started = ws.collect_until(&prompt_id, "progress").await;
+    let submitted = started.iter().find(|e| e["type"] == "submitted").unwrap();
+    assert_eq!(submitted["instance"], "a");
+
+    a.go_down();
+    let events = ws.collect_until(&prompt_id, "completed").await;
+    let resubmitted = events.iter().find(|e| e["type"] == "submitted").unwrap();
+    assert_eq!(resubmitted["instance"], "b");
+    assert_eq!(events.last().unwrap()["instance"], "b");
+    assert_eq!(b.prompts()[0].prompt_id, prompt_id);
+
+    // The image is only fetched from the instance that made it
+    let filename = events.last().unwrap()["images"][0]["filename"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert_eq!(
+        image_size(&app, &format!("/api/images/{}", filename)).await,
+        24
+    );
+    let response = app
+        .get(&format!("/api/images/{}?instance=a", filename))
+        .await;
+    assert!(response.status().is_server_error());
+}